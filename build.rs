@@ -1,12 +1,53 @@
+#[cfg(feature = "vendored")]
+extern crate cc;
+
+#[cfg(feature = "vendored")]
+fn vendored() {
+    use std::path::Path;
+
+    let sources = ["nvlist.c", "nvpair.c", "dnvlist.c", "cnvlist.c", "msgio.c"];
+    let src = Path::new("vendor/libnv/src");
+    let compat = Path::new("vendor/libnv/compat");
+    if let Some(missing) = sources.iter().find(|source| !src.join(source).exists()) {
+        panic!("The `vendored` feature needs the FreeBSD libnv sources in {}, but {} is \
+                missing. Run `vendor/libnv/fetch.sh` to fetch them.",
+               src.display(),
+               missing);
+    }
+    let mut build = cc::Build::new();
+    for source in &sources {
+        build.file(src.join(source));
+    }
+    build.file(compat.join("compat.c"))
+        .include(compat)
+        .include(src)
+        .flag("-include")
+        .flag(compat.join("nv_compat.h").display().to_string())
+        .define("_GNU_SOURCE", None)
+        .warnings(false)
+        .compile("nv");
+    println!("cargo:rerun-if-changed=vendor/libnv");
+}
+
+#[cfg(not(feature = "vendored"))]
+fn vendored() {
+}
+
 #[cfg(target_os = "freebsd")]
 fn freebsd_nop() {
 }
 
 #[cfg(not(target_os = "freebsd"))]
 fn freebsd_nop() {
-    panic!("This is a FreeBSD only crate. It will not compile on other OSes.");
+    if std::env::var_os("CARGO_FEATURE_LIBNV").is_some() &&
+       std::env::var_os("CARGO_FEATURE_VENDORED").is_none() {
+        panic!("The `libnv` feature is FreeBSD only. Build with `--no-default-features` \
+                to use the pure Rust decoder on other OSes, or with `--features vendored` \
+                to build libnv from source.");
+    }
 }
 
 fn main() {
     freebsd_nop();
+    vendored();
 }
@@ -0,0 +1,26 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate nv;
+
+use nv::{NvTree, UnpackOptions};
+
+// Keep the limits small so that the fuzzer spends its time on the
+// format rather than on allocating
+const OPTIONS: UnpackOptions = UnpackOptions {
+    max_bytes: 1 << 16,
+    max_depth: 8,
+    max_pairs: 256,
+    max_array_len: 256,
+    max_string_len: 1024,
+    max_descriptors: 4,
+};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = NvTree::unpack(data, &[0, 1, 2, 3], &OPTIONS);
+});
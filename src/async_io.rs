@@ -0,0 +1,375 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+
+//! Integration with tokio, behind the `tokio` feature
+//!
+//! The futures follow the same protocol as the blocking `NvTree::send`
+//! and `NvTree::recv`, so either side may be a libnv program.
+
+use bytes::{BufMut, BytesMut};
+use common::NvResult;
+use msgio::{self, PKG_MAX_SIZE};
+#[cfg(feature = "libnv")]
+use nvlist::NvList;
+use packed::{ListHeader, UnpackOptions, NVLIST_HEADER_SIZE};
+use value::NvTree;
+use std::cmp;
+use std::future::Future;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::Interest;
+use tokio::net::UnixStream;
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Run a single non-blocking socket operation once the stream is ready
+/// for it, waiting again whenever it would block
+fn poll_io<T, F>(stream: &UnixStream,
+                 cx: &mut Context,
+                 interest: Interest,
+                 mut op: F)
+                 -> Poll<io::Result<T>>
+    where F: FnMut() -> io::Result<T>
+{
+    loop {
+        let ready = if interest.is_readable() {
+            stream.poll_read_ready(cx)
+        } else {
+            stream.poll_write_ready(cx)
+        };
+        match ready {
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+            Poll::Ready(Ok(())) => {}
+        }
+        match stream.try_io(interest, &mut op) {
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock ||
+                            err.kind() == io::ErrorKind::Interrupted => {}
+            res => return Poll::Ready(res),
+        }
+    }
+}
+
+/// Asynchronous counterparts of `NvTree::send` and `NvTree::recv`
+///
+/// Descriptors are passed along with the list, exactly as libnv's
+/// `nvlist_send` and `nvlist_recv` do.
+pub trait AsyncNvExt {
+    /// Send a list, along with any descriptors it holds
+    fn send_nvlist<'a>(&'a self, tree: &NvTree) -> SendNvList<'a>;
+
+    /// Receive a list, enforcing the limits in `options`
+    ///
+    /// The caller takes ownership of any descriptors in the returned tree.
+    fn recv_nvlist<'a>(&'a self, options: &UnpackOptions) -> RecvNvList<'a>;
+
+    /// Send an `NvList`, along with any descriptors it holds
+    ///
+    /// The list stays borrowed until the future completes, so that its
+    /// descriptors remain open.
+    #[cfg(feature = "libnv")]
+    fn send_list<'a>(&'a self, list: &'a NvList) -> SendNvList<'a>;
+
+    /// Receive a list into libnv, enforcing the limits in `options`
+    #[cfg(feature = "libnv")]
+    fn recv_list<'a>(&'a self, options: &UnpackOptions) -> RecvList<'a>;
+}
+
+impl AsyncNvExt for UnixStream {
+    fn send_nvlist<'a>(&'a self, tree: &NvTree) -> SendNvList<'a> {
+        SendNvList::new(self, tree.pack_message())
+    }
+
+    fn recv_nvlist<'a>(&'a self, options: &UnpackOptions) -> RecvNvList<'a> {
+        RecvNvList {
+            stream: self,
+            options: *options,
+            buf: vec![0; NVLIST_HEADER_SIZE],
+            filled: 0,
+            descriptors: None,
+            fds: Vec::new(),
+        }
+    }
+
+    #[cfg(feature = "libnv")]
+    fn send_list<'a>(&'a self, list: &'a NvList) -> SendNvList<'a> {
+        SendNvList::new(self, list.to_tree().and_then(|tree| tree.pack_message()))
+    }
+
+    #[cfg(feature = "libnv")]
+    fn recv_list<'a>(&'a self, options: &UnpackOptions) -> RecvList<'a> {
+        RecvList { inner: self.recv_nvlist(options) }
+    }
+}
+
+/// Future returned by `AsyncNvExt::send_nvlist` and `AsyncNvExt::send_list`
+pub struct SendNvList<'a> {
+    stream: &'a UnixStream,
+    buf: Vec<u8>,
+    fds: Vec<RawFd>,
+    sent: usize,
+    sent_fds: usize,
+    /// The list could not be packed
+    error: Option<io::Error>,
+}
+
+impl<'a> SendNvList<'a> {
+    fn new(stream: &'a UnixStream, packed: io::Result<(Vec<u8>, Vec<RawFd>)>) -> SendNvList<'a> {
+        let (buf, fds, error) = match packed {
+            Ok((buf, fds)) => (buf, fds, None),
+            Err(err) => (Vec::new(), Vec::new(), Some(err)),
+        };
+        SendNvList {
+            stream,
+            buf,
+            fds,
+            sent: 0,
+            sent_fds: 0,
+            error,
+        }
+    }
+}
+
+impl<'a> Future for SendNvList<'a> {
+    type Output = io::Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if let Some(err) = this.error.take() {
+            return Poll::Ready(Err(err));
+        }
+        let sock = this.stream.as_raw_fd();
+        while this.sent < this.buf.len() {
+            let rest = &this.buf[this.sent..];
+            match poll_io(this.stream, cx, Interest::WRITABLE, || msgio::send_some(sock, rest)) {
+                Poll::Ready(Ok(len)) => this.sent += len,
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        while this.sent_fds < this.fds.len() {
+            let step = cmp::min(PKG_MAX_SIZE, this.fds.len() - this.sent_fds);
+            let package = &this.fds[this.sent_fds..this.sent_fds + step];
+            match poll_io(this.stream,
+                          cx,
+                          Interest::WRITABLE,
+                          || msgio::fd_package_send(sock, package)) {
+                Poll::Ready(Ok(())) => this.sent_fds += step,
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Future returned by `AsyncNvExt::recv_nvlist`
+///
+/// Descriptors received before the future is dropped or fails are
+/// closed.
+pub struct RecvNvList<'a> {
+    stream: &'a UnixStream,
+    options: UnpackOptions,
+    buf: Vec<u8>,
+    filled: usize,
+    /// The number of descriptors announced, once the header is in
+    descriptors: Option<usize>,
+    fds: Vec<RawFd>,
+}
+
+impl<'a> RecvNvList<'a> {
+    /// Make progress on receiving the list, returning `true` once it and
+    /// all its descriptors are in
+    fn poll_step(&mut self, cx: &mut Context) -> Poll<NvResult<bool>> {
+        let sock = self.stream.as_raw_fd();
+        if self.filled < self.buf.len() {
+            // Read no further than the end of the list, as the descriptors
+            // that follow are each carried by a byte of their own
+            let rest = &mut self.buf[self.filled..];
+            return match poll_io(self.stream, cx, Interest::READABLE, || msgio::recv_some(sock, rest)) {
+                Poll::Ready(Ok(len)) => {
+                    self.filled += len;
+                    Poll::Ready(Ok(false))
+                }
+                Poll::Ready(Err(err)) => Poll::Ready(Err(msgio::to_nv(err))),
+                Poll::Pending => Poll::Pending,
+            };
+        }
+        let count = match self.descriptors {
+            Some(count) => count,
+            None => {
+                let header = ListHeader::check(&self.buf, &self.options)?;
+                self.buf.resize(NVLIST_HEADER_SIZE + header.size as usize, 0);
+                self.descriptors = Some(header.descriptors as usize);
+                return Poll::Ready(Ok(false));
+            }
+        };
+        if self.fds.len() < count {
+            let step = cmp::min(PKG_MAX_SIZE, count - self.fds.len());
+            let fds = &mut self.fds;
+            return match poll_io(self.stream,
+                                 cx,
+                                 Interest::READABLE,
+                                 || msgio::fd_package_recv(sock, fds, step)) {
+                Poll::Ready(Ok(())) => Poll::Ready(Ok(false)),
+                Poll::Ready(Err(err)) => Poll::Ready(Err(msgio::to_nv(err))),
+                Poll::Pending => Poll::Pending,
+            };
+        }
+        Poll::Ready(Ok(true))
+    }
+
+    /// Receive the whole list, handing its descriptors over to the caller
+    fn poll_message(&mut self, cx: &mut Context) -> Poll<NvResult<Vec<RawFd>>> {
+        loop {
+            match self.poll_step(cx) {
+                Poll::Ready(Ok(true)) => return Poll::Ready(Ok(::std::mem::take(&mut self.fds))),
+                Poll::Ready(Ok(false)) => {}
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl<'a> Future for RecvNvList<'a> {
+    type Output = io::Result<NvTree>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<NvTree>> {
+        let this = self.get_mut();
+        this.poll_message(cx)
+            .map(|res| res.and_then(|fds| msgio::decode(&this.buf, &fds, &this.options)))
+            .map_err(io::Error::from)
+    }
+}
+
+impl<'a> Drop for RecvNvList<'a> {
+    fn drop(&mut self) {
+        msgio::close_all(&self.fds);
+    }
+}
+
+/// Future returned by `AsyncNvExt::recv_list`
+///
+/// The descriptors are duplicated into the returned `NvList`, and the
+/// received ones closed.
+#[cfg(feature = "libnv")]
+pub struct RecvList<'a> {
+    inner: RecvNvList<'a>,
+}
+
+#[cfg(feature = "libnv")]
+impl<'a> Future for RecvList<'a> {
+    type Output = io::Result<NvList>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<NvList>> {
+        let inner = &mut self.get_mut().inner;
+        inner.poll_message(cx)
+            .map(|res| {
+                res.and_then(|fds| {
+                    let res = NvList::unpack_with(&inner.buf, &fds, &inner.options);
+                    msgio::close_all(&fds);
+                    res
+                })
+            })
+            .map_err(io::Error::from)
+    }
+}
+
+/// A `tokio_util` codec for streams of packed `nvlist`s
+///
+/// Frames are delimited by the size in each list's header, so the codec
+/// interoperates with anything writing packed lists back to back. Byte
+/// streams do not carry descriptors, so lists holding them can not be
+/// encoded or decoded; use `AsyncNvExt` to pass descriptors.
+///
+/// Frames decode to `NvTree`s. With the `libnv` feature, `NvList`s can be
+/// encoded too, and `decode_nvlist` loads frames into libnv instead.
+///
+/// ```
+/// extern crate bytes;
+/// extern crate nv;
+/// extern crate tokio_util;
+///
+/// use bytes::BytesMut;
+/// use nv::{NvFlag, NvListCodec, NvTree, NvValue, UnpackOptions};
+/// use tokio_util::codec::{Decoder, Encoder};
+///
+/// let mut tree = NvTree::new(NvFlag::None);
+/// tree.push("the answer", NvValue::Number(42));
+///
+/// let mut codec = NvListCodec::new(UnpackOptions::default());
+/// let mut buf = BytesMut::new();
+/// codec.encode(&tree, &mut buf).unwrap();
+///
+/// assert_eq!(codec.decode(&mut buf).unwrap(), Some(tree));
+/// assert!(buf.is_empty());
+/// ```
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NvListCodec {
+    options: UnpackOptions,
+}
+
+impl NvListCodec {
+    /// Create a codec enforcing the limits in `options` on every list
+    pub fn new(options: UnpackOptions) -> NvListCodec {
+        NvListCodec { options }
+    }
+
+    /// Decode the next frame into libnv, as `Decoder::decode` does into an
+    /// `NvTree`
+    #[cfg(feature = "libnv")]
+    pub fn decode_nvlist(&mut self, src: &mut BytesMut) -> io::Result<Option<NvList>> {
+        match self.decode(src)? {
+            Some(tree) => Ok(Some(NvList::from_tree(&tree)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Decoder for NvListCodec {
+    type Item = NvTree;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<NvTree>> {
+        if src.len() < NVLIST_HEADER_SIZE {
+            return Ok(None);
+        }
+        let header = ListHeader::check(&src[..NVLIST_HEADER_SIZE], &self.options)?;
+        let size = NVLIST_HEADER_SIZE + header.size as usize;
+        if src.len() < size {
+            src.reserve(size - src.len());
+            return Ok(None);
+        }
+        let frame = src.split_to(size);
+        Ok(Some(NvTree::unpack(&frame, &[], &self.options)?))
+    }
+}
+
+impl<'a> Encoder<&'a NvTree> for NvListCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, tree: &'a NvTree, dst: &mut BytesMut) -> io::Result<()> {
+        tree.pack_into(dst.writer())
+    }
+}
+
+impl Encoder<NvTree> for NvListCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, tree: NvTree, dst: &mut BytesMut) -> io::Result<()> {
+        self.encode(&tree, dst)
+    }
+}
+
+#[cfg(feature = "libnv")]
+impl<'a> Encoder<&'a NvList> for NvListCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, list: &'a NvList, dst: &mut BytesMut) -> io::Result<()> {
+        list.pack_into(dst.writer())
+    }
+}
@@ -0,0 +1,165 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+
+//! A canonical packed form of lists, for hashing and signing
+//!
+//! The canonical form is the libnv packed format with the pairs of every
+//! list sorted by name and every integer little-endian, so lists holding
+//! the same pairs pack to the same bytes whatever order the pairs were
+//! added in and whatever host packed them. Pairs of the same name keep
+//! their relative order, since lookups return the first of them.
+//! Descriptors can not be packed, so every list header counts none, as
+//! `nvlist_pack` would write it.
+
+use common::Endian;
+#[cfg(feature = "digest")]
+use digest::{Digest, Output};
+use value::{NvTree, NvValue};
+use writer::NvWriter;
+use std::io::{self, Write};
+use std::{slice, vec};
+
+/// The pairs of a list, sorted by name
+fn sorted(tree: &NvTree) -> vec::IntoIter<(&str, &NvValue)> {
+    let mut pairs: Vec<(&str, &NvValue)> = tree.iter().collect();
+    pairs.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+    pairs.into_iter()
+}
+
+/// A list being added by `add_sorted`
+enum Frame<'a> {
+    /// The pairs of the list left to add
+    List(vec::IntoIter<(&'a str, &'a NvValue)>),
+    /// The elements of an `nvlist` array left to add
+    Array(slice::Iter<'a, NvTree>),
+}
+
+/// Add the pairs of `tree` to `writer`, sorted by name at every depth
+///
+/// Nested lists are tracked on a stack rather than by recursing, so
+/// lists nested however deep can be hashed.
+fn add_sorted<W: Write>(writer: &mut NvWriter<W>, tree: &NvTree) -> io::Result<()> {
+    let mut stack = vec![Frame::List(sorted(tree))];
+    while let Some(frame) = stack.last_mut() {
+        match *frame {
+            Frame::List(ref mut pairs) => {
+                match pairs.next() {
+                    Some((name, NvValue::NvList(nested))) => {
+                        writer.begin_nvlist(name, nested.flags())?;
+                        stack.push(Frame::List(sorted(nested)));
+                    }
+                    Some((name, NvValue::NvListArray(nested))) => {
+                        writer.begin_nvlist_array(name, nested.len())?;
+                        stack.push(Frame::Array(nested.iter()));
+                    }
+                    Some((name, value)) => writer.add_value(name, value)?,
+                    None => {
+                        stack.pop();
+                        match stack.last() {
+                            Some(&Frame::List(_)) => writer.end_nvlist()?,
+                            Some(&Frame::Array(_)) => writer.end_element()?,
+                            None => {}
+                        }
+                    }
+                }
+            }
+            Frame::Array(ref mut elements) => {
+                match elements.next() {
+                    Some(element) => {
+                        writer.begin_element(element.flags())?;
+                        stack.push(Frame::List(sorted(element)));
+                    }
+                    None => {
+                        stack.pop();
+                        writer.end_nvlist_array()?;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Feeds whatever is written to it to a hash function
+#[cfg(feature = "digest")]
+struct DigestWriter<D>(D);
+
+#[cfg(feature = "digest")]
+impl<D: Digest> Write for DigestWriter<D> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl NvTree {
+    /// Stream the canonical form of the tree to `writer`
+    ///
+    /// As with `pack_into`, trees holding descriptors can not be packed.
+    pub fn canonical_into<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut measure = NvWriter::measure(self.flags());
+        add_sorted(&mut measure, self)?;
+        let mut writer = NvWriter::with_endianness(writer,
+                                                   self.flags(),
+                                                   measure.written(),
+                                                   Endian::Little)?;
+        add_sorted(&mut writer, self)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// The canonical form of the tree
+    ///
+    /// ```
+    /// use nv::{NvFlag, NvTree, NvValue};
+    ///
+    /// let mut a = NvTree::new(NvFlag::None);
+    /// a.push("name", NvValue::String("tank".to_owned()));
+    /// a.push("version", NvValue::Number(5000));
+    ///
+    /// let mut b = NvTree::new(NvFlag::None);
+    /// b.push("version", NvValue::Number(5000));
+    /// b.push("name", NvValue::String("tank".to_owned()));
+    ///
+    /// assert_eq!(a.canonical_bytes().unwrap(), b.canonical_bytes().unwrap());
+    /// ```
+    pub fn canonical_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.canonical_into(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Hash the canonical form of the tree with `D`, without holding it
+    /// in memory
+    ///
+    /// This needs the `digest` feature.
+    ///
+    /// ```
+    /// extern crate nv;
+    /// extern crate sha2;
+    ///
+    /// use nv::{NvFlag, NvTree, NvValue};
+    /// use sha2::{Digest, Sha256};
+    ///
+    /// # fn main() {
+    /// let mut tree = NvTree::new(NvFlag::None);
+    /// tree.push("the answer", NvValue::Number(42));
+    ///
+    /// let digest = tree.digest::<Sha256>().unwrap();
+    ///
+    /// assert_eq!(digest, Sha256::digest(tree.canonical_bytes().unwrap()));
+    /// # }
+    /// ```
+    #[cfg(feature = "digest")]
+    pub fn digest<D: Digest>(&self) -> io::Result<Output<D>> {
+        let mut writer = DigestWriter(D::new());
+        self.canonical_into(&mut writer)?;
+        Ok(writer.0.finalize())
+    }
+}
@@ -3,6 +3,8 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 
+use std::io;
+
 /// Error type for the `nv` crate
 #[derive(Copy, Clone, Debug)]
 pub enum NvErr {
@@ -10,18 +12,138 @@ pub enum NvErr {
     ConstructionErr,
     /// Error not set
     ErrorNotSet(i32),
+    /// A packed buffer ended before the value at the given offset
+    /// was complete
+    Truncated(usize),
+    /// A packed buffer is malformed at the given offset
+    InvalidFormat(usize),
+    /// A packed buffer exceeds one of the limits in `UnpackOptions`
+    LimitExceeded(NvLimit),
+    /// An operating system error, carrying the `errno` value
+    Errno(i32),
+    /// No value of the requested type has the given name
+    NotFound,
+    /// The stored number does not fit the requested type
+    OutOfRange(u64),
+    /// The signature of an envelope did not verify, or its key is unknown
+    BadSignature,
 }
 
 /// Result type for the `nv` crate
 pub type NvResult<T> = Result<T, NvErr>;
 
+impl From<NvErr> for io::Error {
+    fn from(err: NvErr) -> io::Error {
+        match err {
+            NvErr::Errno(errno) => io::Error::from_raw_os_error(errno),
+            NvErr::NotFound => io::Error::new(io::ErrorKind::NotFound, "no such value"),
+            NvErr::BadSignature => {
+                io::Error::new(io::ErrorKind::PermissionDenied, "bad signature")
+            }
+            err => io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", err)),
+        }
+    }
+}
+
 /// Max size the name for a name/value pair may take on
 pub const NV_NAME_MAX: u32 = 2048;
 
+/// The limit of `UnpackOptions` that was exceeded
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NvLimit {
+    /// `UnpackOptions::max_bytes`
+    Bytes,
+    /// `UnpackOptions::max_depth`
+    Depth,
+    /// `UnpackOptions::max_pairs`
+    Pairs,
+    /// `UnpackOptions::max_array_len`
+    ArrayLength,
+    /// `UnpackOptions::max_string_len`
+    StringLength,
+    /// `UnpackOptions::max_descriptors`
+    Descriptors,
+}
+
+/// Enumeration of options available to be passed to
+/// the creation of an `nvlist`
+#[repr(i32)]
+#[derive(Copy, Clone, Debug)]
+pub enum NvFlag {
+    /// No user specified options
+    None = 0,
+    /// Use case-insensitive name lookups
+    IgnoreCase = 1,
+    /// There may be duplicate names in the `nvlist`
+    NoUnique = 2,
+    /// Both of the previous flags
+    All = 3,
+}
+
+impl NvFlag {
+    /// Convert an `i32` into an NvFlag
+    pub fn from_i32(flags: i32) -> NvResult<NvFlag> {
+        match flags {
+            0 => Ok(NvFlag::None),
+            1 => Ok(NvFlag::IgnoreCase),
+            2 => Ok(NvFlag::NoUnique),
+            3 => Ok(NvFlag::All),
+            _ => Err(NvErr::ConstructionErr),
+        }
+    }
+}
+
+impl PartialEq for NvFlag {
+    fn eq(&self, other: &NvFlag) -> bool {
+        *self as i32 == *other as i32
+    }
+}
+
+/// The byte order of the integers in a packed `nvlist`
+///
+/// libnv packs in the byte order of the host and records it in the list
+/// header, so lists packed on either kind of host can be decoded.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Endian {
+    /// Least significant byte first, as on amd64 and arm64
+    Little,
+    /// Most significant byte first, as on powerpc64
+    Big,
+}
+
+impl Endian {
+    /// The byte order of the host
+    pub fn native() -> Endian {
+        if cfg!(target_endian = "big") {
+            Endian::Big
+        } else {
+            Endian::Little
+        }
+    }
+
+    pub(crate) fn read_u16(self, data: &[u8]) -> u16 {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(&data[..2]);
+        match self {
+            Endian::Little => u16::from_le_bytes(bytes),
+            Endian::Big => u16::from_be_bytes(bytes),
+        }
+    }
+
+    pub(crate) fn read_u64(self, data: &[u8]) -> u64 {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&data[..8]);
+        match self {
+            Endian::Little => u64::from_le_bytes(bytes),
+            Endian::Big => u64::from_be_bytes(bytes),
+        }
+    }
+}
+
 /// Enumeration of available data types that the
 /// [`nvlist`](https://www.freebsd.org/cgi/man.cgi?query=nv) API supports
 #[repr(i32)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum NvType {
     /// Empty type
     None = 0,
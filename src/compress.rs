@@ -0,0 +1,144 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+
+//! Compressed packed lists
+//!
+//! A compressed list is framed by a 13 byte header: the magic `nvz` and
+//! the format version 1, the codec, and the size of the packed list as a
+//! little-endian `u64`. The magic can not start a libnv packed list, so
+//! `decompress` and `NvTree::unpack_any` take both, passing plain packed
+//! lists through unchanged.
+//!
+//! The codecs are behind the `zstd` and `lz4` features. Without them
+//! compressed frames are still recognised, but give `ENOTSUP`.
+//!
+//! ```
+//! use nv::{NvFlag, NvTree, NvValue, UnpackOptions};
+//!
+//! let mut tree = NvTree::new(NvFlag::None);
+//! tree.push("the answer", NvValue::Number(42));
+//!
+//! let mut buf = Vec::new();
+//! tree.pack_into(&mut buf).unwrap();
+//!
+//! let unpacked = NvTree::unpack_any(&buf, &[], &UnpackOptions::default()).unwrap();
+//! assert_eq!(unpacked, tree);
+//! ```
+
+use common::{NvErr, NvLimit, NvResult};
+use packed::UnpackOptions;
+use value::NvTree;
+use std::borrow::Cow;
+use std::io;
+use std::os::unix::io::RawFd;
+
+const MAGIC: [u8; 4] = [b'n', b'v', b'z', 1];
+const HEADER_SIZE: usize = 13;
+
+/// A compression algorithm for packed lists
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Codec {
+    /// Zstandard, at its default level
+    #[cfg(feature = "zstd")]
+    Zstd = 1,
+    /// LZ4 block compression
+    #[cfg(feature = "lz4")]
+    Lz4 = 2,
+}
+
+impl Codec {
+    // Without any codec there is nothing to compress with
+    #[cfg_attr(not(any(feature = "zstd", feature = "lz4")), allow(unused_variables))]
+    fn compress(self, packed: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => ::zstd::bulk::compress(packed, 0),
+            #[cfg(feature = "lz4")]
+            Codec::Lz4 => Ok(::lz4_flex::block::compress(packed)),
+        }
+    }
+}
+
+#[cfg(feature = "zstd")]
+fn unzstd(data: &[u8], size: usize) -> NvResult<Vec<u8>> {
+    ::zstd::bulk::decompress(data, size).map_err(|_| NvErr::InvalidFormat(HEADER_SIZE))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn unzstd(_: &[u8], _: usize) -> NvResult<Vec<u8>> {
+    Err(NvErr::Errno(::libc::ENOTSUP))
+}
+
+#[cfg(feature = "lz4")]
+fn unlz4(data: &[u8], size: usize) -> NvResult<Vec<u8>> {
+    ::lz4_flex::block::decompress(data, size).map_err(|_| NvErr::InvalidFormat(HEADER_SIZE))
+}
+
+#[cfg(not(feature = "lz4"))]
+fn unlz4(_: &[u8], _: usize) -> NvResult<Vec<u8>> {
+    Err(NvErr::Errno(::libc::ENOTSUP))
+}
+
+/// Compress a packed list with `codec`, framing it with a header
+pub fn compress(packed: &[u8], codec: Codec) -> io::Result<Vec<u8>> {
+    let data = codec.compress(packed)?;
+    let mut buf = Vec::with_capacity(HEADER_SIZE + data.len());
+    buf.extend_from_slice(&MAGIC);
+    buf.push(codec as u8);
+    buf.extend_from_slice(&(packed.len() as u64).to_le_bytes());
+    buf.extend_from_slice(&data);
+    Ok(buf)
+}
+
+/// Determines if `buf` starts with the header of a compressed list
+pub fn is_compressed(buf: &[u8]) -> bool {
+    buf.starts_with(&MAGIC)
+}
+
+/// Get the packed list in `buf`, decompressing it if it is compressed
+///
+/// The size of the packed list is checked against `options.max_bytes`
+/// before anything is decompressed.
+pub fn decompress<'a>(buf: &'a [u8], options: &UnpackOptions) -> NvResult<Cow<'a, [u8]>> {
+    if !is_compressed(buf) {
+        return Ok(Cow::Borrowed(buf));
+    }
+    if buf.len() < HEADER_SIZE {
+        return Err(NvErr::Truncated(0));
+    }
+    let mut size = [0; 8];
+    size.copy_from_slice(&buf[5..HEADER_SIZE]);
+    let size = u64::from_le_bytes(size);
+    if size > options.max_bytes as u64 {
+        return Err(NvErr::LimitExceeded(NvLimit::Bytes));
+    }
+    let data = &buf[HEADER_SIZE..];
+    let packed = match buf[4] {
+        1 => unzstd(data, size as usize)?,
+        2 => unlz4(data, size as usize)?,
+        _ => return Err(NvErr::InvalidFormat(4)),
+    };
+    if packed.len() as u64 != size {
+        return Err(NvErr::InvalidFormat(HEADER_SIZE));
+    }
+    Ok(Cow::Owned(packed))
+}
+
+impl NvTree {
+    /// Pack the tree and compress it with `codec`
+    ///
+    /// Trees holding descriptors can not be packed.
+    pub fn pack_compressed(&self, codec: Codec) -> io::Result<Vec<u8>> {
+        let mut packed = Vec::new();
+        self.pack_into(&mut packed)?;
+        compress(&packed, codec)
+    }
+
+    /// Decode a packed list that may be compressed, enforcing the limits
+    /// in `options` on the decompressed list
+    pub fn unpack_any(buf: &[u8], fds: &[RawFd], options: &UnpackOptions) -> NvResult<NvTree> {
+        NvTree::unpack(&decompress(buf, options)?, fds, options)
+    }
+}
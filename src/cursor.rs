@@ -0,0 +1,213 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+
+//! Cursors rewriting lists in a single pass
+//!
+//! An `NvCursor` walks the pairs of an `NvTree` or an `NvList` and can
+//! read, take, replace or remove the pair it is on, or descend into the
+//! nested list it holds, like libnv's `cnvlist_*` functions do with an
+//! iteration cookie.
+
+use common::NvType;
+use value::{NvTree, NvValue};
+
+/// The operations a list has to provide to be walked by an `NvCursor`
+pub(crate) trait Backend {
+    /// Move to the next pair, returning `false` at the end of the list
+    fn next(&mut self) -> bool;
+
+    /// The name of the current pair
+    fn name(&self) -> Option<&str>;
+
+    /// The type of the current pair
+    fn ty(&self) -> Option<NvType>;
+
+    /// A copy of the value of the current pair
+    fn get(&self) -> Option<NvValue>;
+
+    /// Remove the current pair, returning its value
+    fn take(&mut self) -> Option<NvValue>;
+
+    /// Remove the current pair, dropping its value
+    fn remove(&mut self) -> bool;
+
+    /// Append a pair that the cursor will not visit
+    fn append(&mut self, name: &str, value: NvValue);
+
+    /// A cursor over the list held by the current pair
+    fn descend<'b>(&'b mut self) -> Option<NvCursor<'b>>;
+}
+
+/// A cursor over the pairs of an `NvTree` or an `NvList`
+///
+/// The cursor starts before the first pair, so `next` has to be called
+/// to move onto it. After the current pair is taken, replaced or
+/// removed there is no current pair until the next call to `next`.
+/// libnv can only append pairs, so a replaced pair moves to the end of
+/// the list, where the cursor does not visit it again; both kinds of
+/// list behave the same way.
+///
+/// ```
+/// use nv::{NvFlag, NvTree, NvValue};
+///
+/// let mut tree = NvTree::new(NvFlag::None);
+/// tree.push("password", NvValue::String("hunter2".to_owned()));
+/// tree.push("user", NvValue::String("root".to_owned()));
+/// tree.push("uid", NvValue::Number(0));
+///
+/// {
+///     let mut cursor = tree.cursor_mut();
+///     while cursor.next() {
+///         match cursor.name() {
+///             Some("password") => {
+///                 cursor.remove();
+///             }
+///             Some("uid") => {
+///                 cursor.replace(NvValue::Number(1000));
+///             }
+///             _ => {}
+///         }
+///     }
+/// }
+///
+/// let names: Vec<&str> = tree.iter().map(|(name, _)| name).collect();
+/// assert_eq!(names, ["user", "uid"]);
+/// assert_eq!(tree.get("uid"), Some(&NvValue::Number(1000)));
+/// ```
+pub struct NvCursor<'a> {
+    backend: Box<dyn Backend + 'a>,
+}
+
+impl<'a> NvCursor<'a> {
+    pub(crate) fn new<B: Backend + 'a>(backend: B) -> NvCursor<'a> {
+        NvCursor { backend: Box::new(backend) }
+    }
+
+    /// Move to the next pair, returning `false` at the end of the list
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> bool {
+        self.backend.next()
+    }
+
+    /// The name of the current pair
+    pub fn name(&self) -> Option<&str> {
+        self.backend.name()
+    }
+
+    /// The type of the current pair
+    pub fn ty(&self) -> Option<NvType> {
+        self.backend.ty()
+    }
+
+    /// A copy of the value of the current pair
+    ///
+    /// Descriptors are still owned by the list.
+    pub fn get(&self) -> Option<NvValue> {
+        self.backend.get()
+    }
+
+    /// Remove the current pair, returning its value
+    ///
+    /// The caller takes ownership of any descriptors an `NvList` held in
+    /// the pair, nested lists included, like with `cnvlist_take_descriptor`.
+    pub fn take(&mut self) -> Option<NvValue> {
+        self.backend.take()
+    }
+
+    /// Remove the current pair, returning whether there was one
+    pub fn remove(&mut self) -> bool {
+        self.backend.remove()
+    }
+
+    /// Replace the value of the current pair, returning the old one
+    ///
+    /// The pair is moved to the end of the list, which the cursor does
+    /// not visit. The old value is returned as with `take`.
+    pub fn replace(&mut self, value: NvValue) -> Option<NvValue> {
+        let name = self.name()?.to_owned();
+        let old = self.backend.take();
+        self.backend.append(&name, value);
+        old
+    }
+
+    /// A cursor over the nested list the current pair holds
+    pub fn descend(&mut self) -> Option<NvCursor<'_>> {
+        self.backend.descend()
+    }
+}
+
+struct TreeCursor<'a> {
+    tree: &'a mut NvTree,
+    current: Option<usize>,
+    next: usize,
+    /// Where the pairs appended by the cursor start
+    end: usize,
+}
+
+impl<'a> TreeCursor<'a> {
+    fn pair(&self) -> Option<&(String, NvValue)> {
+        self.current.map(|index| &self.tree.pairs[index])
+    }
+}
+
+impl<'a> Backend for TreeCursor<'a> {
+    fn next(&mut self) -> bool {
+        if self.next < self.end {
+            self.current = Some(self.next);
+            self.next += 1;
+            true
+        } else {
+            self.current = None;
+            false
+        }
+    }
+
+    fn name(&self) -> Option<&str> {
+        self.pair().map(|(name, _)| name.as_str())
+    }
+
+    fn ty(&self) -> Option<NvType> {
+        self.pair().map(|(_, value)| value.ty())
+    }
+
+    fn get(&self) -> Option<NvValue> {
+        self.pair().map(|(_, value)| value.clone())
+    }
+
+    fn take(&mut self) -> Option<NvValue> {
+        let index = self.current.take()?;
+        self.next -= 1;
+        self.end -= 1;
+        Some(self.tree.pairs.remove(index).1)
+    }
+
+    fn remove(&mut self) -> bool {
+        self.take().is_some()
+    }
+
+    fn append(&mut self, name: &str, value: NvValue) {
+        self.tree.push(name, value);
+    }
+
+    fn descend<'b>(&'b mut self) -> Option<NvCursor<'b>> {
+        match self.tree.pairs[self.current?].1 {
+            NvValue::NvList(ref mut nested) => Some(nested.cursor_mut()),
+            _ => None,
+        }
+    }
+}
+
+impl NvTree {
+    /// Get a cursor to rewrite the tree in a single pass
+    pub fn cursor_mut(&mut self) -> NvCursor<'_> {
+        let end = self.pairs.len();
+        NvCursor::new(TreeCursor {
+            tree: self,
+            current: None,
+            next: 0,
+            end,
+        })
+    }
+}
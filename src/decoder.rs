@@ -0,0 +1,134 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+
+use common::{NvErr, NvResult};
+#[cfg(feature = "libnv")]
+use nvlist::NvList;
+use packed::{ListHeader, UnpackOptions, NVLIST_HEADER_SIZE};
+use value::NvTree;
+use std::task::Poll;
+
+/// Incremental decoder for packed `nvlist`s read off a byte stream
+///
+/// Bytes are fed in as they arrive, in chunks of any size, and a list is
+/// returned once all of it has been received. The decoder never blocks,
+/// so it fits any event loop driving a non-blocking socket.
+///
+/// The header of a list is checked against the `UnpackOptions` as soon as
+/// it is in, before anything past it is buffered, and exactly the size of
+/// the list is reserved. `feed` takes no more of a chunk than the list
+/// being received needs and advances the chunk past what it took, so the
+/// bytes of the next list stay with the caller until they are fed, and
+/// nothing that has not been checked is ever buffered.
+///
+/// Descriptors are not carried by the byte stream, so lists holding them
+/// fail to decode. After an error, the stream can not be resynchronized
+/// and the decoder should be dropped.
+///
+/// ```
+/// use nv::{NvDecoder, NvFlag, NvTree, NvValue, UnpackOptions};
+/// use std::task::Poll;
+///
+/// let mut tree = NvTree::new(NvFlag::None);
+/// tree.push("the answer", NvValue::Number(42));
+/// let mut buf = Vec::new();
+/// tree.pack_into(&mut buf).unwrap();
+///
+/// let mut decoder = NvDecoder::new(UnpackOptions::default());
+/// let (mut head, mut tail) = buf.split_at(10);
+///
+/// assert!(decoder.feed(&mut head).is_pending());
+/// match decoder.feed(&mut tail) {
+///     Poll::Ready(Ok(decoded)) => assert_eq!(decoded, tree),
+///     res => panic!("unexpected {:?}", res),
+/// }
+/// assert!(head.is_empty() && tail.is_empty());
+/// ```
+#[derive(Debug)]
+pub struct NvDecoder {
+    options: UnpackOptions,
+    buf: Vec<u8>,
+    /// The total size of the list being received, once its header is in
+    size: Option<usize>,
+}
+
+impl NvDecoder {
+    /// Create a decoder enforcing the limits in `options` on every list
+    pub fn new(options: UnpackOptions) -> NvDecoder {
+        NvDecoder {
+            options,
+            buf: Vec::new(),
+            size: None,
+        }
+    }
+
+    /// The number of bytes of the list being received
+    ///
+    /// If this is not zero when the stream ends, the last list was cut
+    /// short.
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Feed the next bytes of the stream, returning a list once one is
+    /// complete
+    ///
+    /// `data` is advanced past the bytes that were taken. Once a list is
+    /// returned, whatever is left in `data` belongs to the following
+    /// lists and should be fed again.
+    pub fn feed(&mut self, data: &mut &[u8]) -> Poll<NvResult<NvTree>> {
+        let size = match self.size {
+            Some(size) => size,
+            None => {
+                // Only take in the header until it is checked
+                self.take(data, NVLIST_HEADER_SIZE);
+                if self.buf.len() < NVLIST_HEADER_SIZE {
+                    return Poll::Pending;
+                }
+                let header = match ListHeader::check(&self.buf, &self.options) {
+                    Ok(header) => header,
+                    Err(err) => return self.fail(err),
+                };
+                let size = NVLIST_HEADER_SIZE + header.size as usize;
+                self.buf.reserve_exact(size - self.buf.len());
+                self.size = Some(size);
+                size
+            }
+        };
+        self.take(data, size);
+        if self.buf.len() < size {
+            return Poll::Pending;
+        }
+        let res = NvTree::unpack(&self.buf, &[], &self.options);
+        self.buf.clear();
+        self.size = None;
+        match res {
+            Ok(tree) => Poll::Ready(Ok(tree)),
+            Err(err) => self.fail(err),
+        }
+    }
+
+    /// Feed the next bytes of the stream, loading a complete list into
+    /// libnv
+    #[cfg(feature = "libnv")]
+    pub fn feed_nvlist(&mut self, data: &mut &[u8]) -> Poll<NvResult<NvList>> {
+        self.feed(data).map(|res| res.and_then(|tree| NvList::from_tree(&tree)))
+    }
+
+    /// Move bytes from the front of `data` into the buffer until it holds
+    /// `len` of them
+    fn take(&mut self, data: &mut &[u8], len: usize) {
+        let missing = len.saturating_sub(self.buf.len()).min(data.len());
+        let (head, tail) = data.split_at(missing);
+        self.buf.extend_from_slice(head);
+        *data = tail;
+    }
+
+    fn fail<T>(&mut self, err: NvErr) -> Poll<NvResult<T>> {
+        self.buf.clear();
+        self.size = None;
+        Poll::Ready(Err(err))
+    }
+}
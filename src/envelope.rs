@@ -0,0 +1,219 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+
+//! Signed envelopes around packed lists
+//!
+//! An envelope carries a packed list along with the id of the key that
+//! signed it and an HMAC-SHA256 or Ed25519 signature. `open` checks the
+//! signature before handing out the payload, so lists from the other side
+//! of a trust boundary are never decoded unless they are authentic.
+//!
+//! An envelope is laid out as
+//!
+//! | bytes    | content                                            |
+//! |----------|----------------------------------------------------|
+//! | 4        | magic, `nve` and the format version 1              |
+//! | 1        | the algorithm, 1 for HMAC-SHA256 and 2 for Ed25519 |
+//! | 1        | the length of the key id                           |
+//! | *n*      | the key id, in UTF-8                               |
+//! | *m*      | the payload                                        |
+//! | 32 or 64 | the signature of everything before it              |
+//!
+//! ```
+//! use nv::envelope::{self, Algorithm, OpenKey, SealKey};
+//! use nv::{NvFlag, NvTree, NvValue, UnpackOptions};
+//!
+//! let mut tree = NvTree::new(NvFlag::None);
+//! tree.push("cmd", NvValue::String("restart".to_owned()));
+//!
+//! let key = SealKey::HmacSha256(b"shared secret".to_vec());
+//! let buf = envelope::seal_tree(&tree, "jail-a", &key).unwrap();
+//!
+//! let opened = envelope::unpack(&buf, &UnpackOptions::default(), |key_id, algorithm| {
+//!     match (key_id, algorithm) {
+//!         ("jail-a", Algorithm::HmacSha256) => {
+//!             Some(OpenKey::HmacSha256(b"shared secret".to_vec()))
+//!         }
+//!         _ => None,
+//!     }
+//! });
+//! assert_eq!(opened.unwrap(), tree);
+//! ```
+
+use common::{NvErr, NvResult};
+use ed25519_dalek::{self, Signature, Signer};
+use hmac::{Hmac, Mac};
+use msgio;
+use packed::UnpackOptions;
+use sha2::Sha256;
+use value::NvTree;
+use libc;
+use std::str;
+
+const MAGIC: [u8; 4] = [b'n', b'v', b'e', 1];
+
+/// The algorithm an envelope is signed with
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    /// HMAC-SHA256 with a key shared by both sides
+    HmacSha256 = 1,
+    /// Ed25519 signatures
+    Ed25519 = 2,
+}
+
+impl Algorithm {
+    fn from_u8(value: u8) -> Option<Algorithm> {
+        match value {
+            1 => Some(Algorithm::HmacSha256),
+            2 => Some(Algorithm::Ed25519),
+            _ => None,
+        }
+    }
+
+    /// The size of the signatures made with the algorithm
+    pub fn signature_len(&self) -> usize {
+        match *self {
+            Algorithm::HmacSha256 => 32,
+            Algorithm::Ed25519 => ed25519_dalek::SIGNATURE_LENGTH,
+        }
+    }
+}
+
+/// A key to sign envelopes with
+pub enum SealKey {
+    /// A shared HMAC-SHA256 key
+    HmacSha256(Vec<u8>),
+    /// An Ed25519 private key
+    Ed25519(ed25519_dalek::SigningKey),
+}
+
+impl SealKey {
+    /// The algorithm the key signs with
+    pub fn algorithm(&self) -> Algorithm {
+        match *self {
+            SealKey::HmacSha256(_) => Algorithm::HmacSha256,
+            SealKey::Ed25519(_) => Algorithm::Ed25519,
+        }
+    }
+}
+
+/// A key to verify envelopes with
+pub enum OpenKey {
+    /// A shared HMAC-SHA256 key
+    HmacSha256(Vec<u8>),
+    /// An Ed25519 public key
+    Ed25519(ed25519_dalek::VerifyingKey),
+}
+
+/// The contents of an envelope whose signature was verified
+#[derive(Copy, Clone, Debug)]
+pub struct Envelope<'a> {
+    /// The id of the key the envelope was signed with
+    pub key_id: &'a str,
+    /// The algorithm the envelope was signed with
+    pub algorithm: Algorithm,
+    /// The packed list
+    pub payload: &'a [u8],
+}
+
+fn hmac(key: &[u8]) -> Hmac<Sha256> {
+    // HMAC takes keys of any length
+    Hmac::new_from_slice(key).expect("HMAC accepts any key length")
+}
+
+/// Sign `payload` with `key`, recording `key_id` for the receiver to
+/// look the key up
+///
+/// The key id must be between 1 and 255 bytes long.
+pub fn seal(payload: &[u8], key_id: &str, key: &SealKey) -> NvResult<Vec<u8>> {
+    if key_id.is_empty() || key_id.len() > u8::MAX as usize {
+        return Err(NvErr::Errno(libc::EINVAL));
+    }
+    let algorithm = key.algorithm();
+    let mut buf = Vec::with_capacity(6 + key_id.len() + payload.len() +
+                                     algorithm.signature_len());
+    buf.extend_from_slice(&MAGIC);
+    buf.push(algorithm as u8);
+    buf.push(key_id.len() as u8);
+    buf.extend_from_slice(key_id.as_bytes());
+    buf.extend_from_slice(payload);
+    match *key {
+        SealKey::HmacSha256(ref key) => {
+            let mut mac = hmac(key);
+            mac.update(&buf);
+            buf.extend_from_slice(&mac.finalize().into_bytes());
+        }
+        SealKey::Ed25519(ref key) => {
+            let signature = key.sign(&buf);
+            buf.extend_from_slice(&signature.to_bytes());
+        }
+    }
+    Ok(buf)
+}
+
+/// Pack `tree` and sign it with `key`, as `seal` does
+pub fn seal_tree(tree: &NvTree, key_id: &str, key: &SealKey) -> NvResult<Vec<u8>> {
+    let mut payload = Vec::new();
+    tree.pack_into(&mut payload).map_err(msgio::to_nv)?;
+    seal(&payload, key_id, key)
+}
+
+/// Verify the signature of an envelope, returning its contents
+///
+/// `keys` is given the key id and algorithm of the envelope, and returns
+/// the key to verify it with or `None` if the key is unknown. Unknown
+/// keys, keys of another algorithm and invalid signatures all give
+/// `NvErr::BadSignature`.
+pub fn open<'a, F>(buf: &'a [u8], keys: F) -> NvResult<Envelope<'a>>
+    where F: FnOnce(&str, Algorithm) -> Option<OpenKey>
+{
+    if buf.len() < 6 {
+        return Err(NvErr::Truncated(0));
+    }
+    if buf[..4] != MAGIC {
+        return Err(NvErr::InvalidFormat(0));
+    }
+    let algorithm = Algorithm::from_u8(buf[4]).ok_or(NvErr::InvalidFormat(4))?;
+    if buf[5] == 0 {
+        return Err(NvErr::InvalidFormat(5));
+    }
+    let header_len = 6 + buf[5] as usize;
+    if buf.len() < header_len + algorithm.signature_len() {
+        return Err(NvErr::Truncated(6));
+    }
+    let key_id = str::from_utf8(&buf[6..header_len]).map_err(|_| NvErr::InvalidFormat(6))?;
+    let (signed, signature) = buf.split_at(buf.len() - algorithm.signature_len());
+    let verified = match keys(key_id, algorithm) {
+        Some(OpenKey::HmacSha256(ref key)) if algorithm == Algorithm::HmacSha256 => {
+            let mut mac = hmac(key);
+            mac.update(signed);
+            mac.verify_slice(signature).is_ok()
+        }
+        Some(OpenKey::Ed25519(ref key)) if algorithm == Algorithm::Ed25519 => {
+            let signature = Signature::from_slice(signature).map_err(|_| NvErr::BadSignature)?;
+            key.verify_strict(signed, &signature).is_ok()
+        }
+        _ => false,
+    };
+    if !verified {
+        return Err(NvErr::BadSignature);
+    }
+    Ok(Envelope {
+        key_id,
+        algorithm,
+        payload: &signed[header_len..],
+    })
+}
+
+/// Verify the signature of an envelope and decode its payload, enforcing
+/// the limits in `options`
+///
+/// See `open` for how keys are looked up.
+pub fn unpack<F>(buf: &[u8], options: &UnpackOptions, keys: F) -> NvResult<NvTree>
+    where F: FnOnce(&str, Algorithm) -> Option<OpenKey>
+{
+    let envelope = open(buf, keys)?;
+    NvTree::unpack(envelope.payload, &[], options)
+}
@@ -0,0 +1,205 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+
+//! Packed lists stored in files
+//!
+//! `NvFile::open` maps a file holding a packed list into memory and
+//! validates it once, after which `NvFile::view` reads straight from the
+//! mapping. `NvFile::write_atomic` replaces a file with a packed list so
+//! that a crash leaves either the old list or the new one, never a mix
+//! of both.
+
+use common::{NvErr, NvLimit, NvResult};
+use msgio;
+#[cfg(feature = "libnv")]
+use nvlist::NvList;
+use packed::{self, UnpackOptions, NVLIST_HEADER_SIZE};
+use value::NvTree;
+use view::NvListView;
+use libc;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{process, ptr, slice};
+
+/// A file holding a packed list, mapped into memory
+///
+/// The mapping is private and read-only, and files replaced by
+/// `write_atomic` keep their old contents for as long as they are
+/// mapped. Changes made to a mapped file in place are another matter, see
+/// `NvFile::open`.
+///
+/// ```
+/// use nv::{NvFile, NvFlag, NvTree, NvValue};
+/// use std::env;
+///
+/// let path = env::temp_dir().join(format!("nv-doc-{}", std::process::id()));
+///
+/// let mut state = NvTree::new(NvFlag::None);
+/// state.push("generation", NvValue::Number(7));
+/// NvFile::write_tree_atomic(&path, &state).unwrap();
+///
+/// // Nothing else writes to the file
+/// let file = unsafe { NvFile::open(&path) }.unwrap();
+/// assert_eq!(file.view().get_number("generation"), Some(7));
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+pub struct NvFile {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+// The mapping is never written to, so it can be shared between threads
+unsafe impl Send for NvFile {}
+unsafe impl Sync for NvFile {}
+
+impl NvFile {
+    /// Map the packed list in `path`, validating it with the default
+    /// `UnpackOptions`
+    ///
+    /// # Safety
+    ///
+    /// As `open_with`.
+    pub unsafe fn open<P: AsRef<Path>>(path: P) -> NvResult<NvFile> {
+        NvFile::open_with(path, &UnpackOptions::default())
+    }
+
+    /// Map the packed list in `path`, validating it against the limits in
+    /// `options`
+    ///
+    /// The size of the file is checked before it is mapped. Packed lists
+    /// stored in files can not carry descriptors.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified in place or truncated while the
+    /// `NvFile` is alive, by this process or any other. Reading a truncated
+    /// part of the mapping faults, and changes to the file may show through
+    /// the private mapping, so the list would no longer be the one that
+    /// was validated. Replacing the file with `write_atomic` is fine.
+    pub unsafe fn open_with<P: AsRef<Path>>(path: P,
+                                            options: &UnpackOptions)
+                                            -> NvResult<NvFile> {
+        let file = File::open(path).map_err(msgio::to_nv)?;
+        let len = file.metadata().map_err(msgio::to_nv)?.len();
+        if len > options.max_bytes as u64 {
+            return Err(NvErr::LimitExceeded(NvLimit::Bytes));
+        }
+        // Empty mappings are not allowed, and no list is that short anyway
+        if len < NVLIST_HEADER_SIZE as u64 {
+            return Err(NvErr::Truncated(0));
+        }
+        let ptr = libc::mmap(ptr::null_mut(),
+                             len as usize,
+                             libc::PROT_READ,
+                             libc::MAP_PRIVATE,
+                             file.as_raw_fd(),
+                             0);
+        if ptr == libc::MAP_FAILED {
+            return Err(msgio::to_nv(io::Error::last_os_error()));
+        }
+        let mapped = NvFile { ptr, len: len as usize };
+        packed::walk(mapped.as_bytes(), &[], options, &mut ())?;
+        Ok(mapped)
+    }
+
+    /// The packed list
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+
+    /// A view of the list, reading straight from the mapping
+    pub fn view(&self) -> NvListView<'_> {
+        NvListView::validated(self.as_bytes(), &[])
+    }
+
+    /// Replace the file at `path` with `list`, packed
+    ///
+    /// The list is written to a temporary file in the same directory,
+    /// which is synced and renamed over `path`, and the directory is
+    /// synced in turn. The temporary file is only readable by its owner
+    /// until it gets the permissions of the file it replaces. Lists
+    /// holding descriptors can not be packed.
+    #[cfg(feature = "libnv")]
+    pub fn write_atomic<P: AsRef<Path>>(path: P, list: &NvList) -> io::Result<()> {
+        write_atomic(path.as_ref(), |writer| list.pack_into(writer))
+    }
+
+    /// Replace the file at `path` with `tree`, packed, as `write_atomic`
+    /// does
+    pub fn write_tree_atomic<P: AsRef<Path>>(path: P, tree: &NvTree) -> io::Result<()> {
+        write_atomic(path.as_ref(), |writer| tree.pack_into(writer))
+    }
+}
+
+impl Drop for NvFile {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr, self.len) };
+    }
+}
+
+/// Distinguishes the temporary files of concurrent writes in a process
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Replace the file at `path` with what `pack` writes, through a synced
+/// temporary file renamed over it
+pub(crate) fn write_atomic<F>(path: &Path, pack: F) -> io::Result<()>
+    where F: FnOnce(&mut BufWriter<&File>) -> io::Result<()>
+{
+    let name = path.file_name().ok_or_else(|| io::Error::from_raw_os_error(libc::EISDIR))?;
+    let dir = parent(path);
+    let (temp, file) = loop {
+        let mut temp_name = OsString::from(".");
+        temp_name.push(name);
+        temp_name.push(format!(".{}.{}.tmp",
+                               process::id(),
+                               TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)));
+        let temp = dir.join(temp_name);
+        // Never write through a file or link that is already there, such
+        // as the leftover of a crashed process that had the same pid
+        match OpenOptions::new().write(true).create_new(true).mode(0o600).open(&temp) {
+            Ok(file) => break (temp, file),
+            Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(err) => return Err(err),
+        }
+    };
+    let written = (|| {
+        let mut writer = BufWriter::new(&file);
+        pack(&mut writer)?;
+        writer.flush()?;
+        drop(writer);
+        match fs::metadata(path) {
+            Ok(metadata) => file.set_permissions(metadata.permissions())?,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        file.sync_all()?;
+        fs::rename(&temp, path)
+    })();
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written?;
+    // The rename is only durable once the directory is
+    sync_parent(path)
+}
+
+/// The directory holding `path`
+fn parent(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// Sync the directory holding `path`, making the creation or renaming of
+/// the file there durable
+pub(crate) fn sync_parent(path: &Path) -> io::Result<()> {
+    File::open(parent(path))?.sync_all()
+}
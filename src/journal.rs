@@ -0,0 +1,276 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+
+//! An append-only log of packed lists
+//!
+//! A journal starts with the magic `nvj` and the format version 1,
+//! followed by its records, each laid out as
+//!
+//! | bytes | content                                               |
+//! |-------|-------------------------------------------------------|
+//! | 8     | the size of the packed list, as a little-endian `u64` |
+//! | 4     | the CRC-32C of the packed list                        |
+//! | 4     | the CRC-32C of the 12 bytes above                     |
+//! | *n*   | the packed list                                       |
+//!
+//! Checksums are stored little-endian. Every record is written with a
+//! single write and synced before `append` returns. A crash in the middle
+//! of a write can only leave an incomplete or mismatching record at the
+//! end of the journal, or zeros where it was to go, which
+//! `NvJournal::open` drops. Damage anywhere else fails with
+//! `NvErr::InvalidFormat`. A write that fails without a crash is cut off
+//! again before `append` returns its error.
+
+use common::{NvErr, NvLimit, NvResult};
+use file;
+use msgio;
+#[cfg(feature = "libnv")]
+use nvlist::NvList;
+use packed::UnpackOptions;
+use value::NvTree;
+use libc;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: [u8; 4] = [b'n', b'v', b'j', 1];
+const RECORD_HEADER_SIZE: usize = 16;
+
+/// An append-only journal of packed lists in a file
+///
+/// Only one `NvJournal` should have a given file open at a time.
+///
+/// ```
+/// use nv::{NvFlag, NvJournal, NvTree, NvValue};
+/// use std::env;
+///
+/// let path = env::temp_dir().join(format!("nv-journal-doc-{}", std::process::id()));
+///
+/// let mut change = NvTree::new(NvFlag::None);
+/// change.push("mtu", NvValue::Number(9000));
+///
+/// let (mut journal, records) = NvJournal::open(&path).unwrap();
+/// assert!(records.is_empty());
+/// journal.append(&change).unwrap();
+/// drop(journal);
+///
+/// let (_, records) = NvJournal::open(&path).unwrap();
+/// assert_eq!(records, [change]);
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+pub struct NvJournal {
+    file: File,
+    path: PathBuf,
+    records: usize,
+    /// The size of the journal up to the end of the last record
+    len: u64,
+    /// A failed append could not be cut off the journal
+    poisoned: bool,
+}
+
+/// Lookup table for CRC-32C, the Castagnoli polynomial in reversed form
+const CRC32C_TABLE: [u32; 256] = crc32c_table();
+
+const fn crc32c_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0x82f6_3b78 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+fn crc32c(buf: &[u8]) -> [u8; 4] {
+    let crc = buf.iter().fold(!0u32, |crc, &byte| {
+        CRC32C_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    });
+    (!crc).to_le_bytes()
+}
+
+/// Frame a packed list as a record
+fn record(packed: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(RECORD_HEADER_SIZE + packed.len());
+    buf.extend_from_slice(&(packed.len() as u64).to_le_bytes());
+    buf.extend_from_slice(&crc32c(packed));
+    let sum = crc32c(&buf);
+    buf.extend_from_slice(&sum);
+    buf.extend_from_slice(packed);
+    buf
+}
+
+/// Decode the records of a journal, returning them along with the size
+/// of the journal up to the end of the last complete record
+///
+/// Only the last record may be cut short or mismatch its checksum, and
+/// only zeros may follow it; anything else is corruption.
+fn replay(buf: &[u8], options: &UnpackOptions) -> NvResult<(Vec<NvTree>, usize)> {
+    let mut records = Vec::new();
+    let mut pos = MAGIC.len();
+    while buf.len() - pos >= RECORD_HEADER_SIZE {
+        let header = &buf[pos..pos + RECORD_HEADER_SIZE];
+        if header[12..] != crc32c(&header[..12]) {
+            if buf[pos..].iter().all(|&byte| byte == 0) {
+                // The journal grew, but the record never made it to disk
+                break;
+            }
+            return Err(NvErr::InvalidFormat(pos));
+        }
+        let mut size = [0; 8];
+        size.copy_from_slice(&header[..8]);
+        let size = u64::from_le_bytes(size);
+        if size > options.max_bytes as u64 {
+            return Err(NvErr::LimitExceeded(NvLimit::Bytes));
+        }
+        let rest = (buf.len() - pos - RECORD_HEADER_SIZE) as u64;
+        if size > rest {
+            break;
+        }
+        let start = pos + RECORD_HEADER_SIZE;
+        let packed = &buf[start..start + size as usize];
+        if header[8..12] != crc32c(packed) {
+            if size < rest {
+                // Records follow, so this one was not torn by a crash
+                return Err(NvErr::InvalidFormat(pos));
+            }
+            break;
+        }
+        records.push(NvTree::unpack(packed, &[], options).map_err(|err| match err {
+            NvErr::Truncated(offset) => NvErr::Truncated(start + offset),
+            NvErr::InvalidFormat(offset) => NvErr::InvalidFormat(start + offset),
+            err => err,
+        })?);
+        pos = start + size as usize;
+    }
+    Ok((records, pos))
+}
+
+impl NvJournal {
+    /// Open the journal in `path`, creating it if it does not exist, and
+    /// replay its records with the default `UnpackOptions`
+    pub fn open<P: AsRef<Path>>(path: P) -> NvResult<(NvJournal, Vec<NvTree>)> {
+        NvJournal::open_with(path, &UnpackOptions::default())
+    }
+
+    /// Open the journal in `path`, creating it if it does not exist, and
+    /// replay its records, enforcing the limits in `options` on each
+    ///
+    /// A torn record at the end of the journal is cut off, so the next
+    /// record is appended after the last complete one. A new journal is
+    /// synced along with the directory holding it.
+    pub fn open_with<P: AsRef<Path>>(path: P,
+                                     options: &UnpackOptions)
+                                     -> NvResult<(NvJournal, Vec<NvTree>)> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .map_err(msgio::to_nv)?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf).map_err(msgio::to_nv)?;
+        let mut created = false;
+        let (records, end) = if buf.len() >= MAGIC.len() {
+            if buf[..MAGIC.len()] != MAGIC {
+                return Err(NvErr::InvalidFormat(0));
+            }
+            replay(&buf, options)?
+        } else if MAGIC.starts_with(&buf) {
+            // The journal is new, or its creation was cut short
+            file.set_len(0).map_err(msgio::to_nv)?;
+            file.write_all(&MAGIC).map_err(msgio::to_nv)?;
+            created = true;
+            (Vec::new(), MAGIC.len())
+        } else {
+            return Err(NvErr::InvalidFormat(0));
+        };
+        if end < buf.len() {
+            file.set_len(end as u64).map_err(msgio::to_nv)?;
+        }
+        file.sync_all().map_err(msgio::to_nv)?;
+        if created {
+            // Or the journal could vanish along with the records appended
+            file::sync_parent(path).map_err(msgio::to_nv)?;
+        }
+        let journal = NvJournal {
+            file,
+            path: path.to_owned(),
+            records: records.len(),
+            len: end as u64,
+            poisoned: false,
+        };
+        Ok((journal, records))
+    }
+
+    /// The number of records in the journal
+    pub fn records(&self) -> usize {
+        self.records
+    }
+
+    fn append_packed(&mut self, packed: &[u8]) -> io::Result<()> {
+        if self.poisoned {
+            // The journal still holds part of a failed record
+            return Err(io::Error::from_raw_os_error(libc::EIO));
+        }
+        let record = record(packed);
+        if let Err(err) = self.file.write_all(&record).and_then(|()| self.file.sync_data()) {
+            // Anything appended after a partial record would make the
+            // journal fail to replay
+            if self.file.set_len(self.len).and_then(|()| self.file.sync_data()).is_err() {
+                self.poisoned = true;
+            }
+            return Err(err);
+        }
+        self.len += record.len() as u64;
+        self.records += 1;
+        Ok(())
+    }
+
+    /// Append `tree` to the journal, returning once it is on disk
+    ///
+    /// Trees holding descriptors can not be packed. If the record can not
+    /// be written, whatever part of it reached the file is cut off again,
+    /// and if even that fails, every later append fails until the journal
+    /// is compacted or reopened.
+    pub fn append(&mut self, tree: &NvTree) -> io::Result<()> {
+        let mut packed = Vec::new();
+        tree.pack_into(&mut packed)?;
+        self.append_packed(&packed)
+    }
+
+    /// Append `list` to the journal, as `append` does
+    #[cfg(feature = "libnv")]
+    pub fn append_list(&mut self, list: &NvList) -> io::Result<()> {
+        let mut packed = Vec::new();
+        list.pack_into(&mut packed)?;
+        self.append_packed(&packed)
+    }
+
+    /// Replace every record of the journal with `snapshot`
+    ///
+    /// The compacted journal is written next to the old one and renamed
+    /// over it, so a crash leaves either of them whole.
+    pub fn compact(&mut self, snapshot: &NvTree) -> io::Result<()> {
+        let mut packed = Vec::new();
+        snapshot.pack_into(&mut packed)?;
+        let record = record(&packed);
+        file::write_atomic(&self.path, |writer| {
+            writer.write_all(&MAGIC)?;
+            writer.write_all(&record)
+        })?;
+        self.file = OpenOptions::new().read(true).append(true).open(&self.path)?;
+        self.records = 1;
+        self.len = (MAGIC.len() + record.len()) as u64;
+        self.poisoned = false;
+        Ok(())
+    }
+}
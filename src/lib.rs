@@ -11,11 +11,78 @@
 //! Rust bindings to the FreeBSD [`libnv`](https://www.freebsd.org/cgi/man.cgi?query=nv)
 //! which allows easy management of name/value pairs which may be sent and received
 //! over sockets.
+//!
+//! The bindings to libnv itself are behind the default `libnv` feature. The
+//! pure Rust decoder and encoder for packed lists (`NvTree::unpack`,
+//! `NvListView` and `NvWriter`) are always available, so they can be built,
+//! tested and fuzzed on other platforms with `--no-default-features`.
+
+#[cfg(feature = "tokio")]
+extern crate bytes;
+#[cfg(feature = "digest")]
+extern crate digest;
+#[cfg(feature = "envelope")]
+extern crate ed25519_dalek;
+#[cfg(feature = "envelope")]
+extern crate hmac;
+extern crate libc;
+#[cfg(feature = "lz4")]
+extern crate lz4_flex;
+#[cfg(feature = "envelope")]
+extern crate sha2;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(feature = "tokio")]
+extern crate tokio_util;
+#[cfg(feature = "zstd")]
+extern crate zstd;
 
+#[cfg(feature = "tokio")]
+mod async_io;
+mod canonical;
 mod common;
+pub mod compress;
+mod cursor;
+mod decoder;
+#[cfg(feature = "envelope")]
+pub mod envelope;
+mod file;
+mod journal;
+mod msgio;
+#[cfg(feature = "libnv")]
 mod nvlist;
+#[cfg(feature = "libnv")]
 mod nvops;
+pub mod nvpair;
+mod packed;
+mod read;
+mod redact;
+pub mod rpc;
+pub mod service;
+mod value;
+mod view;
+mod walk;
+mod writer;
+pub mod zfs;
 
-pub use common::{NvErr, NvResult, NvType, NV_NAME_MAX};
-pub use nvlist::{NvFlag, NvList};
-pub use nvops::NvListOps;
+#[cfg(feature = "tokio")]
+pub use async_io::{AsyncNvExt, NvListCodec, RecvNvList, SendNvList};
+#[cfg(all(feature = "tokio", feature = "libnv"))]
+pub use async_io::RecvList;
+pub use cursor::NvCursor;
+pub use common::{Endian, NvErr, NvFlag, NvLimit, NvResult, NvType, NV_NAME_MAX};
+pub use decoder::NvDecoder;
+pub use file::NvFile;
+pub use journal::NvJournal;
+#[cfg(feature = "libnv")]
+pub use nvlist::{FrozenNvList, NvEntry, NvList, NvStringWriter};
+#[cfg(feature = "libnv")]
+pub use nvops::{FromNvValue, NvListOps};
+pub use packed::UnpackOptions;
+pub use read::NvRead;
+pub use redact::{BinaryHash, RedactRules};
+pub use value::{NvTree, NvTreeIter, NvValue};
+pub use view::{NvDescriptors, NvListView, NvListViews, NvListViewsIter, NvNumbers, NvStrings,
+               NvValueRef, NvViewIter};
+pub use walk::{NvPath, NvPathElem, NvTreeWalk, NvWalk, Visitor};
+pub use writer::NvWriter;
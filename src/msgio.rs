@@ -0,0 +1,334 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+
+//! Socket transport compatible with libnv's `nvlist_send`/`nvlist_recv`
+//!
+//! The packed list is sent as a plain byte stream and its descriptors
+//! follow in packages of at most `PKG_MAX_SIZE`, each carried by a single
+//! dummy byte with an `SCM_RIGHTS` control message.
+
+use common::{NvErr, NvResult};
+use packed::{ListHeader, UnpackOptions, NVLIST_HEADER_SIZE};
+use value::{NvTree, NvValue};
+use libc;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Instant;
+use std::{mem, ptr};
+
+/// Number of descriptors libnv sends per message
+pub const PKG_MAX_SIZE: usize = 84;
+
+pub fn to_nv(err: io::Error) -> NvErr {
+    NvErr::Errno(err.raw_os_error().unwrap_or(libc::EIO))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+const SEND_FLAGS: libc::c_int = libc::MSG_NOSIGNAL;
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+const SEND_FLAGS: libc::c_int = 0;
+
+/// Receive at most `buf.len()` bytes with a single call
+///
+/// The end of the stream is reported as `ENOTCONN`, as libnv does.
+pub fn recv_some(sock: RawFd, buf: &mut [u8]) -> io::Result<usize> {
+    let ret = unsafe { libc::recv(sock, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+    match ret {
+        0 if !buf.is_empty() => Err(io::Error::from_raw_os_error(libc::ENOTCONN)),
+        ret if ret < 0 => Err(io::Error::last_os_error()),
+        ret => Ok(ret as usize),
+    }
+}
+
+/// Send at most `buf.len()` bytes with a single call
+pub fn send_some(sock: RawFd, buf: &[u8]) -> io::Result<usize> {
+    let ret = unsafe {
+        libc::send(sock, buf.as_ptr() as *const libc::c_void, buf.len(), SEND_FLAGS)
+    };
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret as usize)
+    }
+}
+
+/// Wait until `sock` is readable, failing with `ETIMEDOUT` at `deadline`
+pub fn wait_readable(sock: RawFd, deadline: Instant) -> NvResult<()> {
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        // Round up, so the deadline is never cut short
+        let millis = (left.as_micros() as u64).div_ceil(1000).min(libc::c_int::MAX as u64);
+        let mut fd = libc::pollfd {
+            fd: sock,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        match unsafe { libc::poll(&mut fd, 1, millis as libc::c_int) } {
+            0 => return Err(NvErr::Errno(libc::ETIMEDOUT)),
+            ret if ret > 0 => return Ok(()),
+            _ => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(to_nv(err));
+                }
+            }
+        }
+    }
+}
+
+/// Fill `buf` from the socket, retrying on `EINTR`, and failing with
+/// `ETIMEDOUT` if it is not full by `deadline`
+pub fn buf_recv(sock: RawFd, buf: &mut [u8], deadline: Option<Instant>) -> NvResult<()> {
+    let mut done = 0;
+    while done < buf.len() {
+        if let Some(deadline) = deadline {
+            wait_readable(sock, deadline)?;
+        }
+        match recv_some(sock, &mut buf[done..]) {
+            Ok(len) => done += len,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(to_nv(err)),
+        }
+    }
+    Ok(())
+}
+
+/// Send all of `buf`, retrying on `EINTR`
+pub fn buf_send(sock: RawFd, buf: &[u8]) -> NvResult<()> {
+    let mut done = 0;
+    while done < buf.len() {
+        match send_some(sock, &buf[done..]) {
+            Ok(len) => done += len,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(to_nv(err)),
+        }
+    }
+    Ok(())
+}
+
+/// Receive one package of exactly `count` descriptors with a single call,
+/// appending them to `fds`
+pub fn fd_package_recv(sock: RawFd, fds: &mut Vec<RawFd>, count: usize) -> io::Result<()> {
+    let mut dummy = 0u8;
+    let mut iov = libc::iovec {
+        iov_base: &mut dummy as *mut u8 as *mut libc::c_void,
+        iov_len: 1,
+    };
+    // libnv sends a message per descriptor, which some kernels pass on
+    // as they are rather than merged
+    let space = count * unsafe { libc::CMSG_SPACE(mem::size_of::<libc::c_int>() as u32) } as usize;
+    // `cmsghdr`s must be suitably aligned, which a `Vec<u8>` is not
+    let mut control = vec![0u64; space.div_ceil(8)];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = space as _;
+    let ret = unsafe { libc::recvmsg(sock, &mut msg, libc::MSG_CMSG_CLOEXEC) };
+    if ret == 0 {
+        return Err(io::Error::from_raw_os_error(libc::ENOTCONN));
+    } else if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let before = fds.len();
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(cmsg) as *const libc::c_int;
+                let len = ((*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize) /
+                          mem::size_of::<libc::c_int>();
+                for i in 0..len {
+                    fds.push(ptr::read_unaligned(data.add(i)));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    if fds.len() - before != count || msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        close_all(&fds[before..]);
+        fds.truncate(before);
+        return Err(io::Error::from_raw_os_error(libc::EINVAL));
+    }
+    Ok(())
+}
+
+/// Send one package of descriptors with a single call, each in a control
+/// message of its own as libnv does
+pub fn fd_package_send(sock: RawFd, fds: &[RawFd]) -> io::Result<()> {
+    let mut dummy = 0u8;
+    let mut iov = libc::iovec {
+        iov_base: &mut dummy as *mut u8 as *mut libc::c_void,
+        iov_len: 1,
+    };
+    let len = mem::size_of::<libc::c_int>() as u32;
+    let space = fds.len() * unsafe { libc::CMSG_SPACE(len) } as usize;
+    let mut control = vec![0u64; space.div_ceil(8)];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = space as _;
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        for &fd in fds {
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(len) as _;
+            ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut libc::c_int, fd);
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    let ret = unsafe { libc::sendmsg(sock, &msg, SEND_FLAGS) };
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Receive `count` descriptors by `deadline`, closing any already
+/// received on error
+pub fn fd_recv(sock: RawFd, count: usize, deadline: Option<Instant>) -> NvResult<Vec<RawFd>> {
+    let mut fds = Vec::with_capacity(count);
+    while fds.len() < count {
+        if let Some(deadline) = deadline {
+            if let Err(err) = wait_readable(sock, deadline) {
+                close_all(&fds);
+                return Err(err);
+            }
+        }
+        let step = ::std::cmp::min(PKG_MAX_SIZE, count - fds.len());
+        match fd_package_recv(sock, &mut fds, step) {
+            Ok(()) => {}
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => {
+                close_all(&fds);
+                return Err(to_nv(err));
+            }
+        }
+    }
+    Ok(fds)
+}
+
+/// Send descriptors in packages of at most `PKG_MAX_SIZE`
+pub fn fd_send(sock: RawFd, fds: &[RawFd]) -> NvResult<()> {
+    let mut done = 0;
+    while done < fds.len() {
+        let step = ::std::cmp::min(PKG_MAX_SIZE, fds.len() - done);
+        match fd_package_send(sock, &fds[done..done + step]) {
+            Ok(()) => done += step,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(to_nv(err)),
+        }
+    }
+    Ok(())
+}
+
+pub fn close_all(fds: &[RawFd]) {
+    for &fd in fds {
+        unsafe {
+            libc::close(fd);
+        }
+    }
+}
+
+/// Receive a packed `nvlist` and its descriptors, checking the header
+/// against `options` before allocating for the body
+pub fn recv(sock: RawFd, options: &UnpackOptions) -> NvResult<(Vec<u8>, Vec<RawFd>)> {
+    recv_until(sock, options, None)
+}
+
+/// Receive a packed `nvlist` and its descriptors as `recv` does, failing
+/// with `ETIMEDOUT` if they are not all in by `deadline`
+pub fn recv_until(sock: RawFd,
+                  options: &UnpackOptions,
+                  deadline: Option<Instant>)
+                  -> NvResult<(Vec<u8>, Vec<RawFd>)> {
+    let mut header = [0u8; NVLIST_HEADER_SIZE];
+    buf_recv(sock, &mut header, deadline)?;
+    let parsed = ListHeader::check(&header, options)?;
+    let mut buf = vec![0u8; NVLIST_HEADER_SIZE + parsed.size as usize];
+    buf[..NVLIST_HEADER_SIZE].copy_from_slice(&header);
+    buf_recv(sock, &mut buf[NVLIST_HEADER_SIZE..], deadline)?;
+    let fds = if parsed.descriptors > 0 {
+        fd_recv(sock, parsed.descriptors as usize, deadline)?
+    } else {
+        Vec::new()
+    };
+    Ok((buf, fds))
+}
+
+/// Send a packed `nvlist` followed by its descriptors
+pub fn send(sock: RawFd, buf: &[u8], fds: &[RawFd]) -> NvResult<()> {
+    buf_send(sock, buf)?;
+    fd_send(sock, fds)
+}
+
+impl NvTree {
+    /// Send the tree over a socket the way libnv's `nvlist_send` does, so
+    /// it can be received with `nvlist_recv`
+    ///
+    /// The descriptors in the tree are passed to the peer, and remain
+    /// owned by the caller.
+    pub fn send<S: AsRawFd>(&self, sock: &S) -> NvResult<()> {
+        let (buf, fds) = self.pack_message().map_err(|_| NvErr::Errno(libc::EINVAL))?;
+        send(sock.as_raw_fd(), &buf, &fds)
+    }
+
+    /// Receive an `nvlist` sent with libnv's `nvlist_send` from a socket
+    ///
+    /// The caller takes ownership of any descriptors in the returned tree.
+    /// If the list fails to decode, the received descriptors are closed.
+    pub fn recv<S: AsRawFd>(sock: &S, options: &UnpackOptions) -> NvResult<NvTree> {
+        let (buf, fds) = recv(sock.as_raw_fd(), options)?;
+        decode(&buf, &fds, options)
+    }
+}
+
+/// Decode a received list, closing the descriptors it does not refer to
+/// or all of them if it fails to decode
+pub fn decode(buf: &[u8], fds: &[RawFd], options: &UnpackOptions) -> NvResult<NvTree> {
+    match NvTree::unpack(buf, fds, options) {
+        Ok(tree) => {
+            close_unused(&tree, fds);
+            Ok(tree)
+        }
+        Err(err) => {
+            close_all(fds);
+            Err(err)
+        }
+    }
+}
+
+/// Close the received descriptors that the tree does not refer to
+fn close_unused(tree: &NvTree, fds: &[RawFd]) {
+    let used = descriptors_of(tree);
+    for &fd in fds {
+        if !used.contains(&fd) {
+            unsafe {
+                libc::close(fd);
+            }
+        }
+    }
+}
+
+/// Every descriptor in the tree, nested lists included
+pub fn descriptors_of(tree: &NvTree) -> Vec<RawFd> {
+    let mut used = Vec::new();
+    let mut pending = vec![tree];
+    while let Some(tree) = pending.pop() {
+        for (_, value) in tree.iter() {
+            match *value {
+                NvValue::Descriptor(fd) => used.push(fd),
+                NvValue::DescriptorArray(ref array) => used.extend_from_slice(array),
+                NvValue::NvList(ref nested) => pending.push(nested),
+                NvValue::NvListArray(ref array) => pending.extend(array.iter()),
+                _ => {}
+            }
+        }
+    }
+    used
+}
@@ -3,45 +3,29 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 
-use common::{NvErr, NvResult, NvType};
-use nvops::NvListOps;
+use common::{Endian, NvErr, NvFlag, NvResult, NvType};
+use compress::{self, Codec};
+use cursor::{Backend, NvCursor};
+#[cfg(feature = "digest")]
+use digest::{Digest, Output};
+use msgio;
+use nvops::{FromNvValue, NvListOps};
+use packed::UnpackOptions;
+use redact::RedactRules;
+use value::{NvTree, NvValue};
+use walk::Visitor;
+use writer::NvWriter;
+use libc::{self, c_char, c_void};
+use std::cmp;
 use std::ffi::{CStr, CString};
-use std::{slice, str};
-use std::os::unix::io::AsRawFd;
-
-/// Enumeration of options available to be passed to
-/// the creation of an `nvlist`
-#[repr(i32)]
-#[derive(Copy, Clone, Debug)]
-pub enum NvFlag {
-    /// No user specified options
-    None = 0,
-    /// Use case-insensitive name lookups
-    IgnoreCase = 1,
-    /// There may be duplicate names in the `nvlist`
-    NoUnique = 2,
-    /// Both of the previous flags
-    All = 3,
-}
-
-impl NvFlag {
-    /// Convert an `i32` into an NvFlag
-    pub fn from_i32(flags: i32) -> NvResult<NvFlag> {
-        match flags {
-            0 => Ok(NvFlag::None),
-            1 => Ok(NvFlag::IgnoreCase),
-            2 => Ok(NvFlag::NoUnique),
-            3 => Ok(NvFlag::All),
-            _ => Err(NvErr::ConstructionErr),
-        }
-    }
-}
-
-impl PartialEq for NvFlag {
-    fn eq(&self, other: &NvFlag) -> bool {
-        *self as i32 == *other as i32
-    }
-}
+use std::fmt;
+use std::io::{self, Write};
+use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+use std::ops::Deref;
+use std::{ptr, slice, str};
+use std::os::unix::io::{AsRawFd, BorrowedFd, RawFd};
+use std::sync::Arc;
 
 #[allow(missing_docs)]
 #[repr(C)]
@@ -49,11 +33,25 @@ impl PartialEq for NvFlag {
 struct nvlist;
 
 /// A list of name/value pairs
-#[derive(Debug)]
+///
+/// # Thread safety
+///
+/// An `NvList` owns its `nvlist`, along with any descriptors in it, so it
+/// may be moved to another thread. Every method libnv runs through a
+/// shared reference only reads the list, and its errors are reported
+/// through the thread-local `errno`, so `&NvList` may be shared between
+/// threads too. Everything that modifies the list, including
+/// `set_error`, takes `&mut self`. Use `freeze` to share a finished list
+/// behind an `Arc`.
+#[derive(Debug, Default)]
 pub struct NvList {
     list: Option<*mut nvlist>,
 }
 
+// See "Thread safety" above
+unsafe impl Send for NvList {}
+unsafe impl Sync for NvList {}
+
 impl NvList {
     /// Create a new name/value pair list (`nvlist`)
     ///
@@ -123,6 +121,9 @@ impl NvList {
 
     /// Sets the `NvList` to be in an error state
     ///
+    /// This modifies the list, so it takes `&mut self` to keep shared
+    /// references read-only.
+    ///
     /// ```
     /// use nv::{NvList, NvFlag};
     ///
@@ -133,13 +134,21 @@ impl NvList {
     ///
     /// assert_eq!(0x16, list.error());
     /// ```
-    pub fn set_error(&self, error: i32) -> NvResult<()> {
+    pub fn set_error(&mut self, error: i32) -> NvResult<()> {
         match self.list {
-            Some(list) => Ok(unsafe { nvlist_set_error(list, error) }),
+            Some(list) => {
+                unsafe { nvlist_set_error(list, error) };
+                Ok(())
+            }
             None => Err(NvErr::ErrorNotSet(error)),
         }
     }
 
+    /// Stop modifying the list, so it can be shared between threads
+    pub fn freeze(self) -> FrozenNvList {
+        FrozenNvList { list: Arc::new(self) }
+    }
+
     /// Genericially add a single value to the NvList
     ///
     /// ```
@@ -157,7 +166,7 @@ impl NvList {
     ///
     /// assert_eq!(list.get_number("the answer").unwrap(), 42);
     /// ```
-    pub fn add<T: NvListOps>(&mut self, name: &str, value: T) -> () {
+    pub fn add<T: NvListOps>(&mut self, name: &str, value: T) {
         value.nv_add(self, name);
     }
 
@@ -166,23 +175,13 @@ impl NvList {
     /// ```ignore
     /// list.add_null("Hello, World!");
     /// ```
-    pub fn add_null(&mut self, name: &str) -> () {
-        if let Some(list) = self.list {
-            let c_name = CString::new(name).expect("Could not decode string");
-            unsafe {
-                nvlist_add_null(list, c_name.as_ptr());
-            }
-        }
+    pub fn add_null(&mut self, name: &str) {
+        self.add_null_c(&c_name(name))
     }
 
     /// Add a `bool` to the list
-    pub fn add_bool(&mut self, name: &str, value: bool) -> () {
-        if let Some(list) = self.list {
-            let c_name = CString::new(name).expect("Could not decode string");
-            unsafe {
-                nvlist_add_bool(list, c_name.as_ptr(), value);
-            }
-        }
+    pub fn add_bool(&mut self, name: &str, value: bool) {
+        self.add_bool_c(&c_name(name), value)
     }
 
     /// Add a `u64` to the `NvList`
@@ -194,21 +193,68 @@ impl NvList {
     ///
     /// list.add_number("the answer", 42u64);
     /// ```
-    pub fn add_number(&mut self, name: &str, value: u64) -> () {
-        if let Some(list) = self.list {
-            let c_name = CString::new(name).expect("Could not decode string");
-            unsafe {
-                nvlist_add_number(list, c_name.as_ptr(), value);
+    pub fn add_number(&mut self, name: &str, value: u64) {
+        self.add_number_c(&c_name(name), value)
+    }
+
+    /// Add string to the list
+    pub fn add_string(&mut self, name: &str, value: &str) {
+        self.add_string_c(&c_name(name), value)
+    }
+
+    /// Add a formatted string to the list, like libnv's
+    /// `nvlist_add_stringf`
+    ///
+    /// The string is formatted straight into memory handed over to the
+    /// list. If the formatted string holds a NUL byte, the list's error is
+    /// set to `EINVAL` instead.
+    ///
+    /// ```
+    /// use nv::{NvList, NvFlag};
+    ///
+    /// let mut list = NvList::new(NvFlag::None).unwrap();
+    /// list.add_fmt("greeting", format_args!("Hello, {}!", "World"));
+    ///
+    /// assert_eq!(list.get_str("greeting"), Some("Hello, World!"));
+    /// ```
+    pub fn add_fmt(&mut self, name: &str, args: fmt::Arguments) {
+        let mut writer = self.string_writer(name);
+        match fmt::write(&mut writer, args) {
+            Ok(()) => writer.finish(),
+            Err(_) => {
+                drop(writer);
+                let _ = self.set_error(libc::EINVAL);
             }
         }
     }
 
-    /// Add string to the list
-    pub fn add_string(&mut self, name: &str, value: &str) -> () {
-        if let Some(list) = self.list {
-            let c_name = CString::new(name).expect("Could not decode string");
-            let c_value = CString::new(value).expect("Could not decode string");
-            unsafe { nvlist_add_string(list, c_name.as_ptr(), c_value.as_ptr()) }
+    /// Build a string value from pieces written with `fmt::Write`
+    ///
+    /// The string is added when the writer is finished, and discarded if
+    /// it is dropped instead.
+    ///
+    /// ```
+    /// use nv::{NvList, NvFlag};
+    /// use std::fmt::Write;
+    ///
+    /// let mut list = NvList::new(NvFlag::None).unwrap();
+    /// {
+    ///     let mut writer = list.string_writer("squares");
+    ///     for i in 1..5 {
+    ///         write!(writer, "{} ", i * i).unwrap();
+    ///     }
+    ///     writer.finish();
+    /// }
+    ///
+    /// assert_eq!(list.get_str("squares"), Some("1 4 9 16 "));
+    /// ```
+    pub fn string_writer<'a>(&'a mut self, name: &str) -> NvStringWriter<'a> {
+        NvStringWriter {
+            list: self,
+            name: CString::new(name).expect("Could not decode string"),
+            buf: ptr::null_mut(),
+            len: 0,
+            cap: 0,
         }
     }
 
@@ -225,28 +271,17 @@ impl NvList {
     ///
     /// assert_eq!(other_list.get_bool("something"), None);
     /// ```
-    pub fn add_nvlist(&mut self, name: &str, value: &NvList) -> () {
-        let c_name = CString::new(name).expect("Could not decode string");
-        match (self.list, value.list) {
-            // Both are valid
-            (Some(this), Some(other)) if !other.is_null() => unsafe {
-                nvlist_add_nvlist(this, c_name.as_ptr(), other)
-            },
-            // This is valid, but the other is not
-            (Some(this), _) => unsafe {
-                nvlist_add_nvlist(this, c_name.as_ptr(), nvlist_create(self.flags() as i32))
-            },
-            // Something bad happened... nop
-            _ => {}
-        }
+    pub fn add_nvlist(&mut self, name: &str, value: &NvList) {
+        self.add_nvlist_c(&c_name(name), value)
     }
 
     /// Add binary data to the list
-    pub unsafe fn add_binary(&mut self, name: &str, value: *mut i8, size: u32) -> () {
-        let c_name = CString::new(name).expect("Could not decode string");
-        if let Some(list) = self.list {
-            nvlist_add_binary(list, c_name.as_ptr(), value, size);
-        }
+    ///
+    /// # Safety
+    ///
+    /// `value` must point to `size` bytes that can be read.
+    pub unsafe fn add_binary(&mut self, name: &str, value: *mut i8, size: usize) {
+        self.add_binary_c(&c_name(name), value, size)
     }
 
     /// Add slice of `bool` values
@@ -260,13 +295,8 @@ impl NvList {
     ///
     /// list.add_bool_slice("the answer", &slice);
     /// ```
-    pub fn add_bool_slice(&mut self, name: &str, value: &[bool]) -> () {
-        if let Some(list) = self.list {
-            let c_name = CString::new(name).expect("Could not decode string");
-            unsafe {
-                nvlist_add_bool_array(list, c_name.as_ptr(), value.as_ptr(), value.len());
-            }
-        }
+    pub fn add_bool_slice(&mut self, name: &str, value: &[bool]) {
+        self.add_bool_slice_c(&c_name(name), value)
     }
 
     /// Add slice of `u64`s
@@ -281,20 +311,13 @@ impl NvList {
     /// list.add_number_slice("the answer", &slice);
     ///
     /// ```
-    pub fn add_number_slice(&mut self, name: &str, value: &[u64]) -> () {
-        if let Some(list) = self.list {
-            let c_name = CString::new(name).expect("Could not decode string");
-            unsafe {
-                nvlist_add_number_array(list, c_name.as_ptr(), value.as_ptr(), value.len());
-            }
-        }
+    pub fn add_number_slice(&mut self, name: &str, value: &[u64]) {
+        self.add_number_slice_c(&c_name(name), value)
     }
 
     /// Add a slice of strings
     ///
-    /// **NB**: This is currently broken
-    ///
-    /// ```should_panic
+    /// ```
     /// use nv::{NvList, NvFlag};
     ///
     /// let mut list = NvList::new(NvFlag::None).unwrap();
@@ -307,19 +330,8 @@ impl NvList {
     ///
     /// assert_eq!(*vec, ["Hello", "World!"]);
     /// ```
-    pub fn add_string_slice(&mut self, name: &str, value: &[&str]) -> () {
-        if let Some(list) = self.list {
-            let c_name = CString::new(name).expect("Could not decode string");
-            unsafe {
-                let tmp: Vec<*const i8> = value.iter()
-                    .map(|item| CString::new(*item).expect("Could not decode string").as_ptr())
-                    .collect();
-                nvlist_add_string_array(list,
-                                        c_name.as_ptr(),
-                                        tmp.as_slice().as_ptr(),
-                                        value.len());
-            }
-        }
+    pub fn add_string_slice(&mut self, name: &str, value: &[&str]) {
+        self.add_string_slice_c(&c_name(name), value)
     }
 
     /// Add a slice of `NvList`s
@@ -338,20 +350,22 @@ impl NvList {
     ///
     /// assert_eq!(NvFlag::None, nvlists.pop().unwrap().flags());
     /// ```
-    pub fn add_nvlist_slice(&mut self, name: &str, value: &[NvList]) -> () {
-        if let Some(list) = self.list {
-            let c_name = CString::new(name).expect("Could not decode string");
-            unsafe {
-                let tmp: Vec<*const nvlist> = value.iter()
-                    .filter(|item| match item.list {
-                        Some(item) if !item.is_null() => true,
-                        _ => false,
-                    })
-                    .map(|item| item.list.unwrap() as *const nvlist)
-                    .collect();
-                nvlist_add_nvlist_array(list, c_name.as_ptr(), tmp.as_slice().as_ptr(), tmp.len());
-            }
-        }
+    pub fn add_nvlist_slice(&mut self, name: &str, value: &[NvList]) {
+        self.add_nvlist_slice_c(&c_name(name), value)
+    }
+
+    /// Add a file descriptor to the `NvList`
+    ///
+    /// The descriptor is duplicated, so the caller keeps ownership of `fd`
+    pub fn add_descriptor(&mut self, name: &str, fd: RawFd) {
+        self.add_descriptor_c(&c_name(name), fd)
+    }
+
+    /// Add a slice of file descriptors to the `NvList`
+    ///
+    /// The descriptors are duplicated, so the caller keeps ownership of them
+    pub fn add_descriptor_slice(&mut self, name: &str, value: &[RawFd]) {
+        self.add_descriptor_slice_c(&c_name(name), value)
     }
 
     /// Returns `true` if a name/value pair
@@ -435,6 +449,22 @@ impl NvList {
     /// assert_eq!(list.get_string("Hello").unwrap(), "World!");
     /// ```
     pub fn get_string(&self, name: &str) -> Option<String> {
+        self.get_str(name).map(|value| value.to_owned())
+    }
+
+    /// Get the first matching string paired with the given name without
+    /// copying it out of the `NvList`
+    ///
+    /// ```
+    /// use nv::{NvList, NvFlag};
+    ///
+    /// let mut list = NvList::new(NvFlag::None).unwrap();
+    ///
+    /// list.add_string("Hello", "World!");
+    ///
+    /// assert_eq!(list.get_str("Hello"), Some("World!"));
+    /// ```
+    pub fn get_str<'a>(&'a self, name: &str) -> Option<&'a str> {
         let c_name = CString::new(name).expect("Could not decode string");
         match self.list {
             Some(list) => unsafe {
@@ -443,8 +473,41 @@ impl NvList {
                     if ret.is_null() {
                         None
                     } else {
-                        let len = strlen(ret);
-                        Some(String::from_raw_parts(ret as *mut u8, len, len))
+                        CStr::from_ptr(ret).to_str().ok()
+                    }
+                } else {
+                    None
+                }
+            },
+            _ => None,
+        }
+    }
+
+    /// Get the first matching binary buffer paired with the given name
+    ///
+    /// ```
+    /// use nv::{NvList, NvFlag};
+    ///
+    /// let mut list = NvList::new(NvFlag::None).unwrap();
+    /// let mut data = *b"\xde\xad";
+    ///
+    /// unsafe {
+    ///     list.add_binary("blob", data.as_mut_ptr() as *mut i8, data.len());
+    /// }
+    ///
+    /// assert_eq!(list.get_binary("blob"), Some(&b"\xde\xad"[..]));
+    /// ```
+    pub fn get_binary<'a>(&'a self, name: &str) -> Option<&'a [u8]> {
+        let c_name = CString::new(name).expect("Could not decode string");
+        match self.list {
+            Some(list) => unsafe {
+                if nvlist_exists_binary(list, c_name.as_ptr()) {
+                    let mut len: usize = 0;
+                    let ret = nvlist_get_binary(list, c_name.as_ptr(), &mut len);
+                    if ret.is_null() {
+                        None
+                    } else {
+                        Some(slice::from_raw_parts(ret, len))
                     }
                 } else {
                     None
@@ -491,6 +554,47 @@ impl NvList {
         }
     }
 
+    /// Generically get a value from the `NvList`, converting numbers to
+    /// the requested integer type
+    ///
+    /// ```
+    /// use nv::{NvErr, NvFlag, NvList, NvListOps};
+    ///
+    /// let mut list = NvList::new(NvFlag::None).unwrap();
+    /// list.add("port", 8080u16);
+    /// list.add("offset", -3i32);
+    /// list.add("big", u64::MAX);
+    ///
+    /// assert_eq!(list.get::<u16>("port").unwrap(), 8080);
+    /// assert_eq!(list.get::<i64>("offset").unwrap(), -3);
+    /// assert!(matches!(list.get::<u32>("big"), Err(NvErr::OutOfRange(_))));
+    /// assert!(matches!(list.get::<u32>("missing"), Err(NvErr::NotFound)));
+    /// ```
+    pub fn get<'a, T: FromNvValue<'a>>(&'a self, name: &str) -> NvResult<T> {
+        T::from_nv(self, name)
+    }
+
+    /// Generically get a value from the `NvList`, or `default` if there
+    /// is none
+    ///
+    /// Values that are present but do not convert are still errors.
+    ///
+    /// ```
+    /// use nv::{NvFlag, NvList};
+    ///
+    /// let mut list = NvList::new(NvFlag::None).unwrap();
+    /// list.add_string("host", "localhost");
+    ///
+    /// assert_eq!(list.get_or("host", "example.org").unwrap(), "localhost");
+    /// assert_eq!(list.get_or("port", 8080u16).unwrap(), 8080);
+    /// ```
+    pub fn get_or<'a, T: FromNvValue<'a>>(&'a self, name: &str, default: T) -> NvResult<T> {
+        match T::from_nv(self, name) {
+            Err(NvErr::NotFound) => Ok(default),
+            res => res,
+        }
+    }
+
     /// Get a `&[bool]` from the `NvList`
     ///
     /// ```
@@ -550,8 +654,6 @@ impl NvList {
 
     /// Get a `Vec<String>` of the first string slice added to the `NvList`
     /// for the given name
-    ///
-    /// **NB**: This is currently broken
     pub fn get_string_vec(&self, name: &str) -> Option<Vec<String>> {
         let c_name = CString::new(name).expect("Could not decode string");
         match self.list {
@@ -560,7 +662,7 @@ impl NvList {
                     let mut len: usize = 0;
                     let arr =
                         nvlist_get_string_array(list, c_name.as_ptr(), &mut len as *mut usize);
-                    let slice = slice::from_raw_parts(arr as *const *const i8, len);
+                    let slice = slice::from_raw_parts(arr, len);
                     Some(slice.iter()
                         .map(|item| CStr::from_ptr(*item).to_string_lossy().into_owned())
                         .collect())
@@ -599,7 +701,7 @@ impl NvList {
                     let mut len: usize = 0;
                     let arr =
                         nvlist_get_nvlist_array(list, c_name.as_ptr(), &mut len as *mut usize);
-                    let slice = slice::from_raw_parts(arr as *const *const nvlist, len);
+                    let slice = slice::from_raw_parts(arr, len);
                     Some(slice.iter()
                         .map(|item| NvList { list: Some(nvlist_clone(*item)) })
                         .collect())
@@ -611,6 +713,40 @@ impl NvList {
         }
     }
 
+    /// Borrow the first matching descriptor paired with the given name
+    ///
+    /// The descriptor is owned by the `NvList` and closed along with it.
+    pub fn get_descriptor<'a>(&'a self, name: &str) -> Option<BorrowedFd<'a>> {
+        let c_name = CString::new(name).expect("Could not decode string");
+        match self.list {
+            Some(list) => unsafe {
+                if nvlist_exists_type(list, c_name.as_ptr(), NvType::Descriptor as i32) {
+                    Some(BorrowedFd::borrow_raw(nvlist_get_descriptor(list, c_name.as_ptr())))
+                } else {
+                    None
+                }
+            },
+            None => None,
+        }
+    }
+
+    /// Get a `&[RawFd]` slice of descriptors owned by the `NvList`
+    pub fn get_descriptor_slice<'a>(&'a self, name: &str) -> Option<&'a [RawFd]> {
+        let c_name = CString::new(name).expect("Could not decode string");
+        match self.list {
+            Some(list) => unsafe {
+                if nvlist_exists_type(list, c_name.as_ptr(), NvType::DescriptorArray as i32) {
+                    let mut len: usize = 0;
+                    let arr = nvlist_get_descriptor_array(list, c_name.as_ptr(), &mut len);
+                    Some(slice::from_raw_parts(arr, len))
+                } else {
+                    None
+                }
+            },
+            None => None,
+        }
+    }
+
     /// Write `NvList` to a file descriptor
     ///
     /// ```
@@ -623,7 +759,7 @@ impl NvList {
     ///
     /// list.dump(File::create("/tmp/foo").unwrap());
     /// ```
-    pub fn dump<T: AsRawFd>(&self, file: T) -> () {
+    pub fn dump<T: AsRawFd>(&self, file: T) {
         if let Some(list) = self.list {
             unsafe { nvlist_dump(list, file.as_raw_fd()) }
         }
@@ -639,7 +775,7 @@ impl NvList {
 
     /// Remove the element of the given name from the
     /// `NvList`
-    pub fn free(&mut self, name: &str) -> () {
+    pub fn free(&mut self, name: &str) {
         let c_name = CString::new(name).expect("Could not decode string");
         if let Some(list) = self.list {
             unsafe {
@@ -650,7 +786,7 @@ impl NvList {
 
     /// Remove the element of the given name and type
     /// from the `NvList`
-    pub fn free_type(&mut self, name: &str, ty: NvType) -> () {
+    pub fn free_type(&mut self, name: &str, ty: NvType) {
         let c_name = CString::new(name).expect("Could not decode string");
         if let Some(list) = self.list {
             unsafe {
@@ -658,17 +794,1092 @@ impl NvList {
             }
         }
     }
-}
 
-impl Clone for NvList {
-    fn clone(&self) -> NvList {
-        NvList { list: self.list.map(|list| unsafe { nvlist_clone(list) }) }
+    /// Get the pair of the given name for in-place manipulation, like
+    /// `HashMap::entry`
+    ///
+    /// ```
+    /// use nv::{NvList, NvFlag};
+    ///
+    /// let mut list = NvList::new(NvFlag::None).unwrap();
+    /// for _ in 0..3 {
+    ///     list.entry("count").and_modify(|count: u64| count + 1).or_insert(1u64);
+    /// }
+    /// list.entry("count").or_insert("ignored");
+    ///
+    /// assert_eq!(list.get_number("count"), Some(3));
+    ///
+    /// list.entry("count").insert("three");
+    /// assert_eq!(list.get_str("count"), Some("three"));
+    /// ```
+    pub fn entry<'a, 'b>(&'a mut self, name: &'b str) -> NvEntry<'a, 'b> {
+        NvEntry::new(self, name)
+    }
+
+    /// Get a cursor to rewrite the list in a single pass, with the same
+    /// semantics as `NvTree::cursor_mut`
+    ///
+    /// ```
+    /// use nv::{NvList, NvFlag, NvValue};
+    ///
+    /// let mut list = NvList::new(NvFlag::None).unwrap();
+    /// list.add_string("password", "hunter2");
+    /// list.add_number("uid", 0);
+    ///
+    /// {
+    ///     let mut cursor = list.cursor_mut();
+    ///     while cursor.next() {
+    ///         if cursor.name() == Some("password") {
+    ///             cursor.replace(NvValue::String("*".to_owned()));
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// assert_eq!(list.get_str("password"), Some("*"));
+    /// ```
+    pub fn cursor_mut(&mut self) -> NvCursor<'_> {
+        NvCursor::new(ListCursor {
+            list: self.list.unwrap_or(ptr::null_mut()),
+            cookie: ptr::null_mut(),
+            prev: ptr::null_mut(),
+            name: ptr::null(),
+            ty: 0,
+            current: false,
+            remaining: None,
+            marker: PhantomData,
+        })
+    }
+
+    /// A copy of the list that is safe to log, as `NvTree::redacted`
+    /// makes it
+    ///
+    /// ```
+    /// use nv::{NvList, NvFlag, RedactRules};
+    ///
+    /// let mut list = NvList::new(NvFlag::None).unwrap();
+    /// list.add_string("password", "hunter2");
+    ///
+    /// let redacted = list.redacted(&RedactRules::new(&["password"])).unwrap();
+    ///
+    /// assert_eq!(redacted.get_str("password"), Some("<redacted>"));
+    /// ```
+    pub fn redacted(&self, rules: &RedactRules) -> NvResult<NvList> {
+        match self.list {
+            Some(list) => NvList::from_tree(&unsafe { tree_of(list) }.redacted(rules)),
+            None => Ok(NvList::default()),
+        }
+    }
+
+    /// Keep only the pairs for which `f` returns `true`, like
+    /// `NvTree::retain`
+    ///
+    /// Descriptors of the removed pairs are closed.
+    ///
+    /// ```
+    /// use nv::{NvList, NvFlag, NvValue};
+    ///
+    /// let mut list = NvList::new(NvFlag::None).unwrap();
+    /// list.add_number("uid", 0);
+    /// list.add_string("token", "secret");
+    ///
+    /// list.retain(|_, value| !matches!(value, NvValue::String(_)));
+    ///
+    /// assert!(list.exists("uid"));
+    /// assert!(!list.exists("token"));
+    /// ```
+    pub fn retain<F>(&mut self, mut f: F)
+        where F: FnMut(&str, &NvValue) -> bool
+    {
+        let mut cursor = self.cursor_mut();
+        while cursor.next() {
+            let keep = match (cursor.name(), cursor.get()) {
+                (Some(name), Some(value)) => f(name, &value),
+                _ => true,
+            };
+            if !keep {
+                cursor.remove();
+            }
+        }
+    }
+
+    /// The canonical form of the list, as `NvTree::canonical_bytes`
+    /// makes it
+    ///
+    /// ```
+    /// use nv::{NvList, NvFlag};
+    ///
+    /// let mut a = NvList::new(NvFlag::None).unwrap();
+    /// a.add_string("name", "tank");
+    /// a.add_number("version", 5000);
+    ///
+    /// let mut b = NvList::new(NvFlag::None).unwrap();
+    /// b.add_number("version", 5000);
+    /// b.add_string("name", "tank");
+    ///
+    /// assert_eq!(a.canonical_bytes().unwrap(), b.canonical_bytes().unwrap());
+    /// ```
+    pub fn canonical_bytes(&self) -> io::Result<Vec<u8>> {
+        self.to_tree()?.canonical_bytes()
+    }
+
+    /// Hash the canonical form of the list with `D`
+    #[cfg(feature = "digest")]
+    pub fn digest<D: Digest>(&self) -> io::Result<Output<D>> {
+        self.to_tree()?.digest::<D>()
+    }
+
+    /// Pack the list with its integers in the given byte order, to make
+    /// fixtures for hosts of either byte order
+    pub fn pack_with_endianness(&self, endian: Endian) -> io::Result<Vec<u8>> {
+        self.to_tree()?.pack_with_endianness(endian)
+    }
+
+    /// A copy of the list and everything nested in it
+    ///
+    /// Nested lists are followed with libnv's `nvlist_get_parent` and
+    /// `nvlist_get_array_next` rather than by recursing, so lists nested
+    /// however deep are copied. Descriptors are still owned by the list.
+    ///
+    /// ```
+    /// use nv::{NvList, NvFlag, NvValue};
+    ///
+    /// let mut vdev = NvList::new(NvFlag::None).unwrap();
+    /// vdev.add_string("path", "/dev/ada0");
+    /// let mut list = NvList::new(NvFlag::None).unwrap();
+    /// list.add_nvlist_slice("vdevs", &[vdev]);
+    ///
+    /// let tree = list.to_tree().unwrap();
+    /// let paths: Vec<String> = tree.walk().map(|(path, _)| path.to_string()).collect();
+    ///
+    /// assert_eq!(paths, ["vdevs", "vdevs[0].path"]);
+    /// ```
+    pub fn to_tree(&self) -> io::Result<NvTree> {
+        match self.list {
+            Some(list) => Ok(unsafe { tree_of(list) }),
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "empty NvList")),
+        }
+    }
+
+    /// Pass every pair of the list and the lists nested in it to
+    /// `visitor`, depth-first, as `NvTree::visit` does with `to_tree`
+    pub fn visit<V>(&self, visitor: &mut V) -> io::Result<()>
+        where V: for<'a> Visitor<'a, &'a NvValue>
+    {
+        self.to_tree()?.visit(visitor);
+        Ok(())
     }
-}
 
-impl Default for NvList {
-    fn default() -> NvList {
-        NvList { list: None }
+    /// Create an `NvList` holding the same pairs as an `NvTree`
+    ///
+    /// ```
+    /// use nv::{NvFlag, NvList, NvTree, NvValue};
+    ///
+    /// let mut tree = NvTree::new(NvFlag::None);
+    /// tree.push("the answer", NvValue::Number(42));
+    ///
+    /// let list = NvList::from_tree(&tree).unwrap();
+    ///
+    /// assert_eq!(list.get_number("the answer").unwrap(), 42);
+    /// ```
+    pub fn from_tree(tree: &NvTree) -> NvResult<NvList> {
+        let mut list = NvList::new(tree.flags())?;
+        for (name, value) in tree.iter() {
+            value.nv_add(&mut list, name);
+        }
+        match list.error() {
+            0 => Ok(list),
+            err => Err(NvErr::Errno(err)),
+        }
+    }
+
+    /// Decode a packed `nvlist` with the pure Rust decoder, enforcing the
+    /// limits in `options`, and load the result into libnv
+    ///
+    /// Descriptors in the list are indices into `fds` and are duplicated
+    /// into the returned `NvList`.
+    pub fn unpack_with(buf: &[u8], fds: &[RawFd], options: &UnpackOptions) -> NvResult<NvList> {
+        NvList::from_tree(&NvTree::unpack(buf, fds, options)?)
+    }
+
+    /// Decode a packed `nvlist` that may be compressed, as
+    /// `NvTree::unpack_any` does, and load the result into libnv
+    pub fn unpack_any(buf: &[u8], fds: &[RawFd], options: &UnpackOptions) -> NvResult<NvList> {
+        NvList::from_tree(&NvTree::unpack_any(buf, fds, options)?)
+    }
+
+    /// Receive an `nvlist` sent with libnv's `nvlist_send` from a socket,
+    /// enforcing the limits in `options`
+    pub fn recv_with<S: AsRawFd>(sock: &S, options: &UnpackOptions) -> NvResult<NvList> {
+        let (buf, fds) = msgio::recv(sock.as_raw_fd(), options)?;
+        let res = NvList::unpack_with(&buf, &fds, options);
+        msgio::close_all(&fds);
+        res
+    }
+
+    /// Stream the list to `writer` in the libnv packed format
+    ///
+    /// The list is walked twice, once to find its size and once to write
+    /// it, so the packed list is never held in memory as a whole. As with
+    /// `nvlist_pack`, lists holding descriptors can not be packed.
+    ///
+    /// ```
+    /// use nv::{NvFlag, NvList, NvTree, NvValue, UnpackOptions};
+    ///
+    /// let mut list = NvList::new(NvFlag::None).unwrap();
+    /// list.add_number("the answer", 42);
+    ///
+    /// let mut buf = Vec::new();
+    /// list.pack_into(&mut buf).unwrap();
+    ///
+    /// let tree = NvTree::unpack(&buf, &[], &UnpackOptions::default()).unwrap();
+    /// assert_eq!(tree.get("the answer"), Some(&NvValue::Number(42)));
+    /// ```
+    pub fn pack_into<W: Write>(&self, writer: W) -> io::Result<()> {
+        let list = match self.list {
+            Some(list) => list as *const nvlist,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty NvList")),
+        };
+        let mut measure = NvWriter::measure(self.flags());
+        unsafe { write_pairs(list, &mut measure)? };
+        let mut writer = NvWriter::new(writer, self.flags(), measure.written())?;
+        unsafe { write_pairs(list, &mut writer)? };
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// Pack the list and compress it with `codec`
+    ///
+    /// ```
+    /// use nv::{NvFlag, NvList, UnpackOptions};
+    /// use nv::compress;
+    ///
+    /// let mut list = NvList::new(NvFlag::None).unwrap();
+    /// list.add_number("the answer", 42);
+    ///
+    /// # #[cfg(feature = "lz4")] {
+    /// let buf = list.pack_compressed(compress::Codec::Lz4).unwrap();
+    /// assert!(compress::is_compressed(&buf));
+    ///
+    /// let list = NvList::unpack_any(&buf, &[], &UnpackOptions::default()).unwrap();
+    /// assert_eq!(list.get_number("the answer"), Some(42));
+    /// # }
+    /// ```
+    pub fn pack_compressed(&self, codec: Codec) -> io::Result<Vec<u8>> {
+        let mut packed = Vec::new();
+        self.pack_into(&mut packed)?;
+        compress::compress(&packed, codec)
+    }
+}
+
+fn flags_of(list: *const nvlist) -> NvFlag {
+    NvFlag::from_i32(unsafe { nvlist_flags(list) }).unwrap_or(NvFlag::None)
+}
+
+unsafe fn str_of<'a>(ptr: *const i8) -> io::Result<&'a str> {
+    CStr::from_ptr(ptr)
+        .to_str()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "string is not UTF-8"))
+}
+
+/// Write the pairs of `list` and everything nested in them
+unsafe fn write_pairs<W: Write>(list: *const nvlist, writer: &mut NvWriter<W>) -> io::Result<()> {
+    let mut cookie: *mut c_void = ptr::null_mut();
+    let mut ty = 0;
+    loop {
+        let name = nvlist_next(list, &mut ty, &mut cookie);
+        if name.is_null() {
+            return Ok(());
+        }
+        let name = str_of(name)?;
+        let mut len: usize = 0;
+        match ty {
+            1 => writer.add_null(name)?,
+            2 => writer.add_bool(name, cnvlist_get_bool(cookie))?,
+            3 => writer.add_number(name, cnvlist_get_number(cookie))?,
+            4 => writer.add_string(name, str_of(cnvlist_get_string(cookie))?)?,
+            5 => {
+                let nested = cnvlist_get_nvlist(cookie);
+                writer.begin_nvlist(name, flags_of(nested))?;
+                write_pairs(nested, writer)?;
+                writer.end_nvlist()?;
+            }
+            7 => {
+                let value = cnvlist_get_binary(cookie, &mut len);
+                writer.add_binary(name, slice::from_raw_parts(value, len))?;
+            }
+            8 => {
+                let value = cnvlist_get_bool_array(cookie, &mut len);
+                writer.add_bool_slice(name, slice::from_raw_parts(value, len))?;
+            }
+            9 => {
+                let value = cnvlist_get_number_array(cookie, &mut len);
+                writer.add_number_slice(name, slice::from_raw_parts(value, len))?;
+            }
+            10 => {
+                let value = cnvlist_get_string_array(cookie, &mut len);
+                let strings = slice::from_raw_parts(value, len)
+                    .iter()
+                    .map(|&item| str_of(item))
+                    .collect::<io::Result<Vec<&str>>>()?;
+                writer.add_string_slice(name, &strings)?;
+            }
+            11 => {
+                let value = cnvlist_get_nvlist_array(cookie, &mut len);
+                writer.begin_nvlist_array(name, len)?;
+                for &nested in slice::from_raw_parts(value, len) {
+                    writer.begin_element(flags_of(nested))?;
+                    write_pairs(nested, writer)?;
+                    writer.end_element()?;
+                }
+                writer.end_nvlist_array()?;
+            }
+            _ => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                          "packed lists can not carry descriptors"))
+            }
+        }
+    }
+}
+
+/// A copy of the value of the pair at `cookie`
+unsafe fn value_at(cookie: *mut c_void, ty: i32) -> NvValue {
+    let mut len: usize = 0;
+    match ty {
+        2 => NvValue::Bool(cnvlist_get_bool(cookie)),
+        3 => NvValue::Number(cnvlist_get_number(cookie)),
+        4 => NvValue::String(string_of(cnvlist_get_string(cookie))),
+        5 => NvValue::NvList(tree_of(cnvlist_get_nvlist(cookie))),
+        6 => NvValue::Descriptor(cnvlist_get_descriptor(cookie)),
+        7 => {
+            let value = cnvlist_get_binary(cookie, &mut len);
+            NvValue::Binary(slice::from_raw_parts(value, len).to_vec())
+        }
+        8 => {
+            let value = cnvlist_get_bool_array(cookie, &mut len);
+            NvValue::BoolArray(slice::from_raw_parts(value, len).to_vec())
+        }
+        9 => {
+            let value = cnvlist_get_number_array(cookie, &mut len);
+            NvValue::NumberArray(slice::from_raw_parts(value, len).to_vec())
+        }
+        10 => {
+            let value = cnvlist_get_string_array(cookie, &mut len);
+            NvValue::StringArray(slice::from_raw_parts(value, len)
+                                     .iter()
+                                     .map(|&item| string_of(item))
+                                     .collect())
+        }
+        11 => {
+            let value = cnvlist_get_nvlist_array(cookie, &mut len);
+            NvValue::NvListArray(slice::from_raw_parts(value, len)
+                                     .iter()
+                                     .map(|&item| tree_of(item))
+                                     .collect())
+        }
+        12 => {
+            let value = cnvlist_get_descriptor_array(cookie, &mut len);
+            NvValue::DescriptorArray(slice::from_raw_parts(value, len).to_vec())
+        }
+        _ => NvValue::Null,
+    }
+}
+
+unsafe fn string_of(ptr: *const i8) -> String {
+    CStr::from_ptr(ptr).to_string_lossy().into_owned()
+}
+
+/// A copy of `list` and everything nested in it
+///
+/// Like libnv does, nested lists are entered straight from the pair
+/// holding them, and left by following `nvlist_get_array_next` to the
+/// next list of an array, or else `nvlist_get_parent` back to the pair
+/// in the parent, so only the copies are kept on a stack.
+unsafe fn tree_of(top: *const nvlist) -> NvTree {
+    // The copies of the lists being walked, innermost last, along with
+    // the name of their pair, which is null for the lists of an array
+    let mut copies = vec![(ptr::null(), NvTree::new(flags_of(top)))];
+    let mut list = top;
+    let mut cookie: *mut c_void = ptr::null_mut();
+    let mut ty = 0;
+    loop {
+        let name = nvlist_next(list, &mut ty, &mut cookie);
+        if !name.is_null() {
+            let nested = match ty {
+                5 => cnvlist_get_nvlist(cookie),
+                11 => {
+                    let mut len: usize = 0;
+                    let lists = cnvlist_get_nvlist_array(cookie, &mut len);
+                    let copy = NvValue::NvListArray(Vec::with_capacity(len));
+                    innermost(&mut copies).push(&string_of(name), copy);
+                    if len == 0 {
+                        continue;
+                    }
+                    *lists
+                }
+                _ => {
+                    innermost(&mut copies).push(&string_of(name), value_at(cookie, ty));
+                    continue;
+                }
+            };
+            let name = if ty == 5 { name } else { ptr::null() };
+            copies.push((name, NvTree::new(flags_of(nested))));
+            list = nested;
+            cookie = ptr::null_mut();
+            continue;
+        }
+        // `list` is done, so hand its copy over
+        let (name, copy) = copies.pop().expect("the copy of the top list is left");
+        if copies.is_empty() {
+            return copy;
+        }
+        let parent = innermost(&mut copies);
+        if name.is_null() {
+            match parent.pairs.last_mut() {
+                Some(&mut (_, NvValue::NvListArray(ref mut lists))) => lists.push(copy),
+                _ => unreachable!("the lists of an array follow its pair"),
+            }
+        } else {
+            parent.push(&string_of(name), NvValue::NvList(copy));
+        }
+        let next = nvlist_get_array_next(list);
+        if next.is_null() {
+            list = nvlist_get_parent(list, &mut cookie);
+        } else {
+            copies.push((ptr::null(), NvTree::new(flags_of(next))));
+            list = next;
+            cookie = ptr::null_mut();
+        }
+    }
+}
+
+fn innermost(copies: &mut [(*const i8, NvTree)]) -> &mut NvTree {
+    &mut copies.last_mut().expect("the copy of the top list is left").1
+}
+
+/// Turn a list taken out of its parent into a tree, handing the
+/// descriptors in it over to the tree rather than closing them
+unsafe fn take_tree(list: *mut nvlist) -> NvTree {
+    let tree = tree_of(list);
+    release_descriptors(list);
+    nvlist_destroy(list);
+    tree
+}
+
+/// Remove the descriptors of `list` and the lists nested in it without
+/// closing them
+unsafe fn release_descriptors(list: *mut nvlist) {
+    let mut cookie: *mut c_void = ptr::null_mut();
+    let mut prev = cookie;
+    let mut ty = 0;
+    let mut len: usize = 0;
+    while !nvlist_next(list, &mut ty, &mut cookie).is_null() {
+        match ty {
+            5 => release_descriptors(cnvlist_get_nvlist(cookie) as *mut nvlist),
+            6 => {
+                cnvlist_take_descriptor(cookie);
+                cookie = prev;
+                continue;
+            }
+            11 => {
+                let value = cnvlist_get_nvlist_array(cookie, &mut len);
+                for &item in slice::from_raw_parts(value, len) {
+                    release_descriptors(item as *mut nvlist);
+                }
+            }
+            12 => {
+                libc::free(cnvlist_take_descriptor_array(cookie, &mut len) as *mut c_void);
+                cookie = prev;
+                continue;
+            }
+            _ => {}
+        }
+        prev = cookie;
+    }
+}
+
+/// The libnv backend of `NvCursor`, walking the list with an iteration
+/// cookie
+struct ListCursor<'a> {
+    list: *mut nvlist,
+    /// The current pair, or the one after which to continue
+    cookie: *mut c_void,
+    /// The pair before the current one
+    prev: *mut c_void,
+    name: *const i8,
+    ty: i32,
+    current: bool,
+    /// How many pairs are left to visit once pairs have been appended
+    remaining: Option<usize>,
+    marker: PhantomData<&'a mut NvList>,
+}
+
+impl<'a> ListCursor<'a> {
+    /// Forget the current pair after freeing it
+    fn forget(&mut self) {
+        self.cookie = self.prev;
+        self.current = false;
+    }
+}
+
+impl<'a> Backend for ListCursor<'a> {
+    fn next(&mut self) -> bool {
+        if self.list.is_null() || self.remaining == Some(0) {
+            self.current = false;
+            return false;
+        }
+        let mut cookie = self.cookie;
+        let name = unsafe { nvlist_next(self.list, &mut self.ty, &mut cookie) };
+        if name.is_null() {
+            self.current = false;
+            return false;
+        }
+        self.remaining = self.remaining.map(|remaining| remaining - 1);
+        self.prev = self.cookie;
+        self.cookie = cookie;
+        self.name = name;
+        self.current = true;
+        true
+    }
+
+    fn name(&self) -> Option<&str> {
+        if !self.current {
+            return None;
+        }
+        unsafe { CStr::from_ptr(self.name) }.to_str().ok()
+    }
+
+    fn ty(&self) -> Option<NvType> {
+        if !self.current {
+            return None;
+        }
+        NV_TYPES.get((self.ty - 1) as usize).cloned()
+    }
+
+    fn get(&self) -> Option<NvValue> {
+        if !self.current {
+            return None;
+        }
+        Some(unsafe { value_at(self.cookie, self.ty) })
+    }
+
+    fn take(&mut self) -> Option<NvValue> {
+        if !self.current {
+            return None;
+        }
+        let value = unsafe {
+            match self.ty {
+                5 => NvValue::NvList(take_tree(cnvlist_take_nvlist(self.cookie))),
+                6 => NvValue::Descriptor(cnvlist_take_descriptor(self.cookie)),
+                11 => {
+                    let mut len: usize = 0;
+                    let value = cnvlist_take_nvlist_array(self.cookie, &mut len);
+                    let trees = slice::from_raw_parts(value, len)
+                        .iter()
+                        .map(|&item| take_tree(item))
+                        .collect();
+                    libc::free(value as *mut c_void);
+                    NvValue::NvListArray(trees)
+                }
+                12 => {
+                    let mut len: usize = 0;
+                    let value = cnvlist_take_descriptor_array(self.cookie, &mut len);
+                    let fds = slice::from_raw_parts(value, len).to_vec();
+                    libc::free(value as *mut c_void);
+                    NvValue::DescriptorArray(fds)
+                }
+                _ => {
+                    let value = value_at(self.cookie, self.ty);
+                    self.remove();
+                    value
+                }
+            }
+        };
+        self.forget();
+        Some(value)
+    }
+
+    fn remove(&mut self) -> bool {
+        if !self.current {
+            return false;
+        }
+        let cookie = self.cookie;
+        unsafe {
+            match self.ty {
+                // There is no `cnvlist_free_null`, and freeing by name would
+                // hit the first of several pairs with `NoUnique`
+                1 => nvlist_free_nvpair(self.list, cookie),
+                2 => cnvlist_free_bool(cookie),
+                3 => cnvlist_free_number(cookie),
+                4 => cnvlist_free_string(cookie),
+                5 => cnvlist_free_nvlist(cookie),
+                6 => cnvlist_free_descriptor(cookie),
+                7 => cnvlist_free_binary(cookie),
+                8 => cnvlist_free_bool_array(cookie),
+                9 => cnvlist_free_number_array(cookie),
+                10 => cnvlist_free_string_array(cookie),
+                11 => cnvlist_free_nvlist_array(cookie),
+                _ => cnvlist_free_descriptor_array(cookie),
+            }
+        }
+        self.forget();
+        true
+    }
+
+    fn append(&mut self, name: &str, value: NvValue) {
+        if self.list.is_null() {
+            return;
+        }
+        if self.remaining.is_none() {
+            let mut remaining = 0;
+            let mut cookie = self.cookie;
+            let mut ty = 0;
+            while !unsafe { nvlist_next(self.list, &mut ty, &mut cookie) }.is_null() {
+                remaining += 1;
+            }
+            self.remaining = Some(remaining);
+        }
+        // Borrow the list without destroying it afterwards
+        let mut list = ManuallyDrop::new(NvList { list: Some(self.list) });
+        value.nv_add(&mut list, name);
+    }
+
+    fn descend<'b>(&'b mut self) -> Option<NvCursor<'b>> {
+        if !self.current || self.ty != 5 {
+            return None;
+        }
+        Some(NvCursor::new(ListCursor {
+            list: unsafe { cnvlist_get_nvlist(self.cookie) } as *mut nvlist,
+            cookie: ptr::null_mut(),
+            prev: ptr::null_mut(),
+            name: ptr::null(),
+            ty: 0,
+            current: false,
+            remaining: None,
+            marker: PhantomData,
+        }))
+    }
+}
+
+/// A `fmt::Write` adapter building a string value of an `NvList`,
+/// returned by `NvList::string_writer`
+///
+/// Writing a string holding a NUL byte fails, as libnv strings can not
+/// hold one.
+pub struct NvStringWriter<'a> {
+    list: &'a mut NvList,
+    name: CString,
+    /// `malloc`ed, so libnv can take it over and `free` it
+    buf: *mut c_char,
+    len: usize,
+    cap: usize,
+}
+
+impl<'a> NvStringWriter<'a> {
+    /// Add the string written so far to the list
+    pub fn finish(mut self) {
+        // Make room for the terminating NUL, even for an empty string
+        if self.reserve(1).is_err() {
+            let _ = self.list.set_error(libc::ENOMEM);
+            return;
+        }
+        unsafe {
+            *self.buf.add(self.len) = 0;
+            match self.list.list {
+                // libnv takes ownership of the buffer, even on failure
+                Some(list) => nvlist_move_string(list, self.name.as_ptr(), self.buf),
+                None => libc::free(self.buf as *mut c_void),
+            }
+        }
+        self.buf = ptr::null_mut();
+    }
+
+    fn reserve(&mut self, additional: usize) -> fmt::Result {
+        let needed = self.len.checked_add(additional).ok_or(fmt::Error)?;
+        if needed <= self.cap {
+            return Ok(());
+        }
+        let cap = cmp::max(needed, cmp::max(2 * self.cap, 64));
+        let buf = unsafe { libc::realloc(self.buf as *mut c_void, cap) as *mut c_char };
+        if buf.is_null() {
+            return Err(fmt::Error);
+        }
+        self.buf = buf;
+        self.cap = cap;
+        Ok(())
+    }
+}
+
+impl<'a> fmt::Write for NvStringWriter<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if s.contains('\0') {
+            return Err(fmt::Error);
+        }
+        // Keep room for the terminating NUL
+        self.reserve(s.len() + 1)?;
+        unsafe {
+            ptr::copy_nonoverlapping(s.as_ptr() as *const c_char, self.buf.add(self.len), s.len());
+        }
+        self.len += s.len();
+        Ok(())
+    }
+}
+
+impl<'a> Drop for NvStringWriter<'a> {
+    fn drop(&mut self) {
+        if !self.buf.is_null() {
+            unsafe { libc::free(self.buf as *mut c_void) }
+        }
+    }
+}
+
+/// Add a formatted string to an `NvList`, as `NvList::add_fmt` does
+///
+/// ```
+/// #[macro_use]
+/// extern crate nv;
+///
+/// use nv::{NvList, NvFlag};
+///
+/// # fn main() {
+/// let mut list = NvList::new(NvFlag::None).unwrap();
+/// nv_add_fmt!(list, "path", "/dev/{}{}", "ada", 0);
+///
+/// assert_eq!(list.get_str("path"), Some("/dev/ada0"));
+/// # }
+/// ```
+#[macro_export]
+macro_rules! nv_add_fmt {
+    ($list:expr, $name:expr, $($arg:tt)*) => {
+        $list.add_fmt($name, format_args!($($arg)*))
+    };
+}
+
+/// Adding pairs under a name that is already a C string, so `NvEntry` only
+/// converts its name once
+impl NvList {
+    pub(crate) fn add_null_c(&mut self, name: &CStr) {
+        if let Some(list) = self.list {
+            unsafe {
+                nvlist_add_null(list, name.as_ptr());
+            }
+        }
+    }
+
+    pub(crate) fn add_bool_c(&mut self, name: &CStr, value: bool) {
+        if let Some(list) = self.list {
+            unsafe {
+                nvlist_add_bool(list, name.as_ptr(), value);
+            }
+        }
+    }
+
+    pub(crate) fn add_number_c(&mut self, name: &CStr, value: u64) {
+        if let Some(list) = self.list {
+            unsafe {
+                nvlist_add_number(list, name.as_ptr(), value);
+            }
+        }
+    }
+
+    pub(crate) fn add_string_c(&mut self, name: &CStr, value: &str) {
+        if let Some(list) = self.list {
+            let c_value = CString::new(value).expect("Could not decode string");
+            unsafe { nvlist_add_string(list, name.as_ptr(), c_value.as_ptr()) }
+        }
+    }
+
+    pub(crate) fn add_nvlist_c(&mut self, name: &CStr, value: &NvList) {
+        match (self.list, value.list) {
+            // Both are valid
+            (Some(this), Some(other)) if !other.is_null() => unsafe {
+                nvlist_add_nvlist(this, name.as_ptr(), other)
+            },
+            // This is valid, but the other is not
+            (Some(this), _) => unsafe {
+                nvlist_add_nvlist(this, name.as_ptr(), nvlist_create(self.flags() as i32))
+            },
+            // Something bad happened... nop
+            _ => {}
+        }
+    }
+
+    pub(crate) unsafe fn add_binary_c(&mut self, name: &CStr, value: *mut i8, size: usize) {
+        if let Some(list) = self.list {
+            nvlist_add_binary(list, name.as_ptr(), value, size);
+        }
+    }
+
+    pub(crate) fn add_bool_slice_c(&mut self, name: &CStr, value: &[bool]) {
+        if let Some(list) = self.list {
+            unsafe {
+                nvlist_add_bool_array(list, name.as_ptr(), value.as_ptr(), value.len());
+            }
+        }
+    }
+
+    pub(crate) fn add_number_slice_c(&mut self, name: &CStr, value: &[u64]) {
+        if let Some(list) = self.list {
+            unsafe {
+                nvlist_add_number_array(list, name.as_ptr(), value.as_ptr(), value.len());
+            }
+        }
+    }
+
+    pub(crate) fn add_string_slice_c(&mut self, name: &CStr, value: &[&str]) {
+        if let Some(list) = self.list {
+            let c_values: Vec<CString> = value.iter()
+                .map(|item| CString::new(*item).expect("Could not decode string"))
+                .collect();
+            unsafe {
+                let tmp: Vec<*const i8> = c_values.iter().map(|item| item.as_ptr()).collect();
+                nvlist_add_string_array(list,
+                                        name.as_ptr(),
+                                        tmp.as_slice().as_ptr(),
+                                        value.len());
+            }
+        }
+    }
+
+    pub(crate) fn add_nvlist_slice_c(&mut self, name: &CStr, value: &[NvList]) {
+        if let Some(list) = self.list {
+            unsafe {
+                let tmp: Vec<*const nvlist> = value.iter()
+                    .filter(|item| matches!(item.list, Some(item) if !item.is_null()))
+                    .map(|item| item.list.unwrap() as *const nvlist)
+                    .collect();
+                nvlist_add_nvlist_array(list, name.as_ptr(), tmp.as_slice().as_ptr(), tmp.len());
+            }
+        }
+    }
+
+    pub(crate) fn add_descriptor_c(&mut self, name: &CStr, fd: RawFd) {
+        if let Some(list) = self.list {
+            unsafe {
+                nvlist_add_descriptor(list, name.as_ptr(), fd);
+            }
+        }
+    }
+
+    pub(crate) fn add_descriptor_slice_c(&mut self, name: &CStr, value: &[RawFd]) {
+        if let Some(list) = self.list {
+            unsafe {
+                nvlist_add_descriptor_array(list, name.as_ptr(), value.as_ptr(), value.len());
+            }
+        }
+    }
+}
+
+fn c_name(name: &str) -> CString {
+    CString::new(name).expect("Could not decode string")
+}
+
+/// A pair of an `NvList` that may or may not exist, returned by
+/// `NvList::entry`
+///
+/// Unlike a `HashMap` entry, the pair may have any `NvType`. Replacing
+/// and removing the pair affects every pair of the name, whatever its
+/// type, so lists created with `NvFlag::NoUnique` end up with only the
+/// new one.
+///
+/// The pair is looked up once, when the entry is created, and its name
+/// is converted to a C string once for the lookup and any insertion.
+pub struct NvEntry<'a, 'b> {
+    list: &'a mut NvList,
+    name: &'b str,
+    c_name: CString,
+    /// The first pair of the name, or null if there is none
+    pair: *mut c_void,
+    /// The type of `pair`
+    ty: i32,
+}
+
+impl<'a, 'b> NvEntry<'a, 'b> {
+    fn new(list: &'a mut NvList, name: &'b str) -> NvEntry<'a, 'b> {
+        let mut entry = NvEntry {
+            c_name: c_name(name),
+            list,
+            name,
+            pair: ptr::null_mut(),
+            ty: 0,
+        };
+        entry.find(ptr::null_mut());
+        entry
+    }
+
+    /// The name of the pair
+    pub fn name(&self) -> &'b str {
+        self.name
+    }
+
+    /// Determines if a pair of the name exists
+    pub fn exists(&self) -> bool {
+        !self.pair.is_null()
+    }
+
+    /// The type of the existing pair, if there is one
+    pub fn ty(&self) -> Option<NvType> {
+        if self.pair.is_null() {
+            return None;
+        }
+        NV_TYPES.get((self.ty - 1) as usize).cloned()
+    }
+
+    /// Add `value`, replacing any existing pair of the name
+    pub fn insert<T: NvListOps>(mut self, value: T) -> &'a mut NvList {
+        self.clear();
+        value.nv_add_c(self.list, self.name, &self.c_name);
+        self.list
+    }
+
+    /// Add `default` if there is no pair of the name
+    pub fn or_insert<T: NvListOps>(self, default: T) -> &'a mut NvList {
+        self.or_insert_with(|| default)
+    }
+
+    /// Add the value returned by `default` if there is no pair of the name
+    pub fn or_insert_with<T, F>(self, default: F) -> &'a mut NvList
+        where T: NvListOps,
+              F: FnOnce() -> T
+    {
+        if !self.exists() {
+            default().nv_add_c(self.list, self.name, &self.c_name);
+        }
+        self.list
+    }
+
+    /// Replace the existing value with the one `f` returns, if it can be
+    /// read as a `T`
+    pub fn and_modify<T, F>(mut self, f: F) -> NvEntry<'a, 'b>
+        where T: NvListOps + for<'c> FromNvValue<'c>,
+              F: FnOnce(T) -> T
+    {
+        if self.exists() {
+            if let Ok(value) = T::from_nv(self.list, self.name) {
+                let value = f(value);
+                self.clear();
+                value.nv_add_c(self.list, self.name, &self.c_name);
+                // The new pair was appended, after any other
+                self.find(ptr::null_mut());
+            }
+        }
+        self
+    }
+
+    /// Remove every pair of the name, returning whether there was one
+    pub fn remove(mut self) -> bool {
+        let existed = self.exists();
+        self.clear();
+        existed
+    }
+
+    /// Point the entry at the first pair of the name after `cookie`, or
+    /// from the start of the list if it is null
+    fn find(&mut self, mut cookie: *mut c_void) {
+        self.pair = ptr::null_mut();
+        let list = match self.list.list {
+            Some(list) => list,
+            None => return,
+        };
+        let flags = self.list.flags();
+        let ignore_case = flags == NvFlag::IgnoreCase || flags == NvFlag::All;
+        let name = self.c_name.as_bytes();
+        loop {
+            let pair_name = unsafe { nvlist_next(list, &mut self.ty, &mut cookie) };
+            if pair_name.is_null() {
+                return;
+            }
+            let pair_name = unsafe { CStr::from_ptr(pair_name) }.to_bytes();
+            if pair_name == name || (ignore_case && pair_name.eq_ignore_ascii_case(name)) {
+                self.pair = cookie;
+                return;
+            }
+        }
+    }
+
+    /// Free every pair of the name, in a single pass from the first
+    fn clear(&mut self) {
+        let list = match self.list.list {
+            Some(list) => list,
+            None => return,
+        };
+        let flags = self.list.flags();
+        let no_unique = flags == NvFlag::NoUnique || flags == NvFlag::All;
+        while !self.pair.is_null() {
+            let pair = self.pair;
+            if no_unique {
+                self.find(pair);
+            } else {
+                self.pair = ptr::null_mut();
+            }
+            unsafe { nvlist_free_nvpair(list, pair) }
+        }
+    }
+}
+
+const NV_TYPES: [NvType; 12] = [NvType::Null,
+                                NvType::Bool,
+                                NvType::Number,
+                                NvType::String,
+                                NvType::NvList,
+                                NvType::Descriptor,
+                                NvType::Binary,
+                                NvType::BoolArray,
+                                NvType::NumberArray,
+                                NvType::StringArray,
+                                NvType::NvListArray,
+                                NvType::DescriptorArray];
+
+/// An immutable `NvList` that is cheap to clone and share between threads
+///
+/// Clones share the same `nvlist`, which is destroyed along with the
+/// last of them. It dereferences to `NvList` for reading.
+///
+/// ```
+/// use nv::{NvFlag, NvList};
+/// use std::thread;
+///
+/// let mut list = NvList::new(NvFlag::None).unwrap();
+/// list.add_number("the answer", 42);
+/// let frozen = list.freeze();
+///
+/// let workers: Vec<_> = (0..4).map(|_| {
+///     let frozen = frozen.clone();
+///     thread::spawn(move || frozen.get_number("the answer"))
+/// }).collect();
+/// for worker in workers {
+///     assert_eq!(worker.join().unwrap(), Some(42));
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct FrozenNvList {
+    list: Arc<NvList>,
+}
+
+impl FrozenNvList {
+    /// Get a list that can be modified again, which is only a copy if
+    /// the list is still shared
+    pub fn thaw(self) -> NvList {
+        Arc::try_unwrap(self.list).unwrap_or_else(|list| (*list).clone())
+    }
+}
+
+impl Deref for FrozenNvList {
+    type Target = NvList;
+
+    fn deref(&self) -> &NvList {
+        &self.list
+    }
+}
+
+impl From<NvList> for FrozenNvList {
+    fn from(list: NvList) -> FrozenNvList {
+        list.freeze()
+    }
+}
+
+impl Clone for NvList {
+    fn clone(&self) -> NvList {
+        NvList { list: self.list.map(|list| unsafe { nvlist_clone(list) }) }
     }
 }
 
@@ -682,7 +1893,7 @@ impl Drop for NvList {
     }
 }
 
-#[link(name="nv")]
+#[cfg_attr(not(feature = "vendored"), link(name="nv"))]
 extern "C" {
     fn nvlist_create(flags: i32) -> *mut nvlist;
     fn nvlist_destroy(list: *mut nvlist) -> ();
@@ -699,7 +1910,8 @@ extern "C" {
     fn nvlist_add_number(list: *mut nvlist, name: *const i8, value: u64) -> ();
     fn nvlist_add_string(list: *mut nvlist, name: *const i8, value: *const i8) -> ();
     fn nvlist_add_nvlist(list: *mut nvlist, name: *const i8, value: *const nvlist) -> ();
-    fn nvlist_add_binary(list: *mut nvlist, name: *const i8, value: *mut i8, size: u32) -> ();
+    fn nvlist_move_string(list: *mut nvlist, name: *const i8, value: *mut c_char) -> ();
+    fn nvlist_add_binary(list: *mut nvlist, name: *const i8, value: *mut i8, size: usize) -> ();
     fn nvlist_add_bool_array(list: *mut nvlist,
                              name: *const i8,
                              value: *const bool,
@@ -720,12 +1932,19 @@ extern "C" {
                                value: *const *const nvlist,
                                size: usize)
                                -> ();
+    fn nvlist_add_descriptor(list: *mut nvlist, name: *const i8, value: i32) -> ();
+    fn nvlist_add_descriptor_array(list: *mut nvlist,
+                                   name: *const i8,
+                                   value: *const i32,
+                                   size: usize)
+                                   -> ();
     fn nvlist_exists(list: *const nvlist, name: *const i8) -> bool;
     fn nvlist_exists_type(list: *const nvlist, name: *const i8, ty: i32) -> bool;
     fn nvlist_exists_bool(list: *const nvlist, name: *const i8) -> bool;
     fn nvlist_exists_number(list: *const nvlist, name: *const i8) -> bool;
     fn nvlist_exists_string(list: *const nvlist, name: *const i8) -> bool;
     fn nvlist_exists_nvlist(list: *const nvlist, name: *const i8) -> bool;
+    fn nvlist_exists_binary(list: *const nvlist, name: *const i8) -> bool;
     fn nvlist_exists_bool_array(list: *const nvlist, name: *const i8) -> bool;
     fn nvlist_exists_number_array(list: *const nvlist, name: *const i8) -> bool;
     fn nvlist_exists_string_array(list: *const nvlist, name: *const i8) -> bool;
@@ -734,6 +1953,7 @@ extern "C" {
     fn nvlist_get_number(list: *const nvlist, name: *const i8) -> u64;
     fn nvlist_get_string(list: *const nvlist, name: *const i8) -> *const i8;
     fn nvlist_get_nvlist(list: *const nvlist, name: *const i8) -> *const nvlist;
+    fn nvlist_get_binary(list: *const nvlist, name: *const i8, size: *mut usize) -> *const u8;
     fn nvlist_get_bool_array(list: *const nvlist, name: *const i8, len: *const usize) -> *mut bool;
     fn nvlist_get_number_array(list: *const nvlist,
                                name: *const i8,
@@ -747,7 +1967,41 @@ extern "C" {
                                name: *const i8,
                                len: *const usize)
                                -> *const *const nvlist;
+    fn nvlist_get_descriptor(list: *const nvlist, name: *const i8) -> i32;
+    fn nvlist_get_descriptor_array(list: *const nvlist,
+                                   name: *const i8,
+                                   len: *mut usize)
+                                   -> *const i32;
     fn nvlist_free(list: *mut nvlist, name: *const i8) -> ();
     fn nvlist_free_type(list: *mut nvlist, name: *const i8, ty: i32) -> ();
-    fn strlen(target: *const i8) -> usize;
+    fn nvlist_free_nvpair(list: *mut nvlist, pair: *mut c_void) -> ();
+    fn nvlist_next(list: *const nvlist, ty: *mut i32, cookie: *mut *mut c_void) -> *const i8;
+    fn nvlist_get_parent(list: *const nvlist, cookie: *mut *mut c_void) -> *const nvlist;
+    fn nvlist_get_array_next(list: *const nvlist) -> *const nvlist;
+    fn cnvlist_get_bool(cookie: *mut c_void) -> bool;
+    fn cnvlist_get_number(cookie: *mut c_void) -> u64;
+    fn cnvlist_get_string(cookie: *mut c_void) -> *const i8;
+    fn cnvlist_get_nvlist(cookie: *mut c_void) -> *const nvlist;
+    fn cnvlist_get_binary(cookie: *mut c_void, size: *mut usize) -> *const u8;
+    fn cnvlist_get_bool_array(cookie: *mut c_void, len: *mut usize) -> *const bool;
+    fn cnvlist_get_number_array(cookie: *mut c_void, len: *mut usize) -> *const u64;
+    fn cnvlist_get_string_array(cookie: *mut c_void, len: *mut usize) -> *const *const i8;
+    fn cnvlist_get_nvlist_array(cookie: *mut c_void, len: *mut usize) -> *const *const nvlist;
+    fn cnvlist_get_descriptor(cookie: *mut c_void) -> i32;
+    fn cnvlist_get_descriptor_array(cookie: *mut c_void, len: *mut usize) -> *const i32;
+    fn cnvlist_take_nvlist(cookie: *mut c_void) -> *mut nvlist;
+    fn cnvlist_take_nvlist_array(cookie: *mut c_void, len: *mut usize) -> *mut *mut nvlist;
+    fn cnvlist_take_descriptor(cookie: *mut c_void) -> i32;
+    fn cnvlist_take_descriptor_array(cookie: *mut c_void, len: *mut usize) -> *mut i32;
+    fn cnvlist_free_bool(cookie: *mut c_void) -> ();
+    fn cnvlist_free_number(cookie: *mut c_void) -> ();
+    fn cnvlist_free_string(cookie: *mut c_void) -> ();
+    fn cnvlist_free_nvlist(cookie: *mut c_void) -> ();
+    fn cnvlist_free_descriptor(cookie: *mut c_void) -> ();
+    fn cnvlist_free_binary(cookie: *mut c_void) -> ();
+    fn cnvlist_free_bool_array(cookie: *mut c_void) -> ();
+    fn cnvlist_free_number_array(cookie: *mut c_void) -> ();
+    fn cnvlist_free_string_array(cookie: *mut c_void) -> ();
+    fn cnvlist_free_nvlist_array(cookie: *mut c_void) -> ();
+    fn cnvlist_free_descriptor_array(cookie: *mut c_void) -> ();
 }
@@ -2,59 +2,573 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use common::{NvErr, NvFlag, NvResult, NvType};
 use nvlist::NvList;
+use value::{NvTree, NvValue};
+use libc;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::ffi::{CStr, OsStr, OsString};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{BorrowedFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 macro_rules! impl_nv_list_add {
-    ($self_:ident, $name:ident, $list:ident.$method:ident, true) => {
+    ($self_:ident, $name:expr, $list:ident.$method:ident, true) => {
         $list.$method($name, $self_);
     };
-    ($self_:ident, $name:ident, $list:ident.$method:ident, false) => {
+    ($self_:ident, $name:expr, $list:ident.$method:ident, false) => {
         $list.$method($name, *$self_);
     }
 }
 
 macro_rules! impl_nv_list_ops {
-    ($type_:ty, $add_method:ident, $get_method:ident) => {
-        impl_nv_list_ops!{$type_, $add_method, $get_method, $type_}
+    ($type_:ty, $add_method:ident, $add_c_method:ident) => {
+        impl_nv_list_ops!{$type_, $add_method, $add_c_method, false}
     };
-    ($type_:ty, $add_method:ident, $get_method:ident, $ret_type:ty) => {
-        impl_nv_list_ops!{$type_, $add_method, $get_method, $ret_type, false}
-    };
-    ($type_:ty, $add_method:ident, $get_method:ident, $ret_type:ty, $deref:ident) => {
+    ($type_:ty, $add_method:ident, $add_c_method:ident, $deref:ident) => {
         impl NvListOps for $type_ {
             /// Add a `$type_` value to the `NvList`
-            fn nv_add(&self, list: &mut NvList, name: &str) -> () {
+            fn nv_add(&self, list: &mut NvList, name: &str) {
                 impl_nv_list_add!{self, name, list.$add_method, $deref}
             }
+
+            fn nv_add_c(&self, list: &mut NvList, _: &str, c_name: &CStr) {
+                impl_nv_list_add!{self, c_name, list.$add_c_method, $deref}
+            }
         }
     };
 }
 
 /// Trait implemented for types that are allowed to be
 /// used with an `NvList`
+///
+/// Besides the types libnv stores directly, integers are added as
+/// numbers, byte slices as binary data, maps as nested lists, paths as
+/// strings, addresses as strings in their `Display` form, and durations
+/// and times as nanoseconds since the Unix epoch.
 // I really don't like this trait, but in the meantime it
 // provides the simplest and most generic interface for adding
 // values to `NvList`s
 pub trait NvListOps {
     /// Add the value to the `NvList`
     fn nv_add(&self, nvlist: &mut NvList, name: &str) -> ();
+
+    /// Add the value under a name that is already a C string, which the
+    /// types libnv stores directly use to skip converting it again
+    #[doc(hidden)]
+    fn nv_add_c(&self, nvlist: &mut NvList, name: &str, _: &CStr) {
+        self.nv_add(nvlist, name)
+    }
+}
+
+/// Types that can be read out of an `NvList` with `NvList::get`, the
+/// counterpart of `NvListOps`
+///
+/// Borrowed types, such as `&str`, `&[u64]` and `BorrowedFd`, point into
+/// the list, while owned ones are copies. Reading a missing value fails
+/// with `NvErr::NotFound`, except for `Option`s.
+///
+/// libnv only stores `u64` numbers, so every integer type is stored as
+/// one. Signed integers are stored in two's complement, sign extended to
+/// 64 bits, so `-1i32` is stored as `u64::MAX`. Reading a number that
+/// does not fit the requested type fails with `NvErr::OutOfRange`.
+/// Fieldless enums are stored as their discriminant once declared with
+/// `nv_enum!`.
+pub trait FromNvValue<'a>: Sized {
+    /// Read the value called `name` from `list`
+    fn from_nv(list: &'a NvList, name: &str) -> NvResult<Self>;
 }
 
-impl_nv_list_ops!{bool, add_bool, get_bool}
+impl_nv_list_ops!{bool, add_bool, add_bool_c}
+
+impl_nv_list_ops!{u64, add_number, add_number_c}
+
+macro_rules! impl_nv_list_int {
+    ($($type_:ty),*; $wide:ty) => {
+        $(
+            impl NvListOps for $type_ {
+                /// Add the integer to the `NvList` as a number
+                fn nv_add(&self, list: &mut NvList, name: &str) {
+                    list.add_number(name, *self as $wide as u64);
+                }
+
+                fn nv_add_c(&self, list: &mut NvList, _: &str, c_name: &CStr) {
+                    list.add_number_c(c_name, *self as $wide as u64);
+                }
+            }
+
+            impl<'a> FromNvValue<'a> for $type_ {
+                fn from_nv(list: &'a NvList, name: &str) -> NvResult<$type_> {
+                    let number = list.get_number(name).ok_or(NvErr::NotFound)?;
+                    <$type_>::try_from(number as $wide).map_err(|_| NvErr::OutOfRange(number))
+                }
+            }
+        )*
+    };
+}
+
+impl_nv_list_int!{u8, u16, u32, usize; u64}
+
+impl_nv_list_int!{i8, i16, i32, i64, isize; i64}
+
+macro_rules! impl_nv_list_int128 {
+    ($($type_:ty),*; $wide:ty) => {
+        $(
+            /// 128-bit integers are added as numbers, setting the list's
+            /// error to `EOVERFLOW` if they do not fit 64 bits
+            impl NvListOps for $type_ {
+                fn nv_add(&self, list: &mut NvList, name: &str) {
+                    match <$wide>::try_from(*self) {
+                        Ok(number) => list.add_number(name, number as u64),
+                        Err(_) => {
+                            let _ = list.set_error(libc::EOVERFLOW);
+                        }
+                    }
+                }
+            }
+
+            impl<'a> FromNvValue<'a> for $type_ {
+                fn from_nv(list: &'a NvList, name: &str) -> NvResult<$type_> {
+                    let number = list.get_number(name).ok_or(NvErr::NotFound)?;
+                    Ok(<$type_>::from(number as $wide))
+                }
+            }
+        )*
+    };
+}
+
+impl_nv_list_int128!{u128; u64}
+
+impl_nv_list_int128!{i128; i64}
+
+/// Declare a fieldless enum that can be added to and read from an
+/// `NvList` as a number
+///
+/// Each variant is stored as its discriminant, in the same form as the
+/// integer types. Reading a number that matches no variant fails with
+/// `NvErr::OutOfRange`.
+///
+/// ```
+/// #[macro_use]
+/// extern crate nv;
+///
+/// use nv::{NvList, NvFlag};
+///
+/// nv_enum! {
+///     #[derive(Debug, PartialEq)]
+///     pub enum State {
+///         Idle = 1,
+///         Busy = 2,
+///     }
+/// }
+///
+/// # fn main() {
+/// let mut list = NvList::new(NvFlag::None).unwrap();
+/// list.add("state", State::Busy);
+///
+/// assert_eq!(list.get_number("state"), Some(2));
+/// assert_eq!(list.get::<State>("state").unwrap(), State::Busy);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! nv_enum {
+    ($(#[$meta:meta])* $vis:vis enum $name:ident {
+        $($(#[$variant_meta:meta])* $variant:ident $(= $value:expr)*),* $(,)*
+    }) => {
+        $(#[$meta])*
+        $vis enum $name {
+            $($(#[$variant_meta])* $variant $(= $value)*),*
+        }
+
+        impl $crate::NvListOps for $name {
+            fn nv_add(&self, list: &mut $crate::NvList, name: &str) {
+                let number = match *self {
+                    $($name::$variant => $name::$variant as i64 as u64),*
+                };
+                list.add_number(name, number)
+            }
+        }
+
+        impl<'a> $crate::FromNvValue<'a> for $name {
+            fn from_nv(list: &'a $crate::NvList, name: &str) -> $crate::NvResult<$name> {
+                let number = list.get_number(name).ok_or($crate::NvErr::NotFound)?;
+                $(
+                    if number == $name::$variant as i64 as u64 {
+                        return Ok($name::$variant);
+                    }
+                )*
+                Err($crate::NvErr::OutOfRange(number))
+            }
+        }
+    };
+}
+
+macro_rules! impl_from_nv_value {
+    ($type_:ty, $get_method:ident) => {
+        impl_from_nv_value!{$type_, $get_method, |value| value}
+    };
+    ($type_:ty, $get_method:ident, $convert:expr) => {
+        impl<'a> FromNvValue<'a> for $type_ {
+            fn from_nv(list: &'a NvList, name: &str) -> NvResult<$type_> {
+                list.$get_method(name).map($convert).ok_or(NvErr::NotFound)
+            }
+        }
+    };
+}
+
+impl_from_nv_value!{u64, get_number}
+
+impl_from_nv_value!{bool, get_bool}
+
+impl_from_nv_value!{&'a str, get_str}
+
+impl_from_nv_value!{String, get_string}
 
-impl_nv_list_ops!{u64, add_number, get_number}
+impl_from_nv_value!{NvList, get_nvlist}
 
-impl_nv_list_ops!{str, add_string, get_string, String, true}
+impl_from_nv_value!{&'a [u8], get_binary}
 
-impl_nv_list_ops!{NvList, add_nvlist, get_nvlist, NvList, true}
+impl_from_nv_value!{&'a [bool], get_bool_slice}
+
+impl_from_nv_value!{&'a [u64], get_number_slice}
+
+impl_from_nv_value!{Vec<bool>, get_bool_slice, <[bool]>::to_vec}
+
+impl_from_nv_value!{Vec<u64>, get_number_slice, <[u64]>::to_vec}
+
+impl_from_nv_value!{Vec<String>, get_string_vec}
+
+impl_from_nv_value!{Vec<NvList>, get_nvlist_vec}
+
+impl_from_nv_value!{BorrowedFd<'a>, get_descriptor}
+
+impl_from_nv_value!{&'a [RawFd], get_descriptor_slice}
+
+impl_from_nv_value!{Vec<u8>, get_binary, <[u8]>::to_vec}
+
+/// Strings and binary data are both read as paths, the reverse of adding
+/// a `Path`
+impl<'a> FromNvValue<'a> for PathBuf {
+    fn from_nv(list: &'a NvList, name: &str) -> NvResult<PathBuf> {
+        if let Some(value) = list.get_str(name) {
+            return Ok(PathBuf::from(value));
+        }
+        let value = list.get_binary(name).ok_or(NvErr::NotFound)?;
+        Ok(PathBuf::from(OsStr::from_bytes(value)))
+    }
+}
+
+impl<'a> FromNvValue<'a> for Duration {
+    fn from_nv(list: &'a NvList, name: &str) -> NvResult<Duration> {
+        list.get_number(name).map(Duration::from_nanos).ok_or(NvErr::NotFound)
+    }
+}
+
+impl<'a> FromNvValue<'a> for SystemTime {
+    fn from_nv(list: &'a NvList, name: &str) -> NvResult<SystemTime> {
+        Ok(UNIX_EPOCH + Duration::from_nv(list, name)?)
+    }
+}
+
+/// A missing value, or one of `NvType::Null`, is read as `None`
+impl<'a, T: FromNvValue<'a>> FromNvValue<'a> for Option<T> {
+    fn from_nv(list: &'a NvList, name: &str) -> NvResult<Option<T>> {
+        if list.exists_type(name, NvType::Null) {
+            return Ok(None);
+        }
+        match T::from_nv(list, name) {
+            Ok(value) => Ok(Some(value)),
+            Err(NvErr::NotFound) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl_nv_list_ops!{str, add_string, add_string_c, true}
+
+impl_nv_list_ops!{NvList, add_nvlist, add_nvlist_c, true}
+
+impl<T> NvListOps for &T
+    where T: NvListOps + ?Sized
+{
+    fn nv_add(&self, list: &mut NvList, name: &str) {
+        (**self).nv_add(list, name)
+    }
+
+    fn nv_add_c(&self, list: &mut NvList, name: &str, c_name: &CStr) {
+        (**self).nv_add_c(list, name, c_name)
+    }
+}
+
+impl NvListOps for String {
+    fn nv_add(&self, list: &mut NvList, name: &str) {
+        list.add_string(name, self)
+    }
+
+    fn nv_add_c(&self, list: &mut NvList, _: &str, c_name: &CStr) {
+        list.add_string_c(c_name, self)
+    }
+}
+
+impl NvListOps for [bool] {
+    fn nv_add(&self, list: &mut NvList, name: &str) {
+        list.add_bool_slice(name, self)
+    }
+
+    fn nv_add_c(&self, list: &mut NvList, _: &str, c_name: &CStr) {
+        list.add_bool_slice_c(c_name, self)
+    }
+}
+
+impl NvListOps for [u64] {
+    fn nv_add(&self, list: &mut NvList, name: &str) {
+        list.add_number_slice(name, self)
+    }
+
+    fn nv_add_c(&self, list: &mut NvList, _: &str, c_name: &CStr) {
+        list.add_number_slice_c(c_name, self)
+    }
+}
+
+impl NvListOps for [&str] {
+    fn nv_add(&self, list: &mut NvList, name: &str) {
+        list.add_string_slice(name, self)
+    }
+
+    fn nv_add_c(&self, list: &mut NvList, _: &str, c_name: &CStr) {
+        list.add_string_slice_c(c_name, self)
+    }
+}
+
+impl NvListOps for [String] {
+    fn nv_add(&self, list: &mut NvList, name: &str) {
+        let strs: Vec<&str> = self.iter().map(|item| item.as_str()).collect();
+        list.add_string_slice(name, &strs)
+    }
+}
+
+impl NvListOps for [NvList] {
+    fn nv_add(&self, list: &mut NvList, name: &str) {
+        list.add_nvlist_slice(name, self)
+    }
+
+    fn nv_add_c(&self, list: &mut NvList, _: &str, c_name: &CStr) {
+        list.add_nvlist_slice_c(c_name, self)
+    }
+}
+
+/// Bytes are added as binary data rather than an array of numbers
+impl NvListOps for [u8] {
+    fn nv_add(&self, list: &mut NvList, name: &str) {
+        // libnv copies the data, despite the pointer not being `const`
+        unsafe { list.add_binary(name, self.as_ptr() as *mut i8, self.len()) }
+    }
+
+    fn nv_add_c(&self, list: &mut NvList, _: &str, c_name: &CStr) {
+        unsafe { list.add_binary_c(c_name, self.as_ptr() as *mut i8, self.len()) }
+    }
+}
+
+impl<T> NvListOps for Vec<T>
+    where [T]: NvListOps
+{
+    fn nv_add(&self, list: &mut NvList, name: &str) {
+        self.as_slice().nv_add(list, name)
+    }
+
+    fn nv_add_c(&self, list: &mut NvList, name: &str, c_name: &CStr) {
+        self.as_slice().nv_add_c(list, name, c_name)
+    }
+}
+
+/// Maps are added as a nested `NvList` with a pair for each entry
+impl<K, V, S> NvListOps for HashMap<K, V, S>
+    where K: AsRef<str>,
+          V: NvListOps
+{
+    fn nv_add(&self, list: &mut NvList, name: &str) {
+        add_map(list, name, self.iter())
+    }
+}
+
+/// Maps are added as a nested `NvList` with a pair for each entry
+impl<K, V> NvListOps for BTreeMap<K, V>
+    where K: AsRef<str>,
+          V: NvListOps
+{
+    fn nv_add(&self, list: &mut NvList, name: &str) {
+        add_map(list, name, self.iter())
+    }
+}
+
+fn add_map<'a, K, V, I>(list: &mut NvList, name: &str, entries: I)
+    where K: AsRef<str> + 'a,
+          V: NvListOps + 'a,
+          I: Iterator<Item = (&'a K, &'a V)>
+{
+    match NvList::new(NvFlag::None) {
+        Ok(mut nested) => {
+            for (key, value) in entries {
+                value.nv_add(&mut nested, key.as_ref());
+            }
+            list.add_nvlist(name, &nested)
+        }
+        Err(_) => {
+            let _ = list.set_error(libc::ENOMEM);
+        }
+    }
+}
+
+/// Strings that are valid UTF-8 are added as strings, and others as
+/// binary data
+impl NvListOps for OsStr {
+    fn nv_add(&self, list: &mut NvList, name: &str) {
+        match self.to_str() {
+            Some(value) => list.add_string(name, value),
+            None => self.as_bytes().nv_add(list, name),
+        }
+    }
+}
+
+impl NvListOps for OsString {
+    fn nv_add(&self, list: &mut NvList, name: &str) {
+        self.as_os_str().nv_add(list, name)
+    }
+}
+
+/// Paths are added like `OsStr`s
+impl NvListOps for Path {
+    fn nv_add(&self, list: &mut NvList, name: &str) {
+        self.as_os_str().nv_add(list, name)
+    }
+}
+
+impl NvListOps for PathBuf {
+    fn nv_add(&self, list: &mut NvList, name: &str) {
+        self.as_os_str().nv_add(list, name)
+    }
+}
+
+macro_rules! impl_nv_list_display {
+    ($($type_:ty),*) => {
+        $(
+            /// Addresses are added as strings in their `Display` form
+            impl NvListOps for $type_ {
+                fn nv_add(&self, list: &mut NvList, name: &str) {
+                    list.add_string(name, &self.to_string())
+                }
+            }
+
+            impl<'a> FromNvValue<'a> for $type_ {
+                fn from_nv(list: &'a NvList, name: &str) -> NvResult<$type_> {
+                    let value = list.get_str(name).ok_or(NvErr::NotFound)?;
+                    value.parse().map_err(|_| NvErr::Errno(libc::EINVAL))
+                }
+            }
+        )*
+    };
+}
+
+impl_nv_list_display!{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6}
+
+/// Durations are added as a number of nanoseconds, setting the list's
+/// error to `EOVERFLOW` past `u64::MAX` nanoseconds, some 584 years
+impl NvListOps for Duration {
+    fn nv_add(&self, list: &mut NvList, name: &str) {
+        match u64::try_from(self.as_nanos()) {
+            Ok(nanos) => list.add_number(name, nanos),
+            Err(_) => {
+                let _ = list.set_error(libc::EOVERFLOW);
+            }
+        }
+    }
+}
+
+/// Times are added as the `Duration` since the Unix epoch, setting the
+/// list's error to `EOVERFLOW` for times before it
+impl NvListOps for SystemTime {
+    fn nv_add(&self, list: &mut NvList, name: &str) {
+        match self.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.nv_add(list, name),
+            Err(_) => {
+                let _ = list.set_error(libc::EOVERFLOW);
+            }
+        }
+    }
+}
 
 impl<T> NvListOps for Option<T>
     where T: NvListOps
 {
-    fn nv_add(&self, list: &mut NvList, name: &str) -> () {
-        match self {
-            &Some(ref val) => val.nv_add(list, name),
-            &None => list.add_null(name),
+    fn nv_add(&self, list: &mut NvList, name: &str) {
+        match *self {
+            Some(ref val) => val.nv_add(list, name),
+            None => list.add_null(name),
+        }
+    }
+
+    fn nv_add_c(&self, list: &mut NvList, name: &str, c_name: &CStr) {
+        match *self {
+            Some(ref val) => val.nv_add_c(list, name, c_name),
+            None => list.add_null_c(c_name),
+        }
+    }
+}
+
+/// The `errno` to set on a list when converting a nested `NvTree` fails
+fn errno(err: NvErr) -> i32 {
+    match err {
+        NvErr::Errno(errno) => errno,
+        _ => libc::ENOMEM,
+    }
+}
+
+impl NvListOps for NvTree {
+    fn nv_add(&self, list: &mut NvList, name: &str) {
+        match NvList::from_tree(self) {
+            Ok(ref tree) => list.add_nvlist(name, tree),
+            Err(err) => {
+                let _ = list.set_error(errno(err));
+            }
+        }
+    }
+}
+
+impl NvListOps for NvValue {
+    fn nv_add(&self, list: &mut NvList, name: &str) {
+        match *self {
+            NvValue::Null => list.add_null(name),
+            NvValue::Bool(val) => list.add_bool(name, val),
+            NvValue::Number(val) => list.add_number(name, val),
+            NvValue::String(ref val) => list.add_string(name, val),
+            NvValue::NvList(ref val) => val.nv_add(list, name),
+            NvValue::Descriptor(val) => list.add_descriptor(name, val),
+            NvValue::Binary(ref val) => unsafe {
+                list.add_binary(name, val.as_ptr() as *mut i8, val.len())
+            },
+            NvValue::BoolArray(ref val) => list.add_bool_slice(name, val),
+            NvValue::NumberArray(ref val) => list.add_number_slice(name, val),
+            NvValue::StringArray(ref val) => {
+                let strs: Vec<&str> = val.iter().map(|item| item.as_str()).collect();
+                list.add_string_slice(name, &strs)
+            }
+            NvValue::NvListArray(ref val) => {
+                let mut lists = Vec::with_capacity(val.len());
+                for tree in val {
+                    match NvList::from_tree(tree) {
+                        Ok(nested) => lists.push(nested),
+                        Err(err) => {
+                            let _ = list.set_error(errno(err));
+                            return;
+                        }
+                    }
+                }
+                list.add_nvlist_slice(name, &lists)
+            }
+            NvValue::DescriptorArray(ref val) => list.add_descriptor_slice(name, val),
         }
     }
 }
@@ -0,0 +1,1001 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+
+//! The Solaris `libnvpair` format used by ZFS
+//!
+//! ZFS on FreeBSD and Linux stores and passes name/value pairs with the
+//! Solaris library rather than libnv, for example in `zpool.cache`, in
+//! vdev labels and in ioctl payloads. Its lists have more types than
+//! libnv's, such as sized and signed integers, `hrtime` and `double`, and
+//! are packed in either the portable XDR encoding or the native one,
+//! which is a copy of the in-memory structures of the packing host.
+//!
+//! `NvPairList` holds such a list in Rust memory. It converts to and from
+//! an `NvTree` where the types map, which is also how it is loaded into
+//! an `NvList` with `NvList::from_tree`.
+//!
+//! ```
+//! use nv::nvpair::{Encoding, NvPairList, NvPairValue};
+//! use nv::{NvValue, UnpackOptions};
+//!
+//! let mut list = NvPairList::new(NvPairList::UNIQUE_NAME);
+//! list.push("name", NvPairValue::String("tank".to_owned()));
+//! list.push("version", NvPairValue::Uint64(5000));
+//!
+//! let buf = list.pack(Encoding::Xdr).unwrap();
+//! let unpacked = NvPairList::unpack(&buf, &UnpackOptions::default()).unwrap();
+//! assert_eq!(unpacked, list);
+//!
+//! let tree = unpacked.to_tree().unwrap();
+//! assert_eq!(tree.get("version"), Some(&NvValue::Number(5000)));
+//! ```
+
+use common::{NvErr, NvFlag, NvLimit, NvResult};
+use packed::UnpackOptions;
+use value::{NvTree, NvValue};
+use libc;
+use std::str;
+
+const NV_VERSION: i32 = 0;
+const NV_ENCODE_NATIVE: u8 = 0;
+const NV_ENCODE_XDR: u8 = 1;
+const NV_BIG_ENDIAN: u8 = 0;
+const NV_LITTLE_ENDIAN: u8 = 1;
+/// Size of the stream header preceding the top level list
+const NVS_HEADER_SIZE: usize = 4;
+/// Size of the fixed part of a native `nvpair_t`
+const NVP_HEADER_SIZE: usize = 16;
+/// Size of a native `nvlist_t`
+const NVL_SIZE: usize = 24;
+
+/// How a list is packed
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Encoding {
+    /// The in-memory layout of this host, which only hosts with the same
+    /// byte order can unpack
+    Native,
+    /// The portable XDR encoding, used for everything ZFS writes to disk
+    Xdr,
+}
+
+/// A single value held by an `NvPairList`
+#[derive(Clone, Debug, PartialEq)]
+pub enum NvPairValue {
+    /// A name without a value, `DATA_TYPE_BOOLEAN`
+    Boolean,
+    /// `DATA_TYPE_BOOLEAN_VALUE`
+    BooleanValue(bool),
+    /// `DATA_TYPE_BYTE`
+    Byte(u8),
+    /// `DATA_TYPE_INT8`
+    Int8(i8),
+    /// `DATA_TYPE_UINT8`
+    Uint8(u8),
+    /// `DATA_TYPE_INT16`
+    Int16(i16),
+    /// `DATA_TYPE_UINT16`
+    Uint16(u16),
+    /// `DATA_TYPE_INT32`
+    Int32(i32),
+    /// `DATA_TYPE_UINT32`
+    Uint32(u32),
+    /// `DATA_TYPE_INT64`
+    Int64(i64),
+    /// `DATA_TYPE_UINT64`
+    Uint64(u64),
+    /// `DATA_TYPE_STRING`
+    String(String),
+    /// `DATA_TYPE_HRTIME`, in nanoseconds
+    Hrtime(i64),
+    /// `DATA_TYPE_DOUBLE`
+    Double(f64),
+    /// `DATA_TYPE_NVLIST`
+    NvList(NvPairList),
+    /// `DATA_TYPE_BYTE_ARRAY`
+    ByteArray(Vec<u8>),
+    /// `DATA_TYPE_BOOLEAN_ARRAY`
+    BooleanArray(Vec<bool>),
+    /// `DATA_TYPE_INT8_ARRAY`
+    Int8Array(Vec<i8>),
+    /// `DATA_TYPE_UINT8_ARRAY`
+    Uint8Array(Vec<u8>),
+    /// `DATA_TYPE_INT16_ARRAY`
+    Int16Array(Vec<i16>),
+    /// `DATA_TYPE_UINT16_ARRAY`
+    Uint16Array(Vec<u16>),
+    /// `DATA_TYPE_INT32_ARRAY`
+    Int32Array(Vec<i32>),
+    /// `DATA_TYPE_UINT32_ARRAY`
+    Uint32Array(Vec<u32>),
+    /// `DATA_TYPE_INT64_ARRAY`
+    Int64Array(Vec<i64>),
+    /// `DATA_TYPE_UINT64_ARRAY`
+    Uint64Array(Vec<u64>),
+    /// `DATA_TYPE_STRING_ARRAY`
+    StringArray(Vec<String>),
+    /// `DATA_TYPE_NVLIST_ARRAY`
+    NvListArray(Vec<NvPairList>),
+}
+
+impl NvPairValue {
+    /// The `data_type_t` of the value
+    pub fn data_type(&self) -> i32 {
+        match *self {
+            NvPairValue::Boolean => 1,
+            NvPairValue::Byte(_) => 2,
+            NvPairValue::Int16(_) => 3,
+            NvPairValue::Uint16(_) => 4,
+            NvPairValue::Int32(_) => 5,
+            NvPairValue::Uint32(_) => 6,
+            NvPairValue::Int64(_) => 7,
+            NvPairValue::Uint64(_) => 8,
+            NvPairValue::String(_) => 9,
+            NvPairValue::ByteArray(_) => 10,
+            NvPairValue::Int16Array(_) => 11,
+            NvPairValue::Uint16Array(_) => 12,
+            NvPairValue::Int32Array(_) => 13,
+            NvPairValue::Uint32Array(_) => 14,
+            NvPairValue::Int64Array(_) => 15,
+            NvPairValue::Uint64Array(_) => 16,
+            NvPairValue::StringArray(_) => 17,
+            NvPairValue::Hrtime(_) => 18,
+            NvPairValue::NvList(_) => 19,
+            NvPairValue::NvListArray(_) => 20,
+            NvPairValue::BooleanValue(_) => 21,
+            NvPairValue::Int8(_) => 22,
+            NvPairValue::Uint8(_) => 23,
+            NvPairValue::BooleanArray(_) => 24,
+            NvPairValue::Int8Array(_) => 25,
+            NvPairValue::Uint8Array(_) => 26,
+            NvPairValue::Double(_) => 27,
+        }
+    }
+
+    /// The number of elements, as stored in `nvp_value_elem`
+    fn nelem(&self) -> usize {
+        match *self {
+            NvPairValue::Boolean => 0,
+            NvPairValue::ByteArray(ref value) |
+            NvPairValue::Uint8Array(ref value) => value.len(),
+            NvPairValue::BooleanArray(ref value) => value.len(),
+            NvPairValue::Int8Array(ref value) => value.len(),
+            NvPairValue::Int16Array(ref value) => value.len(),
+            NvPairValue::Uint16Array(ref value) => value.len(),
+            NvPairValue::Int32Array(ref value) => value.len(),
+            NvPairValue::Uint32Array(ref value) => value.len(),
+            NvPairValue::Int64Array(ref value) => value.len(),
+            NvPairValue::Uint64Array(ref value) => value.len(),
+            NvPairValue::StringArray(ref value) => value.len(),
+            NvPairValue::NvListArray(ref value) => value.len(),
+            _ => 1,
+        }
+    }
+
+    /// The size of the value in a native `nvpair_t`, as computed by
+    /// libnvpair's `i_get_value_size`
+    fn native_size(&self) -> usize {
+        match *self {
+            NvPairValue::Boolean => 0,
+            NvPairValue::Byte(_) | NvPairValue::Int8(_) | NvPairValue::Uint8(_) => 1,
+            NvPairValue::Int16(_) | NvPairValue::Uint16(_) => 2,
+            NvPairValue::BooleanValue(_) | NvPairValue::Int32(_) | NvPairValue::Uint32(_) => 4,
+            NvPairValue::Int64(_) | NvPairValue::Uint64(_) | NvPairValue::Hrtime(_) |
+            NvPairValue::Double(_) => 8,
+            NvPairValue::String(ref value) => value.len() + 1,
+            NvPairValue::NvList(_) => NVL_SIZE,
+            NvPairValue::ByteArray(ref value) |
+            NvPairValue::Uint8Array(ref value) => value.len(),
+            NvPairValue::Int8Array(ref value) => value.len(),
+            NvPairValue::BooleanArray(ref value) => 4 * value.len(),
+            NvPairValue::Int16Array(ref value) => 2 * value.len(),
+            NvPairValue::Uint16Array(ref value) => 2 * value.len(),
+            NvPairValue::Int32Array(ref value) => 4 * value.len(),
+            NvPairValue::Uint32Array(ref value) => 4 * value.len(),
+            NvPairValue::Int64Array(ref value) => 8 * value.len(),
+            NvPairValue::Uint64Array(ref value) => 8 * value.len(),
+            NvPairValue::StringArray(ref value) => {
+                value.iter().map(|item| 8 + item.len() + 1).sum()
+            }
+            NvPairValue::NvListArray(ref value) => (8 + NVL_SIZE) * value.len(),
+        }
+    }
+}
+
+/// A Solaris `nvlist` held in Rust memory
+///
+/// Pairs are kept in order and names are not checked for uniqueness,
+/// whatever the flags say.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NvPairList {
+    flags: u32,
+    pairs: Vec<(String, NvPairValue)>,
+}
+
+impl NvPairList {
+    /// Names are unique, `NV_UNIQUE_NAME`
+    pub const UNIQUE_NAME: u32 = 1;
+    /// Pairs with the same name and type are unique, `NV_UNIQUE_NAME_TYPE`
+    pub const UNIQUE_NAME_TYPE: u32 = 2;
+
+    /// Create a new, empty list with the given `nvl_nvflag`
+    pub fn new(flags: u32) -> NvPairList {
+        NvPairList {
+            flags,
+            pairs: Vec::new(),
+        }
+    }
+
+    /// The `nvl_nvflag` the list was created with
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    /// The number of name/value pairs in the list
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    /// Determines if the list is empty
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    /// Append a name/value pair to the list
+    pub fn push(&mut self, name: &str, value: NvPairValue) {
+        self.pairs.push((name.to_owned(), value));
+    }
+
+    /// Get the first value paired with the given name
+    pub fn get(&self, name: &str) -> Option<&NvPairValue> {
+        self.pairs.iter().find(|&(key, _)| key == name).map(|(_, value)| value)
+    }
+
+    /// Iterate over the name/value pairs in order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &NvPairValue)> {
+        self.pairs.iter().map(|(name, value)| (name.as_str(), value))
+    }
+
+    /// Decode a list packed by libnvpair's `nvlist_pack`, in either
+    /// encoding, enforcing the limits in `options`
+    ///
+    /// As with libnvpair, natively packed lists are only accepted from a
+    /// host with the same byte order, and fail with `ENOTSUP` otherwise.
+    pub fn unpack(buf: &[u8], options: &UnpackOptions) -> NvResult<NvPairList> {
+        let (list, len) = NvPairList::unpack_prefix(buf, options)?;
+        if len != buf.len() {
+            return Err(NvErr::InvalidFormat(len));
+        }
+        Ok(list)
+    }
+
+    /// Decode the list at the start of `buf`, returning it along with
+    /// its packed size
+    pub(crate) fn unpack_prefix(buf: &[u8],
+                                options: &UnpackOptions)
+                                -> NvResult<(NvPairList, usize)> {
+        if buf.len() > options.max_bytes {
+            return Err(NvErr::LimitExceeded(NvLimit::Bytes));
+        }
+        if buf.len() < NVS_HEADER_SIZE {
+            return Err(NvErr::Truncated(0));
+        }
+        let mut decoder = Decoder {
+            buf,
+            pos: NVS_HEADER_SIZE,
+            options,
+            pairs: 0,
+            xdr: match buf[0] {
+                NV_ENCODE_XDR => true,
+                NV_ENCODE_NATIVE if buf[1] == host_endian() => false,
+                NV_ENCODE_NATIVE => return Err(NvErr::Errno(libc::ENOTSUP)),
+                _ => return Err(NvErr::InvalidFormat(0)),
+            },
+        };
+        let list = decoder.list(0)?;
+        Ok((list, decoder.pos))
+    }
+
+    /// Pack the list the way libnvpair's `nvlist_pack` does
+    ///
+    /// This fails with `EINVAL` if a string or name holds a NUL byte or
+    /// a pair is too large for the format.
+    pub fn pack(&self, encoding: Encoding) -> NvResult<Vec<u8>> {
+        let mut encoder = Encoder {
+            buf: Vec::new(),
+            xdr: encoding == Encoding::Xdr,
+        };
+        let kind = if encoder.xdr { NV_ENCODE_XDR } else { NV_ENCODE_NATIVE };
+        encoder.buf.extend_from_slice(&[kind, host_endian(), 0, 0]);
+        encoder.list(self)?;
+        Ok(encoder.buf)
+    }
+
+    /// Convert the list to an `NvTree`
+    ///
+    /// Unsigned integers become numbers, as do signed ones and `hrtime`s
+    /// that are not negative. `Boolean` pairs become null ones and byte
+    /// arrays binary ones. Other values fail the conversion with
+    /// `ENOTSUP`.
+    pub fn to_tree(&self) -> NvResult<NvTree> {
+        let flags = if self.flags & NvPairList::UNIQUE_NAME != 0 {
+            NvFlag::None
+        } else {
+            NvFlag::NoUnique
+        };
+        let mut tree = NvTree::new(flags);
+        for (name, value) in self.iter() {
+            tree.push(name, value.to_value()?);
+        }
+        Ok(tree)
+    }
+
+    /// Convert an `NvTree` to a list
+    ///
+    /// Numbers become `Uint64` pairs. Trees holding descriptors fail the
+    /// conversion with `ENOTSUP`.
+    pub fn from_tree(tree: &NvTree) -> NvResult<NvPairList> {
+        let flags = match tree.flags() {
+            NvFlag::None | NvFlag::IgnoreCase => NvPairList::UNIQUE_NAME,
+            NvFlag::NoUnique | NvFlag::All => 0,
+        };
+        let mut list = NvPairList::new(flags);
+        for (name, value) in tree.iter() {
+            list.push(name, NvPairValue::from_value(value)?);
+        }
+        Ok(list)
+    }
+}
+
+fn unsupported<T>() -> NvResult<T> {
+    Err(NvErr::Errno(libc::ENOTSUP))
+}
+
+fn unsigned<T: Copy + Into<i64>>(values: &[T]) -> NvResult<Vec<u64>> {
+    values.iter()
+        .map(|&value| {
+            let value = value.into();
+            if value < 0 { unsupported() } else { Ok(value as u64) }
+        })
+        .collect()
+}
+
+impl NvPairValue {
+    fn to_value(&self) -> NvResult<NvValue> {
+        let value = match *self {
+            NvPairValue::Boolean => NvValue::Null,
+            NvPairValue::BooleanValue(value) => NvValue::Bool(value),
+            NvPairValue::Byte(value) | NvPairValue::Uint8(value) => NvValue::Number(value as u64),
+            NvPairValue::Uint16(value) => NvValue::Number(value as u64),
+            NvPairValue::Uint32(value) => NvValue::Number(value as u64),
+            NvPairValue::Uint64(value) => NvValue::Number(value),
+            NvPairValue::Int8(value) => NvValue::Number(unsigned(&[value])?[0]),
+            NvPairValue::Int16(value) => NvValue::Number(unsigned(&[value])?[0]),
+            NvPairValue::Int32(value) => NvValue::Number(unsigned(&[value])?[0]),
+            NvPairValue::Int64(value) |
+            NvPairValue::Hrtime(value) => NvValue::Number(unsigned(&[value])?[0]),
+            NvPairValue::String(ref value) => NvValue::String(value.clone()),
+            NvPairValue::Double(_) => return unsupported(),
+            NvPairValue::NvList(ref value) => NvValue::NvList(value.to_tree()?),
+            NvPairValue::ByteArray(ref value) |
+            NvPairValue::Uint8Array(ref value) => NvValue::Binary(value.clone()),
+            NvPairValue::BooleanArray(ref value) => NvValue::BoolArray(value.clone()),
+            NvPairValue::Int8Array(ref value) => NvValue::NumberArray(unsigned(value)?),
+            NvPairValue::Int16Array(ref value) => NvValue::NumberArray(unsigned(value)?),
+            NvPairValue::Int32Array(ref value) => NvValue::NumberArray(unsigned(value)?),
+            NvPairValue::Int64Array(ref value) => NvValue::NumberArray(unsigned(value)?),
+            NvPairValue::Uint16Array(ref value) => {
+                NvValue::NumberArray(value.iter().map(|&item| item as u64).collect())
+            }
+            NvPairValue::Uint32Array(ref value) => {
+                NvValue::NumberArray(value.iter().map(|&item| item as u64).collect())
+            }
+            NvPairValue::Uint64Array(ref value) => NvValue::NumberArray(value.clone()),
+            NvPairValue::StringArray(ref value) => NvValue::StringArray(value.clone()),
+            NvPairValue::NvListArray(ref value) => {
+                NvValue::NvListArray(value.iter()
+                    .map(|item| item.to_tree())
+                    .collect::<NvResult<Vec<NvTree>>>()?)
+            }
+        };
+        Ok(value)
+    }
+
+    fn from_value(value: &NvValue) -> NvResult<NvPairValue> {
+        let value = match *value {
+            NvValue::Null => NvPairValue::Boolean,
+            NvValue::Bool(value) => NvPairValue::BooleanValue(value),
+            NvValue::Number(value) => NvPairValue::Uint64(value),
+            NvValue::String(ref value) => NvPairValue::String(value.clone()),
+            NvValue::NvList(ref value) => NvPairValue::NvList(NvPairList::from_tree(value)?),
+            NvValue::Binary(ref value) => NvPairValue::ByteArray(value.clone()),
+            NvValue::BoolArray(ref value) => NvPairValue::BooleanArray(value.clone()),
+            NvValue::NumberArray(ref value) => NvPairValue::Uint64Array(value.clone()),
+            NvValue::StringArray(ref value) => NvPairValue::StringArray(value.clone()),
+            NvValue::NvListArray(ref value) => {
+                NvPairValue::NvListArray(value.iter()
+                    .map(NvPairList::from_tree)
+                    .collect::<NvResult<Vec<NvPairList>>>()?)
+            }
+            NvValue::Descriptor(_) | NvValue::DescriptorArray(_) => return unsupported(),
+        };
+        Ok(value)
+    }
+}
+
+fn host_endian() -> u8 {
+    if cfg!(target_endian = "big") { NV_BIG_ENDIAN } else { NV_LITTLE_ENDIAN }
+}
+
+fn align4(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn align8(len: usize) -> usize {
+    (len + 7) & !7
+}
+
+/// The size of a native `nvpair_t`, libnvpair's `NVP_SIZE_CALC`
+fn nvp_size(name: &str, value: &NvPairValue) -> usize {
+    align8(NVP_HEADER_SIZE + name.len() + 1) + align8(value.native_size())
+}
+
+struct Decoder<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    options: &'a UnpackOptions,
+    pairs: usize,
+    xdr: bool,
+}
+
+impl<'a> Decoder<'a> {
+    fn bytes(&mut self, len: usize) -> NvResult<&'a [u8]> {
+        if self.buf.len() - self.pos < len {
+            return Err(NvErr::Truncated(self.pos));
+        }
+        let bytes = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> NvResult<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.bytes(4)?);
+        Ok(if self.xdr { u32::from_be_bytes(bytes) } else { u32::from_ne_bytes(bytes) })
+    }
+
+    fn u64(&mut self) -> NvResult<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Ok(if self.xdr { u64::from_be_bytes(bytes) } else { u64::from_ne_bytes(bytes) })
+    }
+
+    fn string(&self, bytes: &[u8], start: usize) -> NvResult<String> {
+        if bytes.len() > self.options.max_string_len {
+            return Err(NvErr::LimitExceeded(NvLimit::StringLength));
+        }
+        match str::from_utf8(bytes) {
+            Ok(value) if !value.contains('\0') => Ok(value.to_owned()),
+            _ => Err(NvErr::InvalidFormat(start)),
+        }
+    }
+
+    /// An XDR string: its length, then its bytes padded to 4
+    fn xdr_string(&mut self) -> NvResult<String> {
+        let start = self.pos;
+        let len = self.u32()? as usize;
+        let bytes = self.bytes(align4(len))?;
+        self.string(&bytes[..len], start)
+    }
+
+    fn list(&mut self, depth: usize) -> NvResult<NvPairList> {
+        if depth > self.options.max_depth {
+            return Err(NvErr::LimitExceeded(NvLimit::Depth));
+        }
+        let start = self.pos;
+        if self.u32()? as i32 != NV_VERSION {
+            return Err(NvErr::InvalidFormat(start));
+        }
+        let mut list = NvPairList::new(self.u32()?);
+        loop {
+            self.pairs += 1;
+            if self.pairs > self.options.max_pairs {
+                return Err(NvErr::LimitExceeded(NvLimit::Pairs));
+            }
+            let pair = if self.xdr { self.xdr_pair(depth)? } else { self.native_pair(depth)? };
+            match pair {
+                Some((name, value)) => list.pairs.push((name, value)),
+                None => return Ok(list),
+            }
+        }
+    }
+
+    fn check_nelem(&self, ty: i32, nelem: usize, start: usize) -> NvResult<()> {
+        let expected = match ty {
+            1 => Some(0),
+            2..=9 | 18 | 19 | 21..=23 | 27 => Some(1),
+            10..=17 | 20 | 24..=26 => None,
+            _ => return Err(NvErr::InvalidFormat(start)),
+        };
+        match expected {
+            Some(expected) if nelem != expected => Err(NvErr::InvalidFormat(start)),
+            None if nelem > self.options.max_array_len => {
+                Err(NvErr::LimitExceeded(NvLimit::ArrayLength))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn nested(&mut self, ty: i32, nelem: usize, depth: usize) -> NvResult<NvPairValue> {
+        if ty == 19 {
+            Ok(NvPairValue::NvList(self.list(depth + 1)?))
+        } else {
+            let lists = (0..nelem).map(|_| self.list(depth + 1)).collect::<NvResult<_>>()?;
+            Ok(NvPairValue::NvListArray(lists))
+        }
+    }
+
+    fn xdr_pair(&mut self, depth: usize) -> NvResult<Option<(String, NvPairValue)>> {
+        let start = self.pos;
+        let _encoded_size = self.u32()?;
+        if self.u32()? == 0 {
+            // The decoded size is zero at the end of the list
+            return Ok(None);
+        }
+        let name = self.xdr_string()?;
+        let ty = self.u32()? as i32;
+        let nelem = self.u32()? as i32;
+        if nelem < 0 {
+            return Err(NvErr::InvalidFormat(start));
+        }
+        let nelem = nelem as usize;
+        self.check_nelem(ty, nelem, start)?;
+        let value = match ty {
+            1 => NvPairValue::Boolean,
+            2 => NvPairValue::Byte(self.u32()? as u8),
+            3 => NvPairValue::Int16(self.u32()? as i16),
+            4 => NvPairValue::Uint16(self.u32()? as u16),
+            5 => NvPairValue::Int32(self.u32()? as i32),
+            6 => NvPairValue::Uint32(self.u32()?),
+            7 => NvPairValue::Int64(self.u64()? as i64),
+            8 => NvPairValue::Uint64(self.u64()?),
+            9 => NvPairValue::String(self.xdr_string()?),
+            10 => NvPairValue::ByteArray(self.bytes(align4(nelem))?[..nelem].to_vec()),
+            17 => {
+                NvPairValue::StringArray((0..nelem).map(|_| self.xdr_string())
+                    .collect::<NvResult<_>>()?)
+            }
+            18 => NvPairValue::Hrtime(self.u64()? as i64),
+            19 | 20 => self.nested(ty, nelem, depth)?,
+            21 => NvPairValue::BooleanValue(self.xdr_bool(start)?),
+            22 => NvPairValue::Int8(self.u32()? as i8),
+            23 => NvPairValue::Uint8(self.u32()? as u8),
+            27 => NvPairValue::Double(f64::from_bits(self.u64()?)),
+            _ => {
+                // Arrays encoded with `xdr_array`, which repeats the length
+                if self.u32()? as usize != nelem {
+                    return Err(NvErr::InvalidFormat(start));
+                }
+                let wide = ty == 15 || ty == 16;
+                if self.buf.len() - self.pos < nelem * if wide { 8 } else { 4 } {
+                    return Err(NvErr::Truncated(self.pos));
+                }
+                match ty {
+                    11 => NvPairValue::Int16Array(self.xdr_array(nelem, |v| v as i16)?),
+                    12 => NvPairValue::Uint16Array(self.xdr_array(nelem, |v| v as u16)?),
+                    13 => NvPairValue::Int32Array(self.xdr_array(nelem, |v| v as i32)?),
+                    14 => NvPairValue::Uint32Array(self.xdr_array(nelem, |v| v)?),
+                    15 => {
+                        NvPairValue::Int64Array((0..nelem).map(|_| self.u64().map(|v| v as i64))
+                            .collect::<NvResult<_>>()?)
+                    }
+                    16 => {
+                        NvPairValue::Uint64Array((0..nelem).map(|_| self.u64())
+                            .collect::<NvResult<_>>()?)
+                    }
+                    24 => {
+                        NvPairValue::BooleanArray((0..nelem).map(|_| self.xdr_bool(start))
+                            .collect::<NvResult<_>>()?)
+                    }
+                    25 => NvPairValue::Int8Array(self.xdr_array(nelem, |v| v as i8)?),
+                    _ => NvPairValue::Uint8Array(self.xdr_array(nelem, |v| v as u8)?),
+                }
+            }
+        };
+        Ok(Some((name, value)))
+    }
+
+    fn xdr_bool(&mut self, start: usize) -> NvResult<bool> {
+        match self.u32()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(NvErr::InvalidFormat(start)),
+        }
+    }
+
+    fn xdr_array<T, F: Fn(u32) -> T>(&mut self, nelem: usize, convert: F) -> NvResult<Vec<T>> {
+        (0..nelem).map(|_| self.u32().map(&convert)).collect()
+    }
+
+    fn native_pair(&mut self, depth: usize) -> NvResult<Option<(String, NvPairValue)>> {
+        let start = self.pos;
+        let size = self.u32()? as i32;
+        if size == 0 {
+            return Ok(None);
+        }
+        self.pos = start;
+        if size < NVP_HEADER_SIZE as i32 {
+            return Err(NvErr::InvalidFormat(start));
+        }
+        let pair = self.bytes(size as usize)?;
+        let mut header = Decoder {
+            buf: pair,
+            pos: 4,
+            options: self.options,
+            pairs: 0,
+            xdr: false,
+        };
+        let sizes = header.u32()?;
+        let name_size = if cfg!(target_endian = "big") { sizes >> 16 } else { sizes & 0xffff };
+        let nelem = header.u32()? as i32;
+        let ty = header.u32()? as i32;
+        if nelem < 0 || name_size == 0 {
+            return Err(NvErr::InvalidFormat(start));
+        }
+        let nelem = nelem as usize;
+        self.check_nelem(ty, nelem, start)?;
+        let name_bytes = header.bytes(name_size as usize)
+            .map_err(|_| NvErr::InvalidFormat(start))?;
+        let name = match name_bytes.split_last() {
+            Some((&0, name)) => self.string(name, start)?,
+            _ => return Err(NvErr::InvalidFormat(start)),
+        };
+        header.pos = align8(NVP_HEADER_SIZE + name_size as usize);
+        let mut data = Decoder {
+            buf: &pair[header.pos.min(pair.len())..],
+            pos: 0,
+            options: self.options,
+            pairs: 0,
+            xdr: false,
+        };
+        let value = self.native_value(&mut data, ty, nelem, start)
+            .map_err(|err| match err {
+                NvErr::Truncated(_) => NvErr::InvalidFormat(start),
+                err => err,
+            })?;
+        let value = match value {
+            Some(value) => value,
+            None => self.nested(ty, nelem, depth)?,
+        };
+        if nvp_size(&name, &value) > size as usize {
+            return Err(NvErr::InvalidFormat(start));
+        }
+        Ok(Some((name, value)))
+    }
+
+    /// Decode the value of a native pair, or `None` for lists, whose
+    /// pairs follow the one holding them
+    fn native_value(&self,
+                    data: &mut Decoder<'a>,
+                    ty: i32,
+                    nelem: usize,
+                    start: usize)
+                    -> NvResult<Option<NvPairValue>> {
+        fn array<'a, T, F>(data: &mut Decoder<'a>, nelem: usize, width: usize, convert: F)
+                           -> NvResult<Vec<T>>
+            where F: Fn(&[u8]) -> T
+        {
+            let bytes = data.bytes(nelem.checked_mul(width).ok_or(NvErr::Truncated(0))?)?;
+            Ok(bytes.chunks(width).map(convert).collect())
+        }
+        let value = match ty {
+            1 => NvPairValue::Boolean,
+            2 => NvPairValue::Byte(data.bytes(1)?[0]),
+            3 => NvPairValue::Int16(ne_u16(data.bytes(2)?) as i16),
+            4 => NvPairValue::Uint16(ne_u16(data.bytes(2)?)),
+            5 => NvPairValue::Int32(data.u32()? as i32),
+            6 => NvPairValue::Uint32(data.u32()?),
+            7 => NvPairValue::Int64(data.u64()? as i64),
+            8 => NvPairValue::Uint64(data.u64()?),
+            9 => {
+                let end = data.buf.iter().position(|&byte| byte == 0).ok_or(NvErr::Truncated(0))?;
+                NvPairValue::String(self.string(&data.buf[..end], start)?)
+            }
+            10 => NvPairValue::ByteArray(data.bytes(nelem)?.to_vec()),
+            11 => NvPairValue::Int16Array(array(data, nelem, 2, |b| ne_u16(b) as i16)?),
+            12 => NvPairValue::Uint16Array(array(data, nelem, 2, ne_u16)?),
+            13 => NvPairValue::Int32Array(array(data, nelem, 4, |b| ne_u32(b) as i32)?),
+            14 => NvPairValue::Uint32Array(array(data, nelem, 4, ne_u32)?),
+            15 => NvPairValue::Int64Array(array(data, nelem, 8, |b| ne_u64(b) as i64)?),
+            16 => NvPairValue::Uint64Array(array(data, nelem, 8, ne_u64)?),
+            17 => {
+                // The pointers to the strings are meaningless once packed
+                data.bytes(nelem.checked_mul(8).ok_or(NvErr::Truncated(0))?)?;
+                let mut strings = Vec::with_capacity(nelem);
+                for _ in 0..nelem {
+                    let rest = &data.buf[data.pos..];
+                    let end = rest.iter().position(|&byte| byte == 0).ok_or(NvErr::Truncated(0))?;
+                    strings.push(self.string(&rest[..end], start)?);
+                    data.pos += end + 1;
+                }
+                NvPairValue::StringArray(strings)
+            }
+            18 => NvPairValue::Hrtime(data.u64()? as i64),
+            19 | 20 => return Ok(None),
+            21 => {
+                match data.u32()? {
+                    0 => NvPairValue::BooleanValue(false),
+                    1 => NvPairValue::BooleanValue(true),
+                    _ => return Err(NvErr::InvalidFormat(start)),
+                }
+            }
+            22 => NvPairValue::Int8(data.bytes(1)?[0] as i8),
+            23 => NvPairValue::Uint8(data.bytes(1)?[0]),
+            24 => {
+                let values = array(data, nelem, 4, ne_u32)?;
+                if values.iter().any(|&value| value > 1) {
+                    return Err(NvErr::InvalidFormat(start));
+                }
+                NvPairValue::BooleanArray(values.into_iter().map(|value| value == 1).collect())
+            }
+            25 => NvPairValue::Int8Array(data.bytes(nelem)?.iter().map(|&b| b as i8).collect()),
+            26 => NvPairValue::Uint8Array(data.bytes(nelem)?.to_vec()),
+            _ => NvPairValue::Double(f64::from_bits(data.u64()?)),
+        };
+        Ok(Some(value))
+    }
+}
+
+fn ne_u16(bytes: &[u8]) -> u16 {
+    u16::from_ne_bytes([bytes[0], bytes[1]])
+}
+
+fn ne_u32(bytes: &[u8]) -> u32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(&bytes[..4]);
+    u32::from_ne_bytes(buf)
+}
+
+fn ne_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(&bytes[..8]);
+    u64::from_ne_bytes(buf)
+}
+
+struct Encoder {
+    buf: Vec<u8>,
+    xdr: bool,
+}
+
+fn invalid<T>() -> NvResult<T> {
+    Err(NvErr::Errno(libc::EINVAL))
+}
+
+impl Encoder {
+    fn u32(&mut self, value: u32) {
+        let bytes = if self.xdr { value.to_be_bytes() } else { value.to_ne_bytes() };
+        self.buf.extend_from_slice(&bytes);
+    }
+
+    fn u64(&mut self, value: u64) {
+        let bytes = if self.xdr { value.to_be_bytes() } else { value.to_ne_bytes() };
+        self.buf.extend_from_slice(&bytes);
+    }
+
+    fn pad4(&mut self) {
+        while !self.buf.len().is_multiple_of(4) {
+            self.buf.push(0);
+        }
+    }
+
+    fn xdr_string(&mut self, value: &str) -> NvResult<()> {
+        if value.contains('\0') {
+            return invalid();
+        }
+        self.u32(value.len() as u32);
+        self.buf.extend_from_slice(value.as_bytes());
+        self.pad4();
+        Ok(())
+    }
+
+    fn list(&mut self, list: &NvPairList) -> NvResult<()> {
+        self.u32(NV_VERSION as u32);
+        self.u32(list.flags);
+        for (name, value) in list.iter() {
+            if self.xdr {
+                self.xdr_pair(name, value)?;
+            } else {
+                self.native_pair(name, value)?;
+            }
+        }
+        // The end of the list is marked by a zero encoded and decoded
+        // size in XDR, and a zero pair size natively
+        self.u32(0);
+        if self.xdr {
+            self.u32(0);
+        }
+        Ok(())
+    }
+
+    fn nested(&mut self, value: &NvPairValue) -> NvResult<()> {
+        match *value {
+            NvPairValue::NvList(ref list) => self.list(list),
+            NvPairValue::NvListArray(ref lists) => {
+                for list in lists {
+                    self.list(list)?;
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn xdr_pair(&mut self, name: &str, value: &NvPairValue) -> NvResult<()> {
+        let decoded_size = nvp_size(name, value);
+        let size_at = self.buf.len();
+        self.u32(0);
+        self.u32(decoded_size as u32);
+        self.xdr_string(name)?;
+        self.u32(value.data_type() as u32);
+        self.u32(value.nelem() as u32);
+        let xdr_array = |encoder: &mut Encoder, len: usize| encoder.u32(len as u32);
+        match *value {
+            NvPairValue::Boolean => {}
+            NvPairValue::BooleanValue(value) => self.u32(value as u32),
+            // As `xdr_char` and `xdr_short` do, signed values are widened
+            // with their sign
+            NvPairValue::Byte(value) => self.u32(value as i8 as i32 as u32),
+            NvPairValue::Int8(value) => self.u32(value as i32 as u32),
+            NvPairValue::Uint8(value) => self.u32(value as u32),
+            NvPairValue::Int16(value) => self.u32(value as i32 as u32),
+            NvPairValue::Uint16(value) => self.u32(value as u32),
+            NvPairValue::Int32(value) => self.u32(value as u32),
+            NvPairValue::Uint32(value) => self.u32(value),
+            NvPairValue::Int64(value) | NvPairValue::Hrtime(value) => self.u64(value as u64),
+            NvPairValue::Uint64(value) => self.u64(value),
+            NvPairValue::Double(value) => self.u64(value.to_bits()),
+            NvPairValue::String(ref value) => self.xdr_string(value)?,
+            NvPairValue::ByteArray(ref value) => {
+                self.buf.extend_from_slice(value);
+                self.pad4();
+            }
+            NvPairValue::BooleanArray(ref value) => {
+                xdr_array(self, value.len());
+                value.iter().for_each(|&item| self.u32(item as u32));
+            }
+            NvPairValue::Int8Array(ref value) => {
+                xdr_array(self, value.len());
+                value.iter().for_each(|&item| self.u32(item as i32 as u32));
+            }
+            NvPairValue::Uint8Array(ref value) => {
+                xdr_array(self, value.len());
+                value.iter().for_each(|&item| self.u32(item as u32));
+            }
+            NvPairValue::Int16Array(ref value) => {
+                xdr_array(self, value.len());
+                value.iter().for_each(|&item| self.u32(item as i32 as u32));
+            }
+            NvPairValue::Uint16Array(ref value) => {
+                xdr_array(self, value.len());
+                value.iter().for_each(|&item| self.u32(item as u32));
+            }
+            NvPairValue::Int32Array(ref value) => {
+                xdr_array(self, value.len());
+                value.iter().for_each(|&item| self.u32(item as u32));
+            }
+            NvPairValue::Uint32Array(ref value) => {
+                xdr_array(self, value.len());
+                value.iter().for_each(|&item| self.u32(item));
+            }
+            NvPairValue::Int64Array(ref value) => {
+                xdr_array(self, value.len());
+                value.iter().for_each(|&item| self.u64(item as u64));
+            }
+            NvPairValue::Uint64Array(ref value) => {
+                xdr_array(self, value.len());
+                value.iter().for_each(|&item| self.u64(item));
+            }
+            NvPairValue::StringArray(ref value) => {
+                for item in value {
+                    self.xdr_string(item)?;
+                }
+            }
+            NvPairValue::NvList(_) | NvPairValue::NvListArray(_) => self.nested(value)?,
+        }
+        // The encoded size covers the whole pair, nested lists included
+        let encoded_size = self.buf.len() - size_at;
+        if encoded_size > i32::MAX as usize || decoded_size > i32::MAX as usize {
+            return invalid();
+        }
+        self.buf[size_at..size_at + 4].copy_from_slice(&(encoded_size as u32).to_be_bytes());
+        Ok(())
+    }
+
+    fn native_pair(&mut self, name: &str, value: &NvPairValue) -> NvResult<()> {
+        let size = nvp_size(name, value);
+        if name.contains('\0') || name.len() + 1 > i16::MAX as usize || size > i32::MAX as usize {
+            return invalid();
+        }
+        let start = self.buf.len();
+        self.u32(size as u32);
+        self.buf.extend_from_slice(&((name.len() + 1) as i16).to_ne_bytes());
+        self.buf.extend_from_slice(&[0, 0]);
+        self.u32(value.nelem() as u32);
+        self.u32(value.data_type() as u32);
+        self.buf.extend_from_slice(name.as_bytes());
+        self.buf.push(0);
+        // The value is aligned within the pair, which need not be aligned
+        // within the stream
+        self.buf.resize(start + align8(NVP_HEADER_SIZE + name.len() + 1), 0);
+        match *value {
+            NvPairValue::Boolean => {}
+            NvPairValue::BooleanValue(value) => self.u32(value as u32),
+            NvPairValue::Byte(value) | NvPairValue::Uint8(value) => self.buf.push(value),
+            NvPairValue::Int8(value) => self.buf.push(value as u8),
+            NvPairValue::Int16(value) => self.buf.extend_from_slice(&value.to_ne_bytes()),
+            NvPairValue::Uint16(value) => self.buf.extend_from_slice(&value.to_ne_bytes()),
+            NvPairValue::Int32(value) => self.u32(value as u32),
+            NvPairValue::Uint32(value) => self.u32(value),
+            NvPairValue::Int64(value) | NvPairValue::Hrtime(value) => self.u64(value as u64),
+            NvPairValue::Uint64(value) => self.u64(value),
+            NvPairValue::Double(value) => self.u64(value.to_bits()),
+            NvPairValue::String(ref value) => {
+                if value.contains('\0') {
+                    return invalid();
+                }
+                self.buf.extend_from_slice(value.as_bytes());
+                self.buf.push(0);
+            }
+            NvPairValue::ByteArray(ref value) |
+            NvPairValue::Uint8Array(ref value) => self.buf.extend_from_slice(value),
+            NvPairValue::Int8Array(ref value) => {
+                self.buf.extend(value.iter().map(|&item| item as u8))
+            }
+            NvPairValue::BooleanArray(ref value) => {
+                value.iter().for_each(|&item| self.u32(item as u32))
+            }
+            NvPairValue::Int16Array(ref value) => {
+                value.iter().for_each(|item| self.buf.extend_from_slice(&item.to_ne_bytes()))
+            }
+            NvPairValue::Uint16Array(ref value) => {
+                value.iter().for_each(|item| self.buf.extend_from_slice(&item.to_ne_bytes()))
+            }
+            NvPairValue::Int32Array(ref value) => {
+                value.iter().for_each(|&item| self.u32(item as u32))
+            }
+            NvPairValue::Uint32Array(ref value) => value.iter().for_each(|&item| self.u32(item)),
+            NvPairValue::Int64Array(ref value) => {
+                value.iter().for_each(|&item| self.u64(item as u64))
+            }
+            NvPairValue::Uint64Array(ref value) => value.iter().for_each(|&item| self.u64(item)),
+            NvPairValue::StringArray(ref value) => {
+                // Room for the pointers, which are meaningless once packed
+                self.buf.resize(self.buf.len() + 8 * value.len(), 0);
+                for item in value {
+                    if item.contains('\0') {
+                        return invalid();
+                    }
+                    self.buf.extend_from_slice(item.as_bytes());
+                    self.buf.push(0);
+                }
+            }
+            NvPairValue::NvList(ref list) => self.native_nvlist(list),
+            NvPairValue::NvListArray(ref lists) => {
+                self.buf.resize(self.buf.len() + 8 * lists.len(), 0);
+                for list in lists {
+                    self.native_nvlist(list);
+                }
+            }
+        }
+        self.buf.resize(start + size, 0);
+        self.nested(value)
+    }
+
+    /// A native `nvlist_t`, with its private pointer cleared
+    fn native_nvlist(&mut self, list: &NvPairList) {
+        self.u32(NV_VERSION as u32);
+        self.u32(list.flags);
+        self.buf.extend_from_slice(&[0; NVL_SIZE - 8]);
+    }
+}
@@ -0,0 +1,493 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+
+//! Pure Rust implementation of the libnv packed format
+//!
+//! A packed `nvlist` is a header followed by its pairs in order. A pair
+//! holding an `nvlist` is followed directly by the header and pairs of
+//! that list, closed by an `NV_TYPE_NVLIST_UP` marker. Each element of
+//! an `nvlist` array is closed by an `NV_TYPE_NVLIST_ARRAY_NEXT` marker.
+
+use common::{Endian, NvErr, NvFlag, NvLimit, NvResult, NV_NAME_MAX};
+use value::{NvTree, NvValue};
+use view::{NvDescriptors, NvNumbers, NvStrings, NvValueRef};
+use std::os::unix::io::RawFd;
+use std::{mem, slice, str};
+
+pub const NVLIST_HEADER_MAGIC: u8 = 0x6c;
+pub const NVLIST_HEADER_VERSION: u8 = 0x00;
+pub const NVLIST_HEADER_SIZE: usize = 19;
+pub const NVPAIR_HEADER_SIZE: usize = 19;
+pub const NV_FLAG_PUBLIC_MASK: u8 = 0x03;
+pub const NV_FLAG_BIG_ENDIAN: u8 = 0x80;
+pub const NV_TYPE_NVLIST_ARRAY_NEXT: u8 = 254;
+pub const NV_TYPE_NVLIST_UP: u8 = 255;
+
+/// Limits applied while decoding a packed `nvlist`
+///
+/// Every size read from the buffer is checked against these limits
+/// before anything is allocated for it, so a hostile peer can not make
+/// the decoder allocate more than roughly `max_bytes` of memory.
+///
+/// ```
+/// use nv::UnpackOptions;
+///
+/// let options = UnpackOptions { max_depth: 4, ..UnpackOptions::default() };
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct UnpackOptions {
+    /// Maximum size of the packed buffer, including its header
+    pub max_bytes: usize,
+    /// Maximum nesting depth, where the top level list has a depth of 0
+    pub max_depth: usize,
+    /// Maximum number of name/value pairs, counted across nested lists
+    pub max_pairs: usize,
+    /// Maximum number of elements in a single array value
+    pub max_array_len: usize,
+    /// Maximum length in bytes of a single string, excluding the
+    /// terminating NUL
+    pub max_string_len: usize,
+    /// Maximum number of descriptors carried by the list
+    pub max_descriptors: usize,
+}
+
+impl Default for UnpackOptions {
+    fn default() -> UnpackOptions {
+        UnpackOptions {
+            max_bytes: 16 << 20,
+            max_depth: 32,
+            max_pairs: 1 << 16,
+            max_array_len: 1 << 16,
+            max_string_len: 1 << 20,
+            max_descriptors: 256,
+        }
+    }
+}
+
+/// The fixed size header preceding every packed `nvlist`
+#[derive(Copy, Clone, Debug)]
+pub struct ListHeader {
+    pub flags: NvFlag,
+    /// The byte order of every integer in the list, this header included
+    pub endian: Endian,
+    pub descriptors: u64,
+    pub size: u64,
+}
+
+impl ListHeader {
+    /// Parse a header found at `offset`, without checking its size
+    /// against the rest of the buffer
+    pub fn parse(buf: &[u8], offset: usize) -> NvResult<ListHeader> {
+        if buf.len() < NVLIST_HEADER_SIZE {
+            return Err(NvErr::Truncated(offset));
+        }
+        if buf[0] != NVLIST_HEADER_MAGIC || buf[1] != NVLIST_HEADER_VERSION ||
+           buf[2] & !(NV_FLAG_PUBLIC_MASK | NV_FLAG_BIG_ENDIAN) != 0 {
+            return Err(NvErr::InvalidFormat(offset));
+        }
+        let endian = if buf[2] & NV_FLAG_BIG_ENDIAN != 0 {
+            Endian::Big
+        } else {
+            Endian::Little
+        };
+        Ok(ListHeader {
+            flags: NvFlag::from_i32((buf[2] & NV_FLAG_PUBLIC_MASK) as i32).unwrap(),
+            endian,
+            descriptors: endian.read_u64(&buf[3..11]),
+            size: endian.read_u64(&buf[11..19]),
+        })
+    }
+
+    /// Parse the header at the start of a packed list whose body has not
+    /// been received yet, checking the sizes it announces against
+    /// `options`
+    pub fn check(buf: &[u8], options: &UnpackOptions) -> NvResult<ListHeader> {
+        let header = ListHeader::parse(buf, 0)?;
+        let max_body = options.max_bytes.saturating_sub(NVLIST_HEADER_SIZE) as u64;
+        if header.size > max_body {
+            return Err(NvErr::LimitExceeded(NvLimit::Bytes));
+        }
+        if header.descriptors > options.max_descriptors as u64 {
+            return Err(NvErr::LimitExceeded(NvLimit::Descriptors));
+        }
+        Ok(header)
+    }
+}
+
+/// The fixed size header preceding every packed pair
+#[derive(Copy, Clone, Debug)]
+pub struct PairHeader {
+    pub ty: u8,
+    pub data_size: u64,
+    pub nitems: u64,
+}
+
+/// Bounds checked cursor over a packed buffer
+pub struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    endian: Endian,
+}
+
+impl<'a> Reader<'a> {
+    /// Read a buffer whose integers are in the given byte order
+    pub fn new(buf: &'a [u8], endian: Endian) -> Reader<'a> {
+        Reader { buf, pos: 0, endian }
+    }
+
+    pub fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    /// The bytes not read yet
+    pub fn rest(&self) -> &'a [u8] {
+        &self.buf[self.pos..]
+    }
+
+    pub fn bytes(&mut self, len: usize) -> NvResult<&'a [u8]> {
+        if self.remaining() < len {
+            return Err(NvErr::Truncated(self.pos));
+        }
+        let bytes = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /// Read an `nvlist` header, which must describe exactly the rest
+    /// of the buffer and at most `nfds` descriptors, in the byte order
+    /// of the reader
+    pub fn list_header(&mut self, nfds: usize) -> NvResult<ListHeader> {
+        let start = self.pos;
+        let header = ListHeader::parse(self.bytes(NVLIST_HEADER_SIZE)?, start)?;
+        if header.endian != self.endian || header.size != self.remaining() as u64 ||
+           header.descriptors > nfds as u64 {
+            return Err(NvErr::InvalidFormat(start));
+        }
+        Ok(header)
+    }
+
+    /// Read a pair header and the name following it
+    pub fn pair_header(&mut self) -> NvResult<(PairHeader, &'a str)> {
+        let start = self.pos;
+        let raw = self.bytes(NVPAIR_HEADER_SIZE)?;
+        let header = PairHeader {
+            ty: raw[0],
+            data_size: self.endian.read_u64(&raw[3..11]),
+            nitems: self.endian.read_u64(&raw[11..19]),
+        };
+        let name_size = self.endian.read_u16(&raw[1..3]) as usize;
+        match header.ty {
+            1..=12 | NV_TYPE_NVLIST_ARRAY_NEXT | NV_TYPE_NVLIST_UP => {}
+            _ => return Err(NvErr::InvalidFormat(start)),
+        }
+        if name_size == 0 || name_size > NV_NAME_MAX as usize {
+            return Err(NvErr::InvalidFormat(start));
+        }
+        let name = c_str(self.bytes(name_size)?).ok_or(NvErr::InvalidFormat(start))?;
+        if header.data_size > self.remaining() as u64 {
+            return Err(NvErr::Truncated(start));
+        }
+        Ok((header, name))
+    }
+}
+
+/// Receiver of the contents of a packed `nvlist` while `walk` validates it
+pub trait Visit<'a> {
+    /// The top level list begins
+    fn start(&mut self, flags: NvFlag);
+    /// A pair that does not hold nested lists
+    fn value(&mut self, name: &'a str, value: NvValueRef<'a>);
+    /// A nested list begins, which is the first element of an `nvlist`
+    /// array of the given length if `array` is set
+    fn begin(&mut self, name: &'a str, flags: NvFlag, array: Option<usize>);
+    /// The next element of the current `nvlist` array begins
+    fn next_element(&mut self, flags: NvFlag);
+    /// The current nested list or `nvlist` array ends
+    fn end(&mut self);
+}
+
+/// Validation without collecting anything
+impl<'a> Visit<'a> for () {
+    fn start(&mut self, _: NvFlag) {}
+    fn value(&mut self, _: &'a str, _: NvValueRef<'a>) {}
+    fn begin(&mut self, _: &'a str, _: NvFlag, _: Option<usize>) {}
+    fn next_element(&mut self, _: NvFlag) {}
+    fn end(&mut self) {}
+}
+
+/// Validate a packed `nvlist`, reporting its contents to `visit`
+///
+/// Descriptors in the list are indices into `fds`. Validation is
+/// iterative, so deeply nested input can not exhaust the stack, and
+/// every size is checked against `options` before it is trusted.
+pub fn walk<'a, V: Visit<'a>>(buf: &'a [u8],
+                              fds: &'a [RawFd],
+                              options: &UnpackOptions,
+                              visit: &mut V)
+                              -> NvResult<()> {
+    if buf.len() > options.max_bytes {
+        return Err(NvErr::LimitExceeded(NvLimit::Bytes));
+    }
+    // Nested lists are packed in the byte order of the top level one
+    let mut reader = Reader::new(buf, ListHeader::parse(buf, 0)?.endian);
+    let header = reader.list_header(fds.len())?;
+    if header.descriptors > options.max_descriptors as u64 {
+        return Err(NvErr::LimitExceeded(NvLimit::Descriptors));
+    }
+    visit.start(header.flags);
+    // For every open nested list, the number of `nvlist` array elements
+    // still to come after it, or `None` if it is the value of a pair
+    let mut stack: Vec<Option<u64>> = Vec::new();
+    let mut pairs = 0;
+    while reader.remaining() > 0 {
+        let start = reader.position();
+        let (pair, name) = reader.pair_header()?;
+        match pair.ty {
+            NV_TYPE_NVLIST_UP => {
+                match stack.pop() {
+                    Some(None) => visit.end(),
+                    _ => return Err(NvErr::InvalidFormat(start)),
+                }
+            }
+            NV_TYPE_NVLIST_ARRAY_NEXT => {
+                match stack.pop() {
+                    Some(Some(0)) => visit.end(),
+                    Some(Some(left)) => {
+                        visit.next_element(reader.list_header(fds.len())?.flags);
+                        stack.push(Some(left - 1));
+                    }
+                    _ => return Err(NvErr::InvalidFormat(start)),
+                }
+            }
+            ty => {
+                pairs += 1;
+                if pairs > options.max_pairs {
+                    return Err(NvErr::LimitExceeded(NvLimit::Pairs));
+                }
+                let array = match ty {
+                    // Nothing of a nested list is packed in its own pair
+                    5 if pair.data_size == 0 => None,
+                    5 => return Err(NvErr::InvalidFormat(start)),
+                    11 => {
+                        // The data size of an nvlist array is the size of
+                        // its pointer array in the sender, nothing of it
+                        // is actually packed
+                        let nitems = pair.nitems;
+                        if nitems == 0 || nitems.checked_mul(8) != Some(pair.data_size) {
+                            return Err(NvErr::InvalidFormat(start));
+                        }
+                        if nitems > options.max_array_len as u64 {
+                            return Err(NvErr::LimitExceeded(NvLimit::ArrayLength));
+                        }
+                        Some(nitems)
+                    }
+                    _ => {
+                        let value = decode_value(&mut reader, pair, start, fds, options)?;
+                        visit.value(name, value);
+                        continue;
+                    }
+                };
+                if stack.len() >= options.max_depth {
+                    return Err(NvErr::LimitExceeded(NvLimit::Depth));
+                }
+                let flags = reader.list_header(fds.len())?.flags;
+                visit.begin(name, flags, array.map(|nitems| nitems as usize));
+                stack.push(array.map(|nitems| nitems - 1));
+            }
+        }
+    }
+    if !stack.is_empty() {
+        return Err(NvErr::Truncated(reader.position()));
+    }
+    Ok(())
+}
+
+/// What to do with the list being built once it ends
+enum Nested {
+    /// The list is the value of the named pair
+    List(String),
+    /// The list is the last element of the named array, following the
+    /// elements built so far
+    Array(String, Vec<NvTree>),
+}
+
+/// Builds an `NvTree` out of a validated buffer
+struct TreeBuilder {
+    current: NvTree,
+    stack: Vec<(NvTree, Nested)>,
+}
+
+impl<'a> Visit<'a> for TreeBuilder {
+    fn start(&mut self, flags: NvFlag) {
+        self.current = NvTree::new(flags);
+    }
+
+    fn value(&mut self, name: &'a str, value: NvValueRef<'a>) {
+        self.current.push(name, value.to_value());
+    }
+
+    fn begin(&mut self, name: &'a str, flags: NvFlag, array: Option<usize>) {
+        let nested = match array {
+            Some(nitems) => Nested::Array(name.to_owned(), Vec::with_capacity(nitems)),
+            None => Nested::List(name.to_owned()),
+        };
+        let parent = mem::replace(&mut self.current, NvTree::new(flags));
+        self.stack.push((parent, nested));
+    }
+
+    fn next_element(&mut self, flags: NvFlag) {
+        let element = mem::replace(&mut self.current, NvTree::new(flags));
+        if let Some(&mut (_, Nested::Array(_, ref mut done))) = self.stack.last_mut() {
+            done.push(element);
+        }
+    }
+
+    fn end(&mut self) {
+        if let Some((parent, nested)) = self.stack.pop() {
+            let list = mem::replace(&mut self.current, parent);
+            match nested {
+                Nested::List(name) => self.current.push(&name, NvValue::NvList(list)),
+                Nested::Array(name, mut done) => {
+                    done.push(list);
+                    self.current.push(&name, NvValue::NvListArray(done));
+                }
+            }
+        }
+    }
+}
+
+impl NvTree {
+    /// Decode a packed `nvlist` without going through libnv
+    ///
+    /// Descriptors in the list are indices into `fds`. Decoding is
+    /// iterative, so deeply nested input can not exhaust the stack, and
+    /// every size is checked against `options` before it is trusted.
+    ///
+    /// ```
+    /// use nv::{NvErr, NvTree, UnpackOptions};
+    ///
+    /// match NvTree::unpack(&[0x6c, 0x00], &[], &UnpackOptions::default()) {
+    ///     Err(NvErr::Truncated(0)) => {}
+    ///     res => panic!("unexpected {:?}", res),
+    /// }
+    /// ```
+    pub fn unpack(buf: &[u8], fds: &[RawFd], options: &UnpackOptions) -> NvResult<NvTree> {
+        let mut builder = TreeBuilder {
+            current: NvTree::new(NvFlag::None),
+            stack: Vec::new(),
+        };
+        walk(buf, fds, options, &mut builder)?;
+        Ok(builder.current)
+    }
+}
+
+/// Decode the data of a pair that does not hold nested lists
+pub fn decode_value<'a>(reader: &mut Reader<'a>,
+                        pair: PairHeader,
+                        start: usize,
+                        fds: &'a [RawFd],
+                        options: &UnpackOptions)
+                        -> NvResult<NvValueRef<'a>> {
+    let invalid = NvErr::InvalidFormat(start);
+    let endian = reader.endian();
+    let data = reader.bytes(pair.data_size as usize)?;
+    let is_array = pair.ty >= 8;
+    if is_array {
+        if pair.nitems == 0 {
+            return Err(invalid);
+        }
+        if pair.nitems > options.max_array_len as u64 {
+            return Err(NvErr::LimitExceeded(NvLimit::ArrayLength));
+        }
+    }
+    let nitems = pair.nitems as usize;
+    let value = match pair.ty {
+        1 if data.is_empty() => NvValueRef::Null,
+        2 if data.len() == 1 => NvValueRef::Bool(decode_bool(data[0]).ok_or(invalid)?),
+        3 if data.len() == 8 => NvValueRef::Number(endian.read_u64(data)),
+        4 => NvValueRef::String(decode_string(data, invalid, options)?),
+        6 if data.len() == 8 => {
+            NvValueRef::Descriptor(decode_descriptor(data, fds, endian).ok_or(invalid)?)
+        }
+        7 if !data.is_empty() => NvValueRef::Binary(data),
+        8 if data.len() == nitems => {
+            for &byte in data {
+                decode_bool(byte).ok_or(invalid)?;
+            }
+            // Every byte is either 0 or 1, so they are all valid `bool`s
+            NvValueRef::BoolArray(unsafe {
+                slice::from_raw_parts(data.as_ptr() as *const bool, data.len())
+            })
+        }
+        9 if data.len() / 8 == nitems && data.len() % 8 == 0 => {
+            NvValueRef::NumberArray(NvNumbers::new(data, endian))
+        }
+        10 if nitems <= data.len() => {
+            let mut count = 0;
+            let mut rest = data;
+            while !rest.is_empty() {
+                let end = rest.iter().position(|&byte| byte == 0).ok_or(invalid)? + 1;
+                decode_string(&rest[..end], invalid, options)?;
+                rest = &rest[end..];
+                count += 1;
+            }
+            if count != nitems {
+                return Err(invalid);
+            }
+            NvValueRef::StringArray(NvStrings::new(data, nitems))
+        }
+        12 if data.len() / 8 == nitems && data.len() % 8 == 0 => {
+            for index in data.chunks(8) {
+                decode_descriptor(index, fds, endian).ok_or(invalid)?;
+            }
+            NvValueRef::DescriptorArray(NvDescriptors::new(data, fds, endian))
+        }
+        _ => return Err(invalid),
+    };
+    Ok(value)
+}
+
+fn decode_bool(byte: u8) -> Option<bool> {
+    match byte {
+        0 => Some(false),
+        1 => Some(true),
+        _ => None,
+    }
+}
+
+fn decode_string<'a>(data: &'a [u8],
+                     invalid: NvErr,
+                     options: &UnpackOptions)
+                     -> NvResult<&'a str> {
+    if data.len() > options.max_string_len.saturating_add(1) {
+        return Err(NvErr::LimitExceeded(NvLimit::StringLength));
+    }
+    c_str(data).ok_or(invalid)
+}
+
+pub fn decode_descriptor(data: &[u8], fds: &[RawFd], endian: Endian) -> Option<RawFd> {
+    let index = endian.read_u64(data) as i64;
+    if index < 0 {
+        None
+    } else {
+        fds.get(index as usize).cloned()
+    }
+}
+
+/// Interpret `data` as a UTF-8 string with a single, trailing NUL
+pub fn c_str(data: &[u8]) -> Option<&str> {
+    match data.split_last() {
+        Some((&0, bytes)) if !bytes.contains(&0) => str::from_utf8(bytes).ok(),
+        _ => None,
+    }
+}
@@ -0,0 +1,206 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+
+use common::{NvFlag, NvType};
+#[cfg(feature = "libnv")]
+use nvlist::NvList;
+use value::{names_match, NvTree, NvValue};
+use view::NvListView;
+
+/// Read access shared by `NvList`, `NvTree` and `NvListView`
+///
+/// Code written against `NvRead` works the same on a list owned by libnv,
+/// one decoded into Rust memory and one viewed in place in a packed
+/// buffer. As in libnv, the typed getters return the first pair that has
+/// both the given name and the requested type.
+///
+/// ```
+/// use nv::{NvFlag, NvRead, NvTree, NvValue};
+///
+/// fn the_answer<L: NvRead>(list: &L) -> Option<u64> {
+///     list.get_number("the answer")
+/// }
+///
+/// let mut tree = NvTree::new(NvFlag::None);
+/// tree.push("the answer", NvValue::Number(42));
+///
+/// assert_eq!(the_answer(&tree), Some(42));
+/// ```
+pub trait NvRead {
+    /// The type of the nested lists returned by `get_nvlist`
+    type NvList: NvRead;
+
+    /// The flags the list was created with
+    fn flags(&self) -> NvFlag;
+
+    /// Determines if the list is empty
+    fn is_empty(&self) -> bool;
+
+    /// Returns `true` if a name/value pair exists in the list
+    fn exists(&self, name: &str) -> bool;
+
+    /// Returns `true` if a name/value pair of the specified type exists in
+    /// the list
+    fn exists_type(&self, name: &str, ty: NvType) -> bool;
+
+    /// Get the first `bool` value paired with the given name
+    fn get_bool(&self, name: &str) -> Option<bool>;
+
+    /// Get the first `u64` value paired with the given name
+    fn get_number(&self, name: &str) -> Option<u64>;
+
+    /// Get the first string paired with the given name
+    fn get_str(&self, name: &str) -> Option<&str>;
+
+    /// Get the first binary buffer paired with the given name
+    fn get_binary(&self, name: &str) -> Option<&[u8]>;
+
+    /// Get the first nested list paired with the given name
+    fn get_nvlist(&self, name: &str) -> Option<Self::NvList>;
+}
+
+impl NvTree {
+    fn find(&self, name: &str, ty: NvType) -> Option<&NvValue> {
+        self.iter()
+            .find(|&(key, value)| value.ty() == ty && names_match(self.flags(), key, name))
+            .map(|(_, value)| value)
+    }
+}
+
+impl NvRead for NvTree {
+    type NvList = NvTree;
+
+    fn flags(&self) -> NvFlag {
+        NvTree::flags(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        NvTree::is_empty(self)
+    }
+
+    fn exists(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    fn exists_type(&self, name: &str, ty: NvType) -> bool {
+        self.find(name, ty).is_some()
+    }
+
+    fn get_bool(&self, name: &str) -> Option<bool> {
+        match self.find(name, NvType::Bool) {
+            Some(&NvValue::Bool(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn get_number(&self, name: &str) -> Option<u64> {
+        match self.find(name, NvType::Number) {
+            Some(&NvValue::Number(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn get_str(&self, name: &str) -> Option<&str> {
+        match self.find(name, NvType::String) {
+            Some(NvValue::String(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn get_binary(&self, name: &str) -> Option<&[u8]> {
+        match self.find(name, NvType::Binary) {
+            Some(NvValue::Binary(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn get_nvlist(&self, name: &str) -> Option<NvTree> {
+        match self.find(name, NvType::NvList) {
+            Some(NvValue::NvList(value)) => Some(value.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> NvRead for NvListView<'a> {
+    type NvList = NvListView<'a>;
+
+    fn flags(&self) -> NvFlag {
+        NvListView::flags(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        NvListView::is_empty(self)
+    }
+
+    fn exists(&self, name: &str) -> bool {
+        NvListView::exists(self, name)
+    }
+
+    fn exists_type(&self, name: &str, ty: NvType) -> bool {
+        NvListView::exists_type(self, name, ty)
+    }
+
+    fn get_bool(&self, name: &str) -> Option<bool> {
+        NvListView::get_bool(self, name)
+    }
+
+    fn get_number(&self, name: &str) -> Option<u64> {
+        NvListView::get_number(self, name)
+    }
+
+    fn get_str(&self, name: &str) -> Option<&str> {
+        NvListView::get_str(self, name)
+    }
+
+    fn get_binary(&self, name: &str) -> Option<&[u8]> {
+        NvListView::get_binary(self, name)
+    }
+
+    fn get_nvlist(&self, name: &str) -> Option<NvListView<'a>> {
+        NvListView::get_nvlist(self, name)
+    }
+}
+
+#[cfg(feature = "libnv")]
+impl NvRead for NvList {
+    type NvList = NvList;
+
+    fn flags(&self) -> NvFlag {
+        NvList::flags(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        NvList::is_empty(self)
+    }
+
+    fn exists(&self, name: &str) -> bool {
+        NvList::exists(self, name)
+    }
+
+    fn exists_type(&self, name: &str, ty: NvType) -> bool {
+        NvList::exists_type(self, name, ty)
+    }
+
+    fn get_bool(&self, name: &str) -> Option<bool> {
+        NvList::get_bool(self, name)
+    }
+
+    fn get_number(&self, name: &str) -> Option<u64> {
+        NvList::get_number(self, name)
+    }
+
+    fn get_str(&self, name: &str) -> Option<&str> {
+        NvList::get_str(self, name)
+    }
+
+    fn get_binary(&self, name: &str) -> Option<&[u8]> {
+        NvList::get_binary(self, name)
+    }
+
+    fn get_nvlist(&self, name: &str) -> Option<NvList> {
+        NvList::get_nvlist(self, name)
+    }
+}
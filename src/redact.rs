@@ -0,0 +1,300 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+
+//! Redaction of lists before they are logged
+
+use common::NvFlag;
+#[cfg(feature = "envelope")]
+use hmac::{Hmac, Mac};
+#[cfg(feature = "envelope")]
+use sha2::Sha256;
+use value::{NvTree, NvValue};
+use walk::{NvPath, NvPathElem, Visitor};
+use std::fmt;
+use std::sync::Arc;
+
+/// A hash of binary values, for `RedactRules::binary_hash`
+pub type BinaryHash = Arc<dyn Fn(&[u8]) -> String + Send + Sync>;
+
+/// Which pairs `NvTree::redacted` hides, and what it replaces them with
+///
+/// Patterns are globs where `*` matches any run of characters and `?`
+/// matches one of them. A pattern holding a `.` or a `[` is matched
+/// against the path of each pair, as displayed by `NvPath` like
+/// `vdevs[0].key`, and there neither matches a `.`, so the `.`s of the
+/// pattern and the path line up. Any other pattern is matched against
+/// the whole name of pairs at every depth, `.`s included.
+///
+/// Binary values are replaced by their length. If `binary_hash` is set,
+/// whatever it makes of them follows, so that equal values can be told
+/// apart from different ones without logging them. It is not set by
+/// default, since an unkeyed hash of a short secret is as good as the
+/// secret: use a keyed hash such as `RedactRules::hmac_sha256`.
+///
+/// ```
+/// use nv::RedactRules;
+///
+/// let rules = RedactRules::new(&["*password*", "tls.key"]);
+/// assert_eq!(rules.marker, "<redacted>");
+/// assert!(rules.binary_hash.is_none());
+/// ```
+#[derive(Clone)]
+pub struct RedactRules {
+    /// The patterns of the pairs to redact
+    pub patterns: Vec<String>,
+    /// The string replacing the value of redacted pairs
+    pub marker: String,
+    /// The hash of binary values to show along with their length
+    pub binary_hash: Option<BinaryHash>,
+}
+
+impl RedactRules {
+    /// Rules redacting the pairs matching any of `patterns`
+    pub fn new(patterns: &[&str]) -> RedactRules {
+        RedactRules {
+            patterns: patterns.iter().map(|&pattern| pattern.to_owned()).collect(),
+            ..RedactRules::default()
+        }
+    }
+
+    fn matches(&self, path: &NvPath, name: &str) -> bool {
+        let mut full = None;
+        self.patterns.iter().any(|pattern| {
+            if pattern.contains(['.', '[']) {
+                let full = full.get_or_insert_with(|| path.to_string());
+                glob_match(pattern.as_bytes(), full.as_bytes())
+            } else {
+                segment_match(pattern.as_bytes(), name.as_bytes())
+            }
+        })
+    }
+
+    /// A `binary_hash` showing the first 8 bytes of the HMAC-SHA256 of
+    /// values under `key`, in hex
+    ///
+    /// This needs the `envelope` feature.
+    ///
+    /// ```
+    /// use nv::RedactRules;
+    ///
+    /// let rules = RedactRules {
+    ///     binary_hash: Some(RedactRules::hmac_sha256(b"log key")),
+    ///     ..RedactRules::default()
+    /// };
+    /// let hash = rules.binary_hash.unwrap();
+    /// assert!(hash(b"abc").starts_with("hmac-sha256:"));
+    /// assert_eq!(hash(b"abc"), hash(b"abc"));
+    /// assert_ne!(hash(b"abc"), RedactRules::hmac_sha256(b"other key")(b"abc"));
+    /// ```
+    #[cfg(feature = "envelope")]
+    pub fn hmac_sha256(key: &[u8]) -> BinaryHash {
+        // HMAC takes keys of any length
+        let mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+        Arc::new(move |value: &[u8]| {
+            let mut mac = mac.clone();
+            mac.update(value);
+            let tag = mac.finalize().into_bytes();
+            let hex: Vec<String> = tag[..8].iter().map(|byte| format!("{:02x}", byte)).collect();
+            format!("hmac-sha256:{}", hex.concat())
+        })
+    }
+
+    /// The string a binary value is replaced by
+    fn binary_marker(&self, value: &[u8]) -> String {
+        match self.binary_hash {
+            Some(ref hash) => format!("<{} bytes {}>", value.len(), hash(value)),
+            None => format!("<{} bytes>", value.len()),
+        }
+    }
+}
+
+impl fmt::Debug for RedactRules {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RedactRules")
+            .field("patterns", &self.patterns)
+            .field("marker", &self.marker)
+            .field("binary_hash", &self.binary_hash.as_ref().map(|_| "..."))
+            .finish()
+    }
+}
+
+impl Default for RedactRules {
+    fn default() -> RedactRules {
+        RedactRules {
+            patterns: Vec::new(),
+            marker: "<redacted>".to_owned(),
+            binary_hash: None,
+        }
+    }
+}
+
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    // Neither `*` nor `?` match a `.`, so the `.`s of the pattern and the
+    // text have to line up
+    let mut patterns = pattern.split(|&byte| byte == b'.');
+    let mut texts = text.split(|&byte| byte == b'.');
+    loop {
+        match (patterns.next(), texts.next()) {
+            (Some(pattern), Some(text)) if segment_match(pattern, text) => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+/// Match a glob against the whole of `text`, only ever going back to
+/// just past the last `*`, so it takes time linear in the product of
+/// their lengths
+fn segment_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Where matching resumes if what follows the last `*` fails to match
+    let mut resume = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(&b'*') => {
+                p += 1;
+                resume = Some((p, t));
+            }
+            Some(&byte) if byte == b'?' || byte == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match resume {
+                // Let the `*` take one more character
+                Some((after_star, taken)) => {
+                    p = after_star;
+                    t = taken + 1;
+                    resume = Some((after_star, t));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&byte| byte == b'*')
+}
+
+/// A `Visitor` building the redacted copy of a tree
+struct Redactor<'r> {
+    rules: &'r RedactRules,
+    /// The copies of the lists being visited, innermost last
+    copies: Vec<NvTree>,
+    /// The depth of a redacted pair holding lists, whose pairs are not
+    /// copied
+    skip: Option<usize>,
+}
+
+impl<'r> Redactor<'r> {
+    /// Whether an event at the depth of `path` lies within a redacted
+    /// pair, including entering or leaving its lists when `nested` is set
+    fn skipped(&mut self, path: &NvPath, nested: bool) -> bool {
+        match self.skip {
+            Some(depth) if path.len() > depth || (nested && path.len() == depth) => true,
+            _ => {
+                self.skip = None;
+                false
+            }
+        }
+    }
+
+    fn innermost(&mut self) -> &mut NvTree {
+        self.copies.last_mut().expect("the copy of the tree itself is left")
+    }
+}
+
+impl<'r, 'a> Visitor<'a, &'a NvValue> for Redactor<'r> {
+    fn value(&mut self, path: &NvPath<'a>, value: &'a NvValue) {
+        if self.skipped(path, false) {
+            return;
+        }
+        let name = match path.last() {
+            Some(&NvPathElem::Name(name)) => name,
+            _ => unreachable!("pairs always have a name"),
+        };
+        let value = if self.rules.matches(path, name) {
+            if let NvValue::NvList(_) | NvValue::NvListArray(_) = *value {
+                self.skip = Some(path.len());
+            }
+            NvValue::String(self.rules.marker.clone())
+        } else {
+            match *value {
+                NvValue::Descriptor(_) | NvValue::DescriptorArray(_) => return,
+                NvValue::Binary(ref value) => NvValue::String(self.rules.binary_marker(value)),
+                // Added once all of its pairs are copied
+                NvValue::NvList(_) => return,
+                // Filled in as each of its lists is copied
+                NvValue::NvListArray(ref lists) => {
+                    NvValue::NvListArray(Vec::with_capacity(lists.len()))
+                }
+                ref value => value.clone(),
+            }
+        };
+        self.innermost().push(name, value);
+    }
+
+    fn enter(&mut self, path: &NvPath<'a>, flags: NvFlag) {
+        if !self.skipped(path, true) {
+            self.copies.push(NvTree::new(flags));
+        }
+    }
+
+    fn leave(&mut self, path: &NvPath<'a>) {
+        if self.skipped(path, true) {
+            return;
+        }
+        let copy = self.copies.pop().expect("every list left was entered");
+        match (path.last(), self.innermost().pairs.last_mut()) {
+            (Some(&NvPathElem::Index(_)), Some(&mut (_, NvValue::NvListArray(ref mut lists)))) => {
+                lists.push(copy)
+            }
+            (Some(&NvPathElem::Name(name)), _) => self.innermost().push(name, NvValue::NvList(copy)),
+            _ => unreachable!("lists are left in their parent or their array"),
+        }
+    }
+}
+
+fn redact_tree(tree: &NvTree, rules: &RedactRules) -> NvTree {
+    let mut redactor = Redactor {
+        rules,
+        copies: vec![NvTree::new(tree.flags())],
+        skip: None,
+    };
+    tree.visit(&mut redactor);
+    redactor.copies.pop().expect("the copy of the tree itself is left")
+}
+
+impl NvTree {
+    /// A copy of the tree that is safe to log
+    ///
+    /// Pairs matching `rules` get the marker as their value, binary
+    /// values are replaced by their length and their `binary_hash`, and
+    /// descriptors are dropped, at every depth. Lists nested however deep
+    /// are copied without recursing, by visiting the tree.
+    ///
+    /// ```
+    /// use nv::{NvFlag, NvTree, NvValue, RedactRules};
+    ///
+    /// let mut tree = NvTree::new(NvFlag::None);
+    /// tree.push("user", NvValue::String("root".to_owned()));
+    /// tree.push("password", NvValue::String("hunter2".to_owned()));
+    ///
+    /// let redacted = tree.redacted(&RedactRules::new(&["pass*"]));
+    ///
+    /// assert_eq!(redacted.get("user"), tree.get("user"));
+    /// assert_eq!(redacted.get("password"), Some(&NvValue::String("<redacted>".to_owned())));
+    /// ```
+    pub fn redacted(&self, rules: &RedactRules) -> NvTree {
+        redact_tree(self, rules)
+    }
+
+    /// Keep only the pairs for which `f` returns `true`
+    ///
+    /// Only the pairs of the tree itself are passed to `f`, not those of
+    /// the lists nested in it.
+    pub fn retain<F>(&mut self, mut f: F)
+        where F: FnMut(&str, &NvValue) -> bool
+    {
+        self.pairs.retain(|(name, value)| f(name, value));
+    }
+}
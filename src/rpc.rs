@@ -0,0 +1,318 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+
+//! Request/response calls over `nvlist` sockets
+//!
+//! This is the protocol libcasper services speak. A request names the
+//! command to run in its `"cmd"` string, and the reply carries an `errno`
+//! style `"error"` number, which is zero on success. Each request also
+//! gets a `"id"` number that the server copies into its reply, so the
+//! client can tell late replies to calls that timed out from the one it
+//! is waiting for.
+//!
+//! Handlers and calls take and return `NvTree`s, or with the `libnv`
+//! feature `NvList`s through `Server::register_list` and
+//! `Client::call_list`.
+//!
+//! ```
+//! use nv::rpc::{Client, Server};
+//! use nv::{NvFlag, NvTree, NvValue};
+//! use std::os::unix::net::UnixStream;
+//! use std::thread;
+//!
+//! let (client_sock, server_sock) = UnixStream::pair().unwrap();
+//!
+//! let server = thread::spawn(move || {
+//!     let mut server = Server::new();
+//!     server.register("double", |request: &NvTree| {
+//!         match request.get("value") {
+//!             Some(&NvValue::Number(value)) => {
+//!                 let mut reply = NvTree::new(NvFlag::None);
+//!                 reply.push("value", NvValue::Number(2 * value));
+//!                 Ok(reply)
+//!             }
+//!             _ => Err(22),
+//!         }
+//!     });
+//!     server.serve(&server_sock).unwrap();
+//! });
+//!
+//! let mut client = Client::new(client_sock);
+//! let mut args = NvTree::new(NvFlag::None);
+//! args.push("value", NvValue::Number(21));
+//!
+//! let reply = client.call("double", &args).unwrap();
+//! assert_eq!(reply.get("value"), Some(&NvValue::Number(42)));
+//!
+//! drop(client);
+//! server.join().unwrap();
+//! ```
+
+use common::{NvErr, NvFlag, NvResult};
+use msgio;
+#[cfg(feature = "libnv")]
+use nvlist::NvList;
+use packed::UnpackOptions;
+use value::{NvTree, NvValue};
+use libc;
+use std::collections::HashMap;
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+/// Name of the string naming the command to run
+pub const CMD: &str = "cmd";
+/// Name of the number identifying a request and its reply
+pub const ID: &str = "id";
+/// Name of the `errno` style number in a reply
+pub const ERROR: &str = "error";
+
+/// A command handler, returning the reply or an `errno` value
+pub type Handler = Box<dyn FnMut(&NvTree) -> Result<NvTree, i32> + Send>;
+
+/// A command handler working on `NvList`s, returning the reply or an
+/// `errno` value
+#[cfg(feature = "libnv")]
+pub type ListHandler = Box<dyn FnMut(&NvList) -> Result<NvList, i32> + Send>;
+
+enum Registered {
+    Tree(Handler),
+    #[cfg(feature = "libnv")]
+    List(ListHandler),
+}
+
+/// Dispatches requests to handlers registered by command name
+#[derive(Default)]
+pub struct Server {
+    handlers: HashMap<String, Registered>,
+    options: UnpackOptions,
+    /// The last reply of a `ListHandler`, which holds the descriptors of
+    /// the tree `handle` returned for it
+    #[cfg(feature = "libnv")]
+    reply: Option<NvList>,
+}
+
+impl Server {
+    /// Create a server without any handlers, receiving requests with the
+    /// default `UnpackOptions`
+    pub fn new() -> Server {
+        Server::default()
+    }
+
+    /// Receive requests with the limits in `options` instead
+    pub fn set_options(&mut self, options: UnpackOptions) {
+        self.options = options;
+    }
+
+    /// Register the handler for `cmd`, replacing any previous one
+    pub fn register<F>(&mut self, cmd: &str, handler: F)
+        where F: FnMut(&NvTree) -> Result<NvTree, i32> + Send + 'static
+    {
+        self.handlers.insert(cmd.to_owned(), Registered::Tree(Box::new(handler)));
+    }
+
+    /// Register a handler for `cmd` that takes and returns `NvList`s,
+    /// replacing any previous one
+    ///
+    /// The handler gets copies of the descriptors in the request, which
+    /// are closed once it returns. The descriptors of its reply stay open
+    /// until the next request is handled.
+    #[cfg(feature = "libnv")]
+    pub fn register_list<F>(&mut self, cmd: &str, handler: F)
+        where F: FnMut(&NvList) -> Result<NvList, i32> + Send + 'static
+    {
+        self.handlers.insert(cmd.to_owned(), Registered::List(Box::new(handler)));
+    }
+
+    /// Run the handler for a single request and build its reply
+    ///
+    /// Requests without a command fail with `EINVAL`, and those for an
+    /// unknown command with `EOPNOTSUPP`.
+    pub fn handle(&mut self, request: &NvTree) -> NvTree {
+        #[cfg(feature = "libnv")]
+        {
+            self.reply = None;
+        }
+        let res = match request.get(CMD) {
+            Some(NvValue::String(cmd)) => {
+                match self.handlers.get_mut(cmd) {
+                    Some(&mut Registered::Tree(ref mut handler)) => handler(request),
+                    #[cfg(feature = "libnv")]
+                    Some(&mut Registered::List(ref mut handler)) => {
+                        match NvList::from_tree(request)
+                            .map_err(errno)
+                            .and_then(|request| handler(&request)) {
+                            Ok(reply) => {
+                                let tree = reply.to_tree().map_err(|_| libc::EINVAL);
+                                self.reply = Some(reply);
+                                tree
+                            }
+                            Err(error) => Err(error),
+                        }
+                    }
+                    None => Err(libc::EOPNOTSUPP),
+                }
+            }
+            _ => Err(libc::EINVAL),
+        };
+        let (mut reply, error) = match res {
+            Ok(reply) => (reply, 0),
+            Err(error) => (NvTree::new(NvFlag::None), error),
+        };
+        if let Some(&NvValue::Number(id)) = request.get(ID) {
+            reply.push(ID, NvValue::Number(id));
+        }
+        reply.push(ERROR, NvValue::Number(error as u64));
+        reply
+    }
+
+    /// Answer requests on `sock` until the client closes it
+    ///
+    /// The descriptors of a request for a `register` handler are left to
+    /// the handler, and those of a request for a `register_list` handler
+    /// are closed once it is answered.
+    pub fn serve<S: AsRawFd>(&mut self, sock: &S) -> NvResult<()> {
+        loop {
+            let request = match NvTree::recv(sock, &self.options) {
+                Ok(request) => request,
+                Err(NvErr::Errno(libc::ENOTCONN)) => return Ok(()),
+                Err(err) => return Err(err),
+            };
+            let res = self.handle(&request).send(sock);
+            #[cfg(feature = "libnv")]
+            {
+                // `NvList` handlers only got copies of its descriptors
+                if self.handled_as_list(&request) {
+                    msgio::close_all(&msgio::descriptors_of(&request));
+                }
+            }
+            res?;
+        }
+    }
+
+    #[cfg(feature = "libnv")]
+    fn handled_as_list(&self, request: &NvTree) -> bool {
+        match request.get(CMD) {
+            Some(NvValue::String(cmd)) => {
+                matches!(self.handlers.get(cmd), Some(&Registered::List(_)))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Makes calls to a `Server` on the other end of a socket
+pub struct Client<S: AsRawFd> {
+    sock: S,
+    next_id: u64,
+    timeout: Option<Duration>,
+    options: UnpackOptions,
+}
+
+impl<S: AsRawFd> Client<S> {
+    /// Create a client without a timeout, receiving replies with the
+    /// default `UnpackOptions`
+    pub fn new(sock: S) -> Client<S> {
+        Client {
+            sock,
+            next_id: 1,
+            timeout: None,
+            options: UnpackOptions::default(),
+        }
+    }
+
+    /// Fail calls with `ETIMEDOUT` if their whole reply is not in by the
+    /// timeout
+    ///
+    /// A call that times out in the middle of its reply leaves the rest of
+    /// it on the socket, so the client should be dropped then.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Receive replies with the limits in `options` instead
+    pub fn set_options(&mut self, options: UnpackOptions) {
+        self.options = options;
+    }
+
+    /// The socket the client uses
+    pub fn get_ref(&self) -> &S {
+        &self.sock
+    }
+
+    /// Run `cmd` with the pairs of `args` on the server
+    ///
+    /// The reply is returned without its `"id"` and `"error"` pairs. A
+    /// non-zero `"error"` is returned as `NvErr::Errno`, and a reply
+    /// without an `"id"` fails with `EPROTO`. Late replies to calls that
+    /// timed out are skipped, closing their descriptors.
+    pub fn call(&mut self, cmd: &str, args: &NvTree) -> NvResult<NvTree> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut request = NvTree::new(args.flags());
+        request.push(CMD, NvValue::String(cmd.to_owned()));
+        request.push(ID, NvValue::Number(id));
+        for (name, value) in args.iter() {
+            request.push(name, value.clone());
+        }
+        request.send(&self.sock)?;
+
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let sock = self.sock.as_raw_fd();
+        loop {
+            let (buf, fds) = msgio::recv_until(sock, &self.options, deadline)?;
+            let reply = msgio::decode(&buf, &fds, &self.options)?;
+            match reply.get(ID) {
+                Some(&NvValue::Number(reply_id)) if reply_id == id => {}
+                // A late reply to a call that timed out
+                Some(&NvValue::Number(_)) => {
+                    msgio::close_all(&msgio::descriptors_of(&reply));
+                    continue;
+                }
+                _ => {
+                    msgio::close_all(&msgio::descriptors_of(&reply));
+                    return Err(NvErr::Errno(libc::EPROTO));
+                }
+            }
+            let error = match reply.get(ERROR) {
+                Some(&NvValue::Number(0)) | None => return Ok(strip(&reply)),
+                Some(&NvValue::Number(error)) if error <= i32::MAX as u64 => error as i32,
+                Some(_) => libc::EINVAL,
+            };
+            msgio::close_all(&msgio::descriptors_of(&reply));
+            return Err(NvErr::Errno(error));
+        }
+    }
+
+    /// Run `cmd` with the pairs of `args` on the server, as `call` does
+    ///
+    /// The descriptors that came with the reply are copied into the
+    /// returned list and closed.
+    #[cfg(feature = "libnv")]
+    pub fn call_list(&mut self, cmd: &str, args: &NvList) -> NvResult<NvList> {
+        let args = args.to_tree().map_err(|_| NvErr::Errno(libc::EINVAL))?;
+        let reply = self.call(cmd, &args)?;
+        let list = NvList::from_tree(&reply);
+        msgio::close_all(&msgio::descriptors_of(&reply));
+        list
+    }
+}
+
+/// The `errno` value of a failed conversion
+#[cfg(feature = "libnv")]
+fn errno(err: NvErr) -> i32 {
+    match err {
+        NvErr::Errno(errno) => errno,
+        _ => libc::EINVAL,
+    }
+}
+
+/// Copy a reply without the pairs added by the protocol
+fn strip(reply: &NvTree) -> NvTree {
+    let mut tree = NvTree::new(reply.flags());
+    for (name, value) in reply.iter().filter(|&(name, _)| name != ID && name != ERROR) {
+        tree.push(name, value.clone());
+    }
+    tree
+}
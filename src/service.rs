@@ -0,0 +1,175 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+
+//! Privilege separated services in the style of libcasper
+//!
+//! `Service::spawn` forks a child connected to the parent by a socket
+//! pair. The child sets up an `rpc::Server` and answers requests until
+//! the parent goes away, so work that needs privileges or resources a
+//! sandboxed parent gave up, such as DNS or password lookups, can be kept
+//! in the child.
+//!
+//! As with any `fork` in a multithreaded program, only the calling thread
+//! exists in the child, so services should be spawned early, before
+//! threads holding locks are started.
+//!
+//! ```
+//! extern crate libc;
+//! extern crate nv;
+//!
+//! use nv::rpc::Server;
+//! use nv::service::Service;
+//! use nv::{NvFlag, NvTree, NvValue};
+//!
+//! # fn main() {
+//! let mut service = Service::spawn(|server: &mut Server| {
+//!     server.register("pid", |_: &NvTree| {
+//!         let mut reply = NvTree::new(NvFlag::None);
+//!         reply.push("pid", NvValue::Number(unsafe { libc::getpid() } as u64));
+//!         Ok(reply)
+//!     });
+//! }).unwrap();
+//!
+//! let reply = service.call("pid", &NvTree::new(NvFlag::None)).unwrap();
+//! assert_eq!(reply.get("pid"), Some(&NvValue::Number(service.pid() as u64)));
+//!
+//! assert!(service.shutdown().unwrap().success());
+//! # }
+//! ```
+
+use common::{NvErr, NvResult};
+use msgio;
+use rpc::{Client, Server};
+use value::NvTree;
+use libc;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::ExitStatusExt;
+use std::panic::{self, AssertUnwindSafe};
+use std::process::ExitStatus;
+
+/// Handle to a service running in a child process
+///
+/// Dropping the handle closes the connection, which makes the child
+/// exit once it has answered the request it is working on, and reaps it.
+pub struct Service {
+    client: Option<Client<UnixStream>>,
+    pid: libc::pid_t,
+    status: Option<ExitStatus>,
+}
+
+impl Service {
+    /// Fork a child that sets up a `Server` with `setup` and serves
+    /// requests from the returned handle
+    ///
+    /// The child exits with status 0 once the parent closes the
+    /// connection, or 1 if serving fails or a handler panics.
+    pub fn spawn<F: FnOnce(&mut Server)>(setup: F) -> NvResult<Service> {
+        let (parent, child) = UnixStream::pair().map_err(msgio::to_nv)?;
+        match unsafe { libc::fork() } {
+            -1 => Err(msgio::to_nv(io::Error::last_os_error())),
+            0 => {
+                drop(parent);
+                let res = panic::catch_unwind(AssertUnwindSafe(|| {
+                    let mut server = Server::new();
+                    setup(&mut server);
+                    server.serve(&child)
+                }));
+                let code = match res {
+                    Ok(Ok(())) => 0,
+                    _ => 1,
+                };
+                // Nothing inherited from the parent may run in the child,
+                // including atexit handlers and destructors
+                unsafe { libc::_exit(code) }
+            }
+            pid => {
+                Ok(Service {
+                    client: Some(Client::new(parent)),
+                    pid,
+                    status: None,
+                })
+            }
+        }
+    }
+
+    /// The process id of the child
+    pub fn pid(&self) -> libc::pid_t {
+        self.pid
+    }
+
+    /// The client making calls to the child, to set a timeout for example
+    pub fn client(&mut self) -> &mut Client<UnixStream> {
+        self.client.as_mut().expect("service has been shut down")
+    }
+
+    /// Run `cmd` with the pairs of `args` in the child
+    ///
+    /// If the child has died, the call fails with `ECHILD`.
+    pub fn call(&mut self, cmd: &str, args: &NvTree) -> NvResult<NvTree> {
+        match self.client().call(cmd, args) {
+            Err(NvErr::Errno(errno)) if errno == libc::ENOTCONN || errno == libc::EPIPE ||
+                                        errno == libc::ECONNRESET => {
+                // The connection only breaks when the child exits, which
+                // may not have been reported yet
+                self.wait(0)?;
+                Err(NvErr::Errno(libc::ECHILD))
+            }
+            res => res,
+        }
+    }
+
+    /// Check whether the child has exited, without blocking
+    pub fn try_wait(&mut self) -> NvResult<Option<ExitStatus>> {
+        self.wait(libc::WNOHANG)
+    }
+
+    /// Close the connection and wait for the child to exit
+    pub fn shutdown(mut self) -> NvResult<ExitStatus> {
+        self.close();
+        match self.wait(0)? {
+            Some(status) => Ok(status),
+            None => Err(NvErr::Errno(libc::ECHILD)),
+        }
+    }
+
+    fn close(&mut self) {
+        if let Some(client) = self.client.take() {
+            unsafe {
+                libc::shutdown(client.get_ref().as_raw_fd(), libc::SHUT_RDWR);
+            }
+        }
+    }
+
+    fn wait(&mut self, flags: libc::c_int) -> NvResult<Option<ExitStatus>> {
+        if self.status.is_some() {
+            return Ok(self.status);
+        }
+        let mut status = 0;
+        loop {
+            match unsafe { libc::waitpid(self.pid, &mut status, flags) } {
+                0 => return Ok(None),
+                -1 => {
+                    let err = io::Error::last_os_error();
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        return Err(msgio::to_nv(err));
+                    }
+                }
+                _ => {
+                    self.status = Some(ExitStatus::from_raw(status));
+                    return Ok(self.status);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Service {
+    fn drop(&mut self) {
+        self.close();
+        let _ = self.wait(0);
+    }
+}
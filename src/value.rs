@@ -0,0 +1,151 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+
+use common::{NvFlag, NvType};
+use std::os::unix::io::RawFd;
+use std::slice;
+
+/// A single value held by an `NvTree`
+#[derive(Clone, Debug, PartialEq)]
+pub enum NvValue {
+    /// There is no associated data with the name
+    Null,
+    /// A `bool` value
+    Bool(bool),
+    /// A `u64` value
+    Number(u64),
+    /// A string
+    String(String),
+    /// A nested list
+    NvList(NvTree),
+    /// A file descriptor
+    Descriptor(RawFd),
+    /// A binary buffer
+    Binary(Vec<u8>),
+    /// An array of `bool` values
+    BoolArray(Vec<bool>),
+    /// An array of `u64` values
+    NumberArray(Vec<u64>),
+    /// An array of strings
+    StringArray(Vec<String>),
+    /// An array of nested lists
+    NvListArray(Vec<NvTree>),
+    /// An array of file descriptors
+    DescriptorArray(Vec<RawFd>),
+}
+
+impl NvValue {
+    /// The `NvType` of the value
+    ///
+    /// ```
+    /// use nv::{NvType, NvValue};
+    ///
+    /// assert_eq!(NvValue::Number(42).ty(), NvType::Number);
+    /// ```
+    pub fn ty(&self) -> NvType {
+        match *self {
+            NvValue::Null => NvType::Null,
+            NvValue::Bool(_) => NvType::Bool,
+            NvValue::Number(_) => NvType::Number,
+            NvValue::String(_) => NvType::String,
+            NvValue::NvList(_) => NvType::NvList,
+            NvValue::Descriptor(_) => NvType::Descriptor,
+            NvValue::Binary(_) => NvType::Binary,
+            NvValue::BoolArray(_) => NvType::BoolArray,
+            NvValue::NumberArray(_) => NvType::NumberArray,
+            NvValue::StringArray(_) => NvType::StringArray,
+            NvValue::NvListArray(_) => NvType::NvListArray,
+            NvValue::DescriptorArray(_) => NvType::DescriptorArray,
+        }
+    }
+}
+
+/// A list of name/value pairs held entirely in Rust memory
+///
+/// An `NvTree` is what the pure Rust decoder produces and does not
+/// depend on libnv. Pairs are kept in insertion order and names are
+/// not checked for uniqueness. Descriptors are stored as plain
+/// `RawFd`s and are not closed when the tree is dropped.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NvTree {
+    flags: NvFlag,
+    pub(crate) pairs: Vec<(String, NvValue)>,
+}
+
+impl NvTree {
+    /// Create a new, empty `NvTree`
+    pub fn new(flags: NvFlag) -> NvTree {
+        NvTree {
+            flags,
+            pairs: Vec::new(),
+        }
+    }
+
+    /// The flags the tree was created with
+    pub fn flags(&self) -> NvFlag {
+        self.flags
+    }
+
+    /// The number of name/value pairs in the tree
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    /// Determines if the tree is empty
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    /// Append a name/value pair to the tree
+    ///
+    /// ```
+    /// use nv::{NvFlag, NvTree, NvValue};
+    ///
+    /// let mut tree = NvTree::new(NvFlag::None);
+    ///
+    /// tree.push("the answer", NvValue::Number(42));
+    ///
+    /// assert_eq!(tree.get("the answer"), Some(&NvValue::Number(42)));
+    /// ```
+    pub fn push(&mut self, name: &str, value: NvValue) {
+        self.pairs.push((name.to_owned(), value));
+    }
+
+    /// Get the first value paired with the given name, honouring
+    /// `NvFlag::IgnoreCase`
+    pub fn get(&self, name: &str) -> Option<&NvValue> {
+        self.pairs
+            .iter()
+            .find(|&(key, _)| names_match(self.flags, key, name))
+            .map(|(_, value)| value)
+    }
+
+    /// Iterate over the name/value pairs in insertion order
+    pub fn iter<'a>(&'a self) -> NvTreeIter<'a> {
+        NvTreeIter { inner: self.pairs.iter() }
+    }
+}
+
+/// Iterator over the name/value pairs of an `NvTree`
+pub struct NvTreeIter<'a> {
+    inner: slice::Iter<'a, (String, NvValue)>,
+}
+
+impl<'a> Iterator for NvTreeIter<'a> {
+    type Item = (&'a str, &'a NvValue);
+
+    fn next(&mut self) -> Option<(&'a str, &'a NvValue)> {
+        self.inner.next().map(|(name, value)| (name.as_str(), value))
+    }
+}
+
+/// Compare the names of two pairs the way a list with `flags` does
+pub fn names_match(flags: NvFlag, a: &str, b: &str) -> bool {
+    if flags == NvFlag::IgnoreCase || flags == NvFlag::All {
+        a.eq_ignore_ascii_case(b)
+    } else {
+        a == b
+    }
+}
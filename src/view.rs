@@ -0,0 +1,568 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+
+use common::{Endian, NvFlag, NvResult, NvType};
+use packed::{self, ListHeader, PairHeader, Reader, UnpackOptions, NVLIST_HEADER_MAGIC,
+             NVLIST_HEADER_SIZE, NV_TYPE_NVLIST_ARRAY_NEXT, NV_TYPE_NVLIST_UP};
+use value::{names_match, NvTree, NvValue};
+use std::fmt;
+use std::os::unix::io::RawFd;
+use std::str;
+
+/// Options used to decode values a second time, once the whole buffer
+/// has already been validated
+const VALIDATED: UnpackOptions = UnpackOptions {
+    max_bytes: usize::MAX,
+    max_depth: usize::MAX,
+    max_pairs: usize::MAX,
+    max_array_len: usize::MAX,
+    max_string_len: usize::MAX,
+    max_descriptors: usize::MAX,
+};
+
+/// A value borrowed from an `NvListView`
+#[derive(Copy, Clone, Debug)]
+pub enum NvValueRef<'a> {
+    /// There is no associated data with the name
+    Null,
+    /// A `bool` value
+    Bool(bool),
+    /// A `u64` value
+    Number(u64),
+    /// A string
+    String(&'a str),
+    /// A nested list
+    NvList(NvListView<'a>),
+    /// A file descriptor
+    Descriptor(RawFd),
+    /// A binary buffer
+    Binary(&'a [u8]),
+    /// An array of `bool` values
+    BoolArray(&'a [bool]),
+    /// An array of `u64` values
+    NumberArray(NvNumbers<'a>),
+    /// An array of strings
+    StringArray(NvStrings<'a>),
+    /// An array of nested lists
+    NvListArray(NvListViews<'a>),
+    /// An array of file descriptors
+    DescriptorArray(NvDescriptors<'a>),
+}
+
+impl<'a> NvValueRef<'a> {
+    /// The `NvType` of the value
+    pub fn ty(&self) -> NvType {
+        match *self {
+            NvValueRef::Null => NvType::Null,
+            NvValueRef::Bool(_) => NvType::Bool,
+            NvValueRef::Number(_) => NvType::Number,
+            NvValueRef::String(_) => NvType::String,
+            NvValueRef::NvList(_) => NvType::NvList,
+            NvValueRef::Descriptor(_) => NvType::Descriptor,
+            NvValueRef::Binary(_) => NvType::Binary,
+            NvValueRef::BoolArray(_) => NvType::BoolArray,
+            NvValueRef::NumberArray(_) => NvType::NumberArray,
+            NvValueRef::StringArray(_) => NvType::StringArray,
+            NvValueRef::NvListArray(_) => NvType::NvListArray,
+            NvValueRef::DescriptorArray(_) => NvType::DescriptorArray,
+        }
+    }
+
+    /// Copy the value out of the buffer
+    pub fn to_value(&self) -> NvValue {
+        match *self {
+            NvValueRef::Null => NvValue::Null,
+            NvValueRef::Bool(value) => NvValue::Bool(value),
+            NvValueRef::Number(value) => NvValue::Number(value),
+            NvValueRef::String(value) => NvValue::String(value.to_owned()),
+            NvValueRef::NvList(ref value) => NvValue::NvList(value.to_tree()),
+            NvValueRef::Descriptor(value) => NvValue::Descriptor(value),
+            NvValueRef::Binary(value) => NvValue::Binary(value.to_vec()),
+            NvValueRef::BoolArray(value) => NvValue::BoolArray(value.to_vec()),
+            NvValueRef::NumberArray(ref value) => NvValue::NumberArray(value.iter().collect()),
+            NvValueRef::StringArray(ref value) => {
+                NvValue::StringArray(value.iter().map(|item| item.to_owned()).collect())
+            }
+            NvValueRef::NvListArray(ref value) => {
+                NvValue::NvListArray(value.iter().map(|item| item.to_tree()).collect())
+            }
+            NvValueRef::DescriptorArray(ref value) => {
+                NvValue::DescriptorArray(value.iter().collect())
+            }
+        }
+    }
+}
+
+/// An array of `u64` values in a packed buffer
+///
+/// The values are not necessarily aligned, so they are read one at a time
+/// rather than exposed as a slice.
+#[derive(Copy, Clone)]
+pub struct NvNumbers<'a> {
+    data: &'a [u8],
+    endian: Endian,
+}
+
+impl<'a> NvNumbers<'a> {
+    pub(crate) fn new(data: &'a [u8], endian: Endian) -> NvNumbers<'a> {
+        NvNumbers { data, endian }
+    }
+
+    /// The number of values in the array
+    pub fn len(&self) -> usize {
+        self.data.len() / 8
+    }
+
+    /// Determines if the array is empty, which libnv does not allow
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Get the value at `index`
+    pub fn get(&self, index: usize) -> Option<u64> {
+        self.data.chunks(8).nth(index).map(|data| self.endian.read_u64(data))
+    }
+
+    /// Iterate over the values
+    pub fn iter(&self) -> impl Iterator<Item = u64> + 'a {
+        let endian = self.endian;
+        self.data.chunks(8).map(move |data| endian.read_u64(data))
+    }
+}
+
+impl<'a> fmt::Debug for NvNumbers<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// An array of strings in a packed buffer
+#[derive(Copy, Clone)]
+pub struct NvStrings<'a> {
+    data: &'a [u8],
+    len: usize,
+}
+
+impl<'a> NvStrings<'a> {
+    pub(crate) fn new(data: &'a [u8], len: usize) -> NvStrings<'a> {
+        NvStrings { data, len }
+    }
+
+    /// The number of strings in the array
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Determines if the array is empty, which libnv does not allow
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Iterate over the strings
+    pub fn iter(&self) -> impl Iterator<Item = &'a str> + 'a {
+        // Every string is NUL terminated, so drop the last NUL to not
+        // get an empty string at the end
+        self.data[..self.data.len() - 1]
+            .split(|&byte| byte == 0)
+            .map(|item| str::from_utf8(item).unwrap_or(""))
+    }
+}
+
+impl<'a> fmt::Debug for NvStrings<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// An array of descriptors in a packed buffer
+#[derive(Copy, Clone)]
+pub struct NvDescriptors<'a> {
+    data: &'a [u8],
+    fds: &'a [RawFd],
+    endian: Endian,
+}
+
+impl<'a> NvDescriptors<'a> {
+    pub(crate) fn new(data: &'a [u8], fds: &'a [RawFd], endian: Endian) -> NvDescriptors<'a> {
+        NvDescriptors { data, fds, endian }
+    }
+
+    /// The number of descriptors in the array
+    pub fn len(&self) -> usize {
+        self.data.len() / 8
+    }
+
+    /// Determines if the array is empty, which libnv does not allow
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Iterate over the descriptors
+    pub fn iter(&self) -> impl Iterator<Item = RawFd> + 'a {
+        let fds = self.fds;
+        let endian = self.endian;
+        self.data.chunks(8).filter_map(move |index| packed::decode_descriptor(index, fds, endian))
+    }
+}
+
+impl<'a> fmt::Debug for NvDescriptors<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// An array of nested lists in a packed buffer
+#[derive(Copy, Clone)]
+pub struct NvListViews<'a> {
+    data: &'a [u8],
+    len: usize,
+    fds: &'a [RawFd],
+    endian: Endian,
+}
+
+impl<'a> NvListViews<'a> {
+    /// The number of lists in the array
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Determines if the array is empty, which libnv does not allow
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Iterate over the lists
+    pub fn iter(&self) -> NvListViewsIter<'a> {
+        NvListViewsIter {
+            reader: Reader::new(self.data, self.endian),
+            left: self.len,
+            fds: self.fds,
+        }
+    }
+}
+
+impl<'a> fmt::Debug for NvListViews<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// Iterator over the lists of an `NvListViews`
+pub struct NvListViewsIter<'a> {
+    reader: Reader<'a>,
+    left: usize,
+    fds: &'a [RawFd],
+}
+
+impl<'a> Iterator for NvListViewsIter<'a> {
+    type Item = NvListView<'a>;
+
+    fn next(&mut self) -> Option<NvListView<'a>> {
+        if self.left == 0 {
+            return None;
+        }
+        self.left -= 1;
+        let view = NvListView::nested(&mut self.reader, self.fds)?;
+        skip_list(&mut self.reader);
+        Some(view)
+    }
+}
+
+/// A read-only view of a packed `nvlist`
+///
+/// The buffer is validated once by `parse`, after which lookups and
+/// iteration read straight from it without allocating. Lookups are linear
+/// in the number of pairs, as they are in libnv.
+///
+/// ```
+/// use nv::NvListView;
+///
+/// // An empty list
+/// let buf = [0x6c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+///            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+///
+/// let view = NvListView::parse(&buf).unwrap();
+///
+/// assert!(view.is_empty());
+/// assert_eq!(view.get_number("the answer"), None);
+/// ```
+#[derive(Copy, Clone)]
+pub struct NvListView<'a> {
+    flags: NvFlag,
+    endian: Endian,
+    /// The packed pairs of the list, running on to the end of the buffer
+    data: &'a [u8],
+    fds: &'a [RawFd],
+}
+
+impl<'a> NvListView<'a> {
+    /// Validate a buffer produced by libnv's `nvlist_pack`, using the
+    /// default `UnpackOptions`
+    ///
+    /// Packed buffers can not carry descriptors.
+    pub fn parse(buf: &'a [u8]) -> NvResult<NvListView<'a>> {
+        NvListView::parse_with(buf, &[], &UnpackOptions::default())
+    }
+
+    /// Validate a buffer whose descriptors are indices into `fds`,
+    /// enforcing the limits in `options`
+    pub fn parse_with(buf: &'a [u8],
+                      fds: &'a [RawFd],
+                      options: &UnpackOptions)
+                      -> NvResult<NvListView<'a>> {
+        packed::walk(buf, fds, options, &mut ())?;
+        Ok(NvListView::validated(buf, fds))
+    }
+
+    /// A view of a buffer already validated by `packed::walk`
+    pub(crate) fn validated(buf: &'a [u8], fds: &'a [RawFd]) -> NvListView<'a> {
+        // Only the header is read here, which holds the byte order
+        let mut reader = Reader::new(buf, Endian::native());
+        NvListView::nested(&mut reader, fds).expect("validated buffer")
+    }
+
+    /// A view of the list whose header is next in `reader`
+    fn nested(reader: &mut Reader<'a>, fds: &'a [RawFd]) -> Option<NvListView<'a>> {
+        let header = ListHeader::parse(reader.bytes(NVLIST_HEADER_SIZE).ok()?, 0).ok()?;
+        Some(NvListView {
+            flags: header.flags,
+            endian: header.endian,
+            data: reader.rest(),
+            fds,
+        })
+    }
+
+    /// The flags the list was created with
+    pub fn flags(&self) -> NvFlag {
+        self.flags
+    }
+
+    /// Determines if the list is empty
+    pub fn is_empty(&self) -> bool {
+        self.pairs().next().is_none()
+    }
+
+    /// Iterate over the name/value pairs in order
+    pub fn iter(&self) -> NvViewIter<'a> {
+        NvViewIter {
+            pairs: self.pairs(),
+            fds: self.fds,
+        }
+    }
+
+    fn pairs(&self) -> RawPairs<'a> {
+        RawPairs { reader: Reader::new(self.data, self.endian) }
+    }
+
+    /// Find the first pair with the given name, and type if one is given
+    fn find(&self, name: &str, ty: Option<NvType>) -> Option<NvValueRef<'a>> {
+        self.pairs()
+            .find(|pair| {
+                names_match(self.flags, pair.name, name) &&
+                ty.is_none_or(|ty| pair.header.ty as i32 == ty as i32)
+            })
+            .map(|pair| pair.value(self.fds))
+    }
+
+    /// Get the first value paired with the given name
+    pub fn get(&self, name: &str) -> Option<NvValueRef<'a>> {
+        self.find(name, None)
+    }
+
+    /// Returns `true` if a name/value pair exists in the list
+    pub fn exists(&self, name: &str) -> bool {
+        self.find(name, None).is_some()
+    }
+
+    /// Returns `true` if a name/value pair of the specified type exists in
+    /// the list
+    pub fn exists_type(&self, name: &str, ty: NvType) -> bool {
+        self.find(name, Some(ty)).is_some()
+    }
+
+    /// Get the first `bool` value paired with the given name
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        match self.find(name, Some(NvType::Bool)) {
+            Some(NvValueRef::Bool(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Get the first `u64` value paired with the given name
+    pub fn get_number(&self, name: &str) -> Option<u64> {
+        match self.find(name, Some(NvType::Number)) {
+            Some(NvValueRef::Number(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Get the first string paired with the given name
+    pub fn get_str(&self, name: &str) -> Option<&'a str> {
+        match self.find(name, Some(NvType::String)) {
+            Some(NvValueRef::String(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Get the first binary buffer paired with the given name
+    pub fn get_binary(&self, name: &str) -> Option<&'a [u8]> {
+        match self.find(name, Some(NvType::Binary)) {
+            Some(NvValueRef::Binary(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Get a view of the first nested list paired with the given name
+    pub fn get_nvlist(&self, name: &str) -> Option<NvListView<'a>> {
+        match self.find(name, Some(NvType::NvList)) {
+            Some(NvValueRef::NvList(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Get the first `bool` array paired with the given name
+    pub fn get_bool_slice(&self, name: &str) -> Option<&'a [bool]> {
+        match self.find(name, Some(NvType::BoolArray)) {
+            Some(NvValueRef::BoolArray(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Copy the list out of the buffer
+    pub fn to_tree(&self) -> NvTree {
+        let mut tree = NvTree::new(self.flags);
+        for (name, value) in self.iter() {
+            tree.push(name, value.to_value());
+        }
+        tree
+    }
+}
+
+impl<'a> fmt::Debug for NvListView<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+/// Iterator over the name/value pairs of an `NvListView`
+pub struct NvViewIter<'a> {
+    pairs: RawPairs<'a>,
+    fds: &'a [RawFd],
+}
+
+impl<'a> Iterator for NvViewIter<'a> {
+    type Item = (&'a str, NvValueRef<'a>);
+
+    fn next(&mut self) -> Option<(&'a str, NvValueRef<'a>)> {
+        self.pairs.next().map(|pair| (pair.name, pair.value(self.fds)))
+    }
+}
+
+/// A pair in a validated buffer whose value has not been decoded
+struct RawPair<'a> {
+    name: &'a str,
+    header: PairHeader,
+    endian: Endian,
+    /// The data of the pair, or the rest of the buffer starting at the
+    /// first nested header for pairs holding lists
+    data: &'a [u8],
+}
+
+impl<'a> RawPair<'a> {
+    fn value(&self, fds: &'a [RawFd]) -> NvValueRef<'a> {
+        let mut reader = Reader::new(self.data, self.endian);
+        let value = match self.header.ty {
+            5 => NvListView::nested(&mut reader, fds).map(NvValueRef::NvList),
+            11 => {
+                Some(NvValueRef::NvListArray(NvListViews {
+                    data: self.data,
+                    len: self.header.nitems as usize,
+                    fds,
+                    endian: self.endian,
+                }))
+            }
+            _ => packed::decode_value(&mut reader, self.header, 0, fds, &VALIDATED).ok(),
+        };
+        value.expect("validated buffer")
+    }
+}
+
+/// Iterator over the pairs of a single list in a validated buffer
+struct RawPairs<'a> {
+    reader: Reader<'a>,
+}
+
+impl<'a> Iterator for RawPairs<'a> {
+    type Item = RawPair<'a>;
+
+    fn next(&mut self) -> Option<RawPair<'a>> {
+        if self.reader.remaining() == 0 {
+            return None;
+        }
+        let (header, name) = self.reader.pair_header().ok()?;
+        let data = self.reader.rest();
+        match header.ty {
+            NV_TYPE_NVLIST_UP | NV_TYPE_NVLIST_ARRAY_NEXT => {
+                // The end of this list
+                self.reader = Reader::new(&[], self.reader.endian());
+                return None;
+            }
+            5 => {
+                self.reader.bytes(NVLIST_HEADER_SIZE).ok()?;
+                skip_list(&mut self.reader);
+            }
+            11 => {
+                for _ in 0..header.nitems {
+                    self.reader.bytes(NVLIST_HEADER_SIZE).ok()?;
+                    skip_list(&mut self.reader);
+                }
+            }
+            _ => {
+                self.reader.bytes(header.data_size as usize).ok()?;
+            }
+        }
+        Some(RawPair {
+            name,
+            header,
+            endian: self.reader.endian(),
+            data: &data[..data.len() - self.reader.remaining()],
+        })
+    }
+}
+
+/// Skip over the pairs of a nested list in a validated buffer, up to and
+/// including the marker that ends it
+fn skip_list(reader: &mut Reader) {
+    let mut depth = 0;
+    while let Ok((header, _)) = reader.pair_header() {
+        let skipped = match header.ty {
+            5 | 11 => {
+                depth += 1;
+                reader.bytes(NVLIST_HEADER_SIZE)
+            }
+            NV_TYPE_NVLIST_UP if depth == 0 => return,
+            NV_TYPE_NVLIST_ARRAY_NEXT if depth == 0 => return,
+            NV_TYPE_NVLIST_UP => {
+                depth -= 1;
+                continue;
+            }
+            NV_TYPE_NVLIST_ARRAY_NEXT => {
+                // Another element of the array follows if a list header
+                // does. The header magic is not a valid pair type, so the
+                // two can not be confused.
+                if reader.rest().first() == Some(&NVLIST_HEADER_MAGIC) {
+                    reader.bytes(NVLIST_HEADER_SIZE)
+                } else {
+                    depth -= 1;
+                    continue;
+                }
+            }
+            _ => reader.bytes(header.data_size as usize),
+        };
+        if skipped.is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,339 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+
+//! Depth-first traversal of nested lists
+//!
+//! libnv walks nested lists without recursion by following
+//! `nvlist_get_parent` and `nvlist_get_array_next`. `walk` and `visit`
+//! on `NvListView` and `NvTree` do the same with an explicit stack, so the
+//! depth of a list is only bounded by memory. `NvList::to_tree` follows
+//! libnv's links itself.
+
+use common::NvFlag;
+use value::{NvTree, NvTreeIter, NvValue};
+use view::{NvListView, NvListViewsIter, NvValueRef, NvViewIter};
+use std::fmt;
+use std::ops::Deref;
+use std::slice;
+
+/// A step on the path from the top level list to a pair
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NvPathElem<'a> {
+    /// The pair of the name
+    Name(&'a str),
+    /// The element at the index of an `nvlist` array
+    Index(usize),
+}
+
+/// The path from the top level list to a pair or a nested list
+///
+/// It dereferences to its steps and is displayed like
+/// `children[1].path`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NvPath<'a> {
+    pub(crate) elems: Vec<NvPathElem<'a>>,
+}
+
+impl<'a> Deref for NvPath<'a> {
+    type Target = [NvPathElem<'a>];
+
+    fn deref(&self) -> &[NvPathElem<'a>] {
+        &self.elems
+    }
+}
+
+impl<'a> fmt::Display for NvPath<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, elem) in self.elems.iter().enumerate() {
+            match *elem {
+                NvPathElem::Name(name) if i == 0 => write!(f, "{}", name)?,
+                NvPathElem::Name(name) => write!(f, ".{}", name)?,
+                NvPathElem::Index(index) => write!(f, "[{}]", index)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Receiver of the pairs of a list in `NvListView::visit`, or of an
+/// `NvTree` with `V` being `&NvValue`
+///
+/// Every pair is passed to `value`, including the ones holding nested
+/// lists. The pairs of a nested list, or of each element of an `nvlist`
+/// array, are then passed between calls to `enter` and `leave` with the
+/// path of that list.
+pub trait Visitor<'a, V = NvValueRef<'a>> {
+    /// A pair of the list being visited
+    fn value(&mut self, path: &NvPath<'a>, value: V);
+
+    /// The pairs of a nested list follow
+    fn enter(&mut self, _path: &NvPath<'a>, _flags: NvFlag) {}
+
+    /// The pairs of a nested list are done
+    fn leave(&mut self, _path: &NvPath<'a>) {}
+}
+
+/// A list `Events` can walk, which is a view or a tree
+trait WalkList<'a>: Sized {
+    /// The values of the pairs of the list
+    type Value: Copy;
+    type Pairs: Iterator<Item = (&'a str, Self::Value)>;
+    type Lists: Iterator<Item = Self>;
+
+    fn flags(&self) -> NvFlag;
+
+    fn pairs(&self) -> Self::Pairs;
+
+    /// The lists held by `value`, if it is a list or an `nvlist` array
+    fn nested(value: Self::Value) -> Option<Nested<Self, Self::Lists>>;
+}
+
+enum Nested<L, A> {
+    List(L),
+    Array(A),
+}
+
+impl<'a> WalkList<'a> for NvListView<'a> {
+    type Value = NvValueRef<'a>;
+    type Pairs = NvViewIter<'a>;
+    type Lists = NvListViewsIter<'a>;
+
+    fn flags(&self) -> NvFlag {
+        NvListView::flags(self)
+    }
+
+    fn pairs(&self) -> NvViewIter<'a> {
+        self.iter()
+    }
+
+    fn nested(value: NvValueRef<'a>) -> Option<Nested<NvListView<'a>, NvListViewsIter<'a>>> {
+        match value {
+            NvValueRef::NvList(view) => Some(Nested::List(view)),
+            NvValueRef::NvListArray(views) => Some(Nested::Array(views.iter())),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> WalkList<'a> for &'a NvTree {
+    type Value = &'a NvValue;
+    type Pairs = NvTreeIter<'a>;
+    type Lists = slice::Iter<'a, NvTree>;
+
+    fn flags(&self) -> NvFlag {
+        NvTree::flags(self)
+    }
+
+    fn pairs(&self) -> NvTreeIter<'a> {
+        NvTree::iter(self)
+    }
+
+    fn nested(value: &'a NvValue) -> Option<Nested<&'a NvTree, slice::Iter<'a, NvTree>>> {
+        match *value {
+            NvValue::NvList(ref tree) => Some(Nested::List(tree)),
+            NvValue::NvListArray(ref trees) => Some(Nested::Array(trees.iter())),
+            _ => None,
+        }
+    }
+}
+
+enum Event<'a, V> {
+    Value(NvPath<'a>, V),
+    Enter(NvPath<'a>, NvFlag),
+    Leave(NvPath<'a>),
+}
+
+impl<'a, V> Event<'a, V> {
+    fn visit<T: Visitor<'a, V>>(self, visitor: &mut T) {
+        match self {
+            Event::Value(path, value) => visitor.value(&path, value),
+            Event::Enter(path, flags) => visitor.enter(&path, flags),
+            Event::Leave(path) => visitor.leave(&path),
+        }
+    }
+}
+
+enum Frame<'a, L: WalkList<'a>> {
+    List(L::Pairs),
+    Array(L::Lists, usize),
+}
+
+/// The traversal behind `walk` and `visit`
+struct Events<'a, L: WalkList<'a>> {
+    stack: Vec<Frame<'a, L>>,
+    /// The path of the innermost list, one step per frame past the first
+    path: NvPath<'a>,
+    pending: Option<Event<'a, L::Value>>,
+}
+
+impl<'a, L: WalkList<'a>> Events<'a, L> {
+    fn new(list: &L) -> Events<'a, L> {
+        Events {
+            stack: vec![Frame::List(list.pairs())],
+            path: NvPath::default(),
+            pending: None,
+        }
+    }
+
+    fn enter(&mut self, list: L) -> Event<'a, L::Value> {
+        self.stack.push(Frame::List(list.pairs()));
+        Event::Enter(self.path.clone(), list.flags())
+    }
+
+    /// The next pair, skipping the events of entering and leaving lists
+    fn next_value(&mut self) -> Option<(NvPath<'a>, L::Value)> {
+        loop {
+            if let Event::Value(path, value) = self.next()? {
+                return Some((path, value));
+            }
+        }
+    }
+}
+
+impl<'a, L: WalkList<'a>> Iterator for Events<'a, L> {
+    type Item = Event<'a, L::Value>;
+
+    fn next(&mut self) -> Option<Event<'a, L::Value>> {
+        if let Some(event) = self.pending.take() {
+            return Some(event);
+        }
+        loop {
+            match *self.stack.last_mut()? {
+                Frame::List(ref mut pairs) => {
+                    if let Some((name, value)) = pairs.next() {
+                        self.path.elems.push(NvPathElem::Name(name));
+                        let path = self.path.clone();
+                        match L::nested(value) {
+                            Some(Nested::List(list)) => self.pending = Some(self.enter(list)),
+                            Some(Nested::Array(lists)) => {
+                                self.stack.push(Frame::Array(lists, 0))
+                            }
+                            None => {
+                                self.path.elems.pop();
+                            }
+                        }
+                        return Some(Event::Value(path, value));
+                    }
+                }
+                Frame::Array(ref mut lists, ref mut index) => {
+                    if let Some(list) = lists.next() {
+                        self.path.elems.push(NvPathElem::Index(*index));
+                        *index += 1;
+                        return Some(self.enter(list));
+                    }
+                }
+            }
+            // The innermost list or array is done
+            let frame = self.stack.pop();
+            if self.stack.is_empty() {
+                return None;
+            }
+            let event = match frame {
+                Some(Frame::List(_)) => Some(Event::Leave(self.path.clone())),
+                _ => None,
+            };
+            self.path.elems.pop();
+            if event.is_some() {
+                return event;
+            }
+        }
+    }
+}
+
+/// Iterator over every pair of an `NvListView` and the lists nested in
+/// it, returned by `NvListView::walk`
+pub struct NvWalk<'a> {
+    events: Events<'a, NvListView<'a>>,
+}
+
+impl<'a> Iterator for NvWalk<'a> {
+    type Item = (NvPath<'a>, NvValueRef<'a>);
+
+    fn next(&mut self) -> Option<(NvPath<'a>, NvValueRef<'a>)> {
+        self.events.next_value()
+    }
+}
+
+/// Iterator over every pair of an `NvTree` and the lists nested in it,
+/// returned by `NvTree::walk`
+pub struct NvTreeWalk<'a> {
+    events: Events<'a, &'a NvTree>,
+}
+
+impl<'a> Iterator for NvTreeWalk<'a> {
+    type Item = (NvPath<'a>, &'a NvValue);
+
+    fn next(&mut self) -> Option<(NvPath<'a>, &'a NvValue)> {
+        self.events.next_value()
+    }
+}
+
+impl<'a> NvListView<'a> {
+    /// Iterate depth-first over every pair of the list, including those
+    /// of nested lists and `nvlist` arrays, along with their paths
+    ///
+    /// Pairs holding nested lists are returned before the pairs of the
+    /// lists they hold.
+    ///
+    /// ```
+    /// use nv::{NvFlag, NvListView, NvTree, NvValue};
+    ///
+    /// let mut vdev = NvTree::new(NvFlag::None);
+    /// vdev.push("path", NvValue::String("/dev/ada0".to_owned()));
+    /// let mut tree = NvTree::new(NvFlag::None);
+    /// tree.push("version", NvValue::Number(1));
+    /// tree.push("vdev", NvValue::NvList(vdev));
+    ///
+    /// let mut buf = Vec::new();
+    /// tree.pack_into(&mut buf).unwrap();
+    ///
+    /// let view = NvListView::parse(&buf).unwrap();
+    /// let paths: Vec<String> = view.walk().map(|(path, _)| path.to_string()).collect();
+    ///
+    /// assert_eq!(paths, ["version", "vdev", "vdev.path"]);
+    /// ```
+    pub fn walk(&self) -> NvWalk<'a> {
+        NvWalk { events: Events::new(self) }
+    }
+
+    /// Pass every pair of the list and the lists nested in it to
+    /// `visitor`, depth-first
+    pub fn visit<V: Visitor<'a>>(&self, visitor: &mut V) {
+        for event in Events::new(self) {
+            event.visit(visitor);
+        }
+    }
+}
+
+impl NvTree {
+    /// Iterate depth-first over every pair of the tree, including those
+    /// of nested lists and `nvlist` arrays, along with their paths, as
+    /// `NvListView::walk` does
+    ///
+    /// ```
+    /// use nv::{NvFlag, NvTree, NvValue};
+    ///
+    /// let mut vdev = NvTree::new(NvFlag::None);
+    /// vdev.push("path", NvValue::String("/dev/ada0".to_owned()));
+    /// let mut tree = NvTree::new(NvFlag::None);
+    /// tree.push("version", NvValue::Number(1));
+    /// tree.push("vdevs", NvValue::NvListArray(vec![vdev]));
+    ///
+    /// let paths: Vec<String> = tree.walk().map(|(path, _)| path.to_string()).collect();
+    ///
+    /// assert_eq!(paths, ["version", "vdevs", "vdevs[0].path"]);
+    /// ```
+    pub fn walk(&self) -> NvTreeWalk<'_> {
+        NvTreeWalk { events: Events::new(&self) }
+    }
+
+    /// Pass every pair of the tree and the lists nested in it to
+    /// `visitor`, depth-first
+    pub fn visit<'a, V: Visitor<'a, &'a NvValue>>(&'a self, visitor: &mut V) {
+        for event in Events::new(&self) {
+            event.visit(visitor);
+        }
+    }
+}
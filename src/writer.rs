@@ -0,0 +1,536 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+
+//! Streaming encoder for the libnv packed format
+//!
+//! The size field of every list header holds the number of bytes that
+//! follow it up to the end of the whole buffer, so the total size has to
+//! be known before the first byte is written. `NvWriter::measure` runs the
+//! same sequence of calls without writing anything to find it.
+//!
+//! Likewise, every list header holds the number of descriptors in that
+//! list and the lists nested in it, as `nvlist_pack` writes them. When
+//! packing a message for a socket, measuring counts them for every list,
+//! and the second pass writes them into the headers.
+
+use common::{Endian, NvFlag, NV_NAME_MAX};
+use packed::{NVLIST_HEADER_MAGIC, NVLIST_HEADER_SIZE, NVPAIR_HEADER_SIZE, NV_FLAG_BIG_ENDIAN,
+             NV_TYPE_NVLIST_ARRAY_NEXT, NV_TYPE_NVLIST_UP};
+use value::{NvTree, NvValue};
+use std::io::{self, Write};
+use std::os::unix::io::RawFd;
+use std::slice;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+/// A nested list that has been started but not ended
+enum Open {
+    /// The list is the value of a pair
+    List,
+    /// An `nvlist` array with `left` elements still to start, and whether
+    /// an element is currently open
+    Array { left: u64, element: bool },
+}
+
+/// Push-style writer producing a packed `nvlist` as pairs are added
+///
+/// Nothing is buffered beyond what `W` buffers itself, so arbitrarily
+/// large lists can be streamed to files and sockets. Since the format
+/// stores the size of the whole list in its header, the writer is created
+/// with that size, which is checked by `finish`. If it is not known up
+/// front, make the same calls on `NvWriter::measure` first.
+///
+/// Packed lists can not carry descriptors, so there are no methods to add
+/// them.
+///
+/// ```
+/// use nv::{NvFlag, NvTree, NvValue, NvWriter, UnpackOptions};
+///
+/// fn produce<W: std::io::Write>(writer: &mut NvWriter<W>) -> std::io::Result<()> {
+///     writer.add_number("the answer", 42)?;
+///     writer.begin_nvlist("nested", NvFlag::None)?;
+///     writer.add_string("Hello", "World!")?;
+///     writer.end_nvlist()
+/// }
+///
+/// let mut measure = NvWriter::measure(NvFlag::None);
+/// produce(&mut measure).unwrap();
+///
+/// let mut writer = NvWriter::new(Vec::new(), NvFlag::None, measure.written()).unwrap();
+/// produce(&mut writer).unwrap();
+/// let buf = writer.finish().unwrap();
+///
+/// let tree = NvTree::unpack(&buf, &[], &UnpackOptions::default()).unwrap();
+/// assert_eq!(tree.get("the answer"), Some(&NvValue::Number(42)));
+/// ```
+pub struct NvWriter<W: Write> {
+    inner: W,
+    /// The total size of the packed list, or `None` while measuring
+    size: Option<u64>,
+    written: u64,
+    stack: Vec<Open>,
+    /// The descriptors added so far when packing a message for a socket,
+    /// which carries them out of band, or `None` if there may be none
+    fds: Option<Vec<RawFd>>,
+    /// The number of descriptors in every list, in the order their
+    /// headers are written, as counted while measuring
+    descriptors: Vec<u64>,
+    /// The number of list headers written so far
+    headers: usize,
+    /// The lists that are still open, outermost first, as indices into
+    /// `descriptors`
+    open: Vec<usize>,
+    /// The byte order integers are written in
+    endian: Endian,
+}
+
+impl NvWriter<io::Sink> {
+    /// Create a writer that only counts the bytes of the packed list
+    ///
+    /// After the pairs have been added, `written` is the size to pass to
+    /// `NvWriter::new`.
+    pub fn measure(flags: NvFlag) -> NvWriter<io::Sink> {
+        let mut writer = NvWriter {
+            inner: io::sink(),
+            size: None,
+            written: 0,
+            stack: Vec::new(),
+            fds: None,
+            descriptors: Vec::new(),
+            headers: 0,
+            open: Vec::new(),
+            endian: Endian::native(),
+        };
+        // Writing to a sink can not fail
+        let _ = writer.list_header(flags);
+        writer
+    }
+}
+
+impl<W: Write> NvWriter<W> {
+    /// Create a writer for a packed list of `size` bytes in total,
+    /// writing its header right away
+    pub fn new(inner: W, flags: NvFlag, size: u64) -> io::Result<NvWriter<W>> {
+        NvWriter::with_endianness(inner, flags, size, Endian::native())
+    }
+
+    /// Create a writer like `new`, packing integers in the given byte
+    /// order rather than the native one
+    ///
+    /// The size of a list does not depend on its byte order, so it can
+    /// be measured as usual.
+    pub fn with_endianness(inner: W,
+                           flags: NvFlag,
+                           size: u64,
+                           endian: Endian)
+                           -> io::Result<NvWriter<W>> {
+        if size < NVLIST_HEADER_SIZE as u64 {
+            return Err(invalid("size is smaller than the list header"));
+        }
+        let mut writer = NvWriter {
+            inner,
+            size: Some(size),
+            written: 0,
+            stack: Vec::new(),
+            fds: None,
+            descriptors: Vec::new(),
+            headers: 0,
+            open: Vec::new(),
+            endian,
+        };
+        writer.list_header(flags)?;
+        Ok(writer)
+    }
+
+    /// The number of bytes written so far
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        if let Some(size) = self.size {
+            if self.written + buf.len() as u64 > size {
+                return Err(invalid("list is larger than its declared size"));
+            }
+        }
+        self.inner.write_all(buf)?;
+        self.written += buf.len() as u64;
+        Ok(())
+    }
+
+    fn list_header(&mut self, flags: NvFlag) -> io::Result<()> {
+        if self.headers == self.descriptors.len() {
+            self.descriptors.push(0);
+        }
+        let descriptors = self.descriptors[self.headers];
+        self.open.push(self.headers);
+        self.headers += 1;
+        let mut flags = flags as u8;
+        if self.endian == Endian::Big {
+            flags |= NV_FLAG_BIG_ENDIAN;
+        }
+        // The size is that of everything after this header, up to the
+        // end of the whole buffer
+        let rest = self.size
+            .unwrap_or(0)
+            .saturating_sub(self.written + NVLIST_HEADER_SIZE as u64);
+        let mut header = [0u8; NVLIST_HEADER_SIZE];
+        header[0] = NVLIST_HEADER_MAGIC;
+        header[2] = flags;
+        header[3..11].copy_from_slice(&self.u64_bytes(descriptors));
+        header[11..19].copy_from_slice(&self.u64_bytes(rest));
+        self.write(&header)
+    }
+
+    fn u16_bytes(&self, value: u16) -> [u8; 2] {
+        match self.endian {
+            Endian::Little => value.to_le_bytes(),
+            Endian::Big => value.to_be_bytes(),
+        }
+    }
+
+    fn u64_bytes(&self, value: u64) -> [u8; 8] {
+        match self.endian {
+            Endian::Little => value.to_le_bytes(),
+            Endian::Big => value.to_be_bytes(),
+        }
+    }
+
+    fn pair_header(&mut self, ty: u8, name: &str, data_size: u64, nitems: u64) -> io::Result<()> {
+        if let Some(&Open::Array { element: false, .. }) = self.stack.last() {
+            return Err(invalid("pair added to an nvlist array outside of an element"));
+        }
+        if name.len() >= NV_NAME_MAX as usize || name.as_bytes().contains(&0) {
+            return Err(invalid("invalid name"));
+        }
+        self.raw_pair_header(ty, name, data_size, nitems)
+    }
+
+    fn raw_pair_header(&mut self,
+                       ty: u8,
+                       name: &str,
+                       data_size: u64,
+                       nitems: u64)
+                       -> io::Result<()> {
+        let mut header = [0u8; NVPAIR_HEADER_SIZE];
+        header[0] = ty;
+        header[1..3].copy_from_slice(&self.u16_bytes(name.len() as u16 + 1));
+        header[3..11].copy_from_slice(&self.u64_bytes(data_size));
+        header[11..19].copy_from_slice(&self.u64_bytes(nitems));
+        self.write(&header)?;
+        self.write(name.as_bytes())?;
+        self.write(&[0])
+    }
+
+    /// Add a `null` value
+    pub fn add_null(&mut self, name: &str) -> io::Result<()> {
+        self.pair_header(1, name, 0, 0)
+    }
+
+    /// Add a `bool` value
+    pub fn add_bool(&mut self, name: &str, value: bool) -> io::Result<()> {
+        self.pair_header(2, name, 1, 0)?;
+        self.write(&[value as u8])
+    }
+
+    /// Add a `u64` value
+    pub fn add_number(&mut self, name: &str, value: u64) -> io::Result<()> {
+        self.pair_header(3, name, 8, 0)?;
+        let bytes = self.u64_bytes(value);
+        self.write(&bytes)
+    }
+
+    /// Add a string, which must not contain NUL bytes
+    pub fn add_string(&mut self, name: &str, value: &str) -> io::Result<()> {
+        if value.as_bytes().contains(&0) {
+            return Err(invalid("string contains a NUL byte"));
+        }
+        self.pair_header(4, name, value.len() as u64 + 1, 0)?;
+        self.write(value.as_bytes())?;
+        self.write(&[0])
+    }
+
+    /// Add a binary buffer, which must not be empty
+    pub fn add_binary(&mut self, name: &str, value: &[u8]) -> io::Result<()> {
+        if value.is_empty() {
+            return Err(invalid("empty binary value"));
+        }
+        self.pair_header(7, name, value.len() as u64, 0)?;
+        self.write(value)
+    }
+
+    /// Add a non-empty array of `bool` values
+    pub fn add_bool_slice(&mut self, name: &str, value: &[bool]) -> io::Result<()> {
+        if value.is_empty() {
+            return Err(invalid("empty array"));
+        }
+        self.pair_header(8, name, value.len() as u64, value.len() as u64)?;
+        // A `bool` is a single byte holding either 0 or 1, as in the format
+        self.write(unsafe { slice::from_raw_parts(value.as_ptr() as *const u8, value.len()) })
+    }
+
+    /// Add a non-empty array of `u64` values
+    pub fn add_number_slice(&mut self, name: &str, value: &[u64]) -> io::Result<()> {
+        if value.is_empty() {
+            return Err(invalid("empty array"));
+        }
+        self.pair_header(9, name, 8 * value.len() as u64, value.len() as u64)?;
+        if self.endian == Endian::native() {
+            return self.write(unsafe {
+                slice::from_raw_parts(value.as_ptr() as *const u8, 8 * value.len())
+            });
+        }
+        for &item in value {
+            let bytes = self.u64_bytes(item);
+            self.write(&bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Add a non-empty array of strings, none of which may contain NUL
+    /// bytes
+    pub fn add_string_slice<S: AsRef<str>>(&mut self, name: &str, value: &[S]) -> io::Result<()> {
+        if value.is_empty() {
+            return Err(invalid("empty array"));
+        }
+        if value.iter().any(|item| item.as_ref().as_bytes().contains(&0)) {
+            return Err(invalid("string contains a NUL byte"));
+        }
+        let data_size = value.iter().map(|item| item.as_ref().len() as u64 + 1).sum();
+        self.pair_header(10, name, data_size, value.len() as u64)?;
+        for item in value {
+            self.write(item.as_ref().as_bytes())?;
+            self.write(&[0])?;
+        }
+        Ok(())
+    }
+
+    /// Start a nested list, whose pairs are added until `end_nvlist`
+    pub fn begin_nvlist(&mut self, name: &str, flags: NvFlag) -> io::Result<()> {
+        // As in libnv, the pair itself has no data
+        self.pair_header(5, name, 0, 0)?;
+        self.list_header(flags)?;
+        self.stack.push(Open::List);
+        Ok(())
+    }
+
+    /// End the nested list started by the last `begin_nvlist`
+    pub fn end_nvlist(&mut self) -> io::Result<()> {
+        match self.stack.last() {
+            Some(&Open::List) => {}
+            _ => return Err(invalid("no nested list to end")),
+        }
+        self.stack.pop();
+        self.open.pop();
+        self.raw_pair_header(NV_TYPE_NVLIST_UP, "", 0, 0)
+    }
+
+    /// Start an array of `len` nested lists, each of which is written
+    /// between `begin_element` and `end_element`
+    pub fn begin_nvlist_array(&mut self, name: &str, len: usize) -> io::Result<()> {
+        if len == 0 {
+            return Err(invalid("empty array"));
+        }
+        let len = len as u64;
+        self.pair_header(11, name, 8 * len, len)?;
+        self.stack.push(Open::Array {
+            left: len,
+            element: false,
+        });
+        Ok(())
+    }
+
+    /// Start the next element of the current `nvlist` array
+    pub fn begin_element(&mut self, flags: NvFlag) -> io::Result<()> {
+        match self.stack.last_mut() {
+            Some(&mut Open::Array { ref mut left, ref mut element }) if *left > 0 && !*element => {
+                *left -= 1;
+                *element = true;
+            }
+            _ => return Err(invalid("no nvlist array element to start")),
+        }
+        self.list_header(flags)
+    }
+
+    /// End the current element of the current `nvlist` array
+    pub fn end_element(&mut self) -> io::Result<()> {
+        match self.stack.last_mut() {
+            Some(&mut Open::Array { ref mut element, .. }) if *element => *element = false,
+            _ => return Err(invalid("no nvlist array element to end")),
+        }
+        self.open.pop();
+        self.raw_pair_header(NV_TYPE_NVLIST_ARRAY_NEXT, "", 0, 0)
+    }
+
+    /// End the current `nvlist` array once all of its elements are written
+    pub fn end_nvlist_array(&mut self) -> io::Result<()> {
+        match self.stack.last() {
+            Some(&Open::Array { left: 0, element: false }) => {}
+            _ => return Err(invalid("nvlist array is not complete")),
+        }
+        self.stack.pop();
+        Ok(())
+    }
+
+    /// Add a value held in an `NvTree`, along with any lists nested in it
+    pub fn add_value(&mut self, name: &str, value: &NvValue) -> io::Result<()> {
+        match *value {
+            NvValue::Null => self.add_null(name),
+            NvValue::Bool(value) => self.add_bool(name, value),
+            NvValue::Number(value) => self.add_number(name, value),
+            NvValue::String(ref value) => self.add_string(name, value),
+            NvValue::NvList(ref value) => {
+                self.begin_nvlist(name, value.flags())?;
+                self.add_tree(value)?;
+                self.end_nvlist()
+            }
+            NvValue::Binary(ref value) => self.add_binary(name, value),
+            NvValue::BoolArray(ref value) => self.add_bool_slice(name, value),
+            NvValue::NumberArray(ref value) => self.add_number_slice(name, value),
+            NvValue::StringArray(ref value) => self.add_string_slice(name, value),
+            NvValue::NvListArray(ref value) => {
+                self.begin_nvlist_array(name, value.len())?;
+                for element in value {
+                    self.begin_element(element.flags())?;
+                    self.add_tree(element)?;
+                    self.end_element()?;
+                }
+                self.end_nvlist_array()
+            }
+            NvValue::Descriptor(fd) => self.add_descriptors(6, name, &[fd]),
+            NvValue::DescriptorArray(ref value) => self.add_descriptors(12, name, value),
+        }
+    }
+
+    /// Add descriptors as indices into those carried by the message
+    fn add_descriptors(&mut self, ty: u8, name: &str, value: &[RawFd]) -> io::Result<()> {
+        let first = match self.fds {
+            Some(ref fds) => fds.len() as u64,
+            None => return Err(invalid("packed lists can not carry descriptors")),
+        };
+        if value.is_empty() {
+            return Err(invalid("empty array"));
+        }
+        let nitems = if ty == 12 { value.len() as u64 } else { 0 };
+        self.pair_header(ty, name, 8 * value.len() as u64, nitems)?;
+        for index in first..first + value.len() as u64 {
+            let bytes = self.u64_bytes(index);
+            self.write(&bytes)?;
+        }
+        if let Some(ref mut fds) = self.fds {
+            fds.extend_from_slice(value);
+        }
+        if self.size.is_none() {
+            for &list in &self.open {
+                self.descriptors[list] += value.len() as u64;
+            }
+        }
+        Ok(())
+    }
+
+    /// Add every pair of an `NvTree`
+    pub fn add_tree(&mut self, tree: &NvTree) -> io::Result<()> {
+        for (name, value) in tree.iter() {
+            self.add_value(name, value)?;
+        }
+        Ok(())
+    }
+
+    /// Check that the list is complete and has exactly the declared size,
+    /// returning the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.stack.is_empty() {
+            return Err(invalid("nested list was not ended"));
+        }
+        if let Some(size) = self.size {
+            if self.written != size {
+                return Err(invalid("list is smaller than its declared size"));
+            }
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl NvTree {
+    /// Stream the tree to `writer` in the libnv packed format
+    ///
+    /// The tree is walked twice, once to find its size and once to write
+    /// it, so the packed list is never held in memory as a whole. Trees
+    /// holding descriptors can not be packed.
+    ///
+    /// ```
+    /// use nv::{NvFlag, NvTree, NvValue, UnpackOptions};
+    ///
+    /// let mut tree = NvTree::new(NvFlag::None);
+    /// tree.push("the answer", NvValue::Number(42));
+    ///
+    /// let mut buf = Vec::new();
+    /// tree.pack_into(&mut buf).unwrap();
+    ///
+    /// assert_eq!(NvTree::unpack(&buf, &[], &UnpackOptions::default()).unwrap(), tree);
+    /// ```
+    pub fn pack_into<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut measure = NvWriter::measure(self.flags());
+        measure.add_tree(self)?;
+        let mut writer = NvWriter::new(writer, self.flags(), measure.written())?;
+        writer.add_tree(self)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// Pack the tree with its integers in the given byte order, as libnv
+    /// does on a host of that byte order
+    ///
+    /// ```
+    /// use nv::{Endian, NvFlag, NvTree, NvValue, UnpackOptions};
+    ///
+    /// let mut tree = NvTree::new(NvFlag::None);
+    /// tree.push("the answer", NvValue::Number(42));
+    ///
+    /// let buf = tree.pack_with_endianness(Endian::Big).unwrap();
+    ///
+    /// assert_eq!(buf[2], 0x80);
+    /// assert_eq!(NvTree::unpack(&buf, &[], &UnpackOptions::default()).unwrap(), tree);
+    /// ```
+    pub fn pack_with_endianness(&self, endian: Endian) -> io::Result<Vec<u8>> {
+        let mut measure = NvWriter::measure(self.flags());
+        measure.add_tree(self)?;
+        let size = measure.written();
+        let mut writer = NvWriter::with_endianness(Vec::with_capacity(size as usize),
+                                                   self.flags(),
+                                                   size,
+                                                   endian)?;
+        writer.add_tree(self)?;
+        writer.finish()
+    }
+
+    /// Pack the tree as a message for a socket, whose descriptors are
+    /// sent out of band as with `nvlist_send`
+    pub(crate) fn pack_message(&self) -> io::Result<(Vec<u8>, Vec<RawFd>)> {
+        let mut measure = NvWriter::measure(self.flags());
+        measure.fds = Some(Vec::new());
+        measure.add_tree(self)?;
+        let size = measure.written();
+        let descriptors = measure.fds.map_or(0, |fds| fds.len());
+        let mut writer = NvWriter {
+            inner: Vec::with_capacity(size as usize),
+            size: Some(size),
+            written: 0,
+            stack: Vec::new(),
+            fds: Some(Vec::with_capacity(descriptors)),
+            descriptors: measure.descriptors,
+            headers: 0,
+            open: Vec::new(),
+            endian: Endian::native(),
+        };
+        writer.list_header(self.flags())?;
+        writer.add_tree(self)?;
+        let fds = writer.fds.take().unwrap_or_default();
+        Ok((writer.finish()?, fds))
+    }
+}
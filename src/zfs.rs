@@ -0,0 +1,110 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+
+//! Reading the configuration ZFS keeps on disk
+//!
+//! Both the pool cache file and the labels at the start and end of each
+//! vdev hold an XDR packed `nvpair` list, which is returned as an
+//! `NvPairList`. Pool GUIDs and vdev trees can be read from it without
+//! running `zdb`.
+//!
+//! ```no_run
+//! use nv::nvpair::NvPairValue;
+//! use nv::zfs;
+//!
+//! let label = zfs::read_vdev_label("/dev/ada0p3").unwrap();
+//! if let Some(&NvPairValue::Uint64(guid)) = label.get("pool_guid") {
+//!     println!("pool guid: {}", guid);
+//! }
+//!
+//! let cache = zfs::read_cache("/boot/zfs/zpool.cache").unwrap();
+//! for (pool, _) in cache.iter() {
+//!     println!("cached pool: {}", pool);
+//! }
+//! ```
+
+use common::{NvErr, NvLimit, NvResult};
+use msgio;
+use nvpair::NvPairList;
+use packed::UnpackOptions;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Size of each of the four labels, `sizeof (vdev_label_t)`
+pub const VDEV_LABEL_SIZE: u64 = 256 << 10;
+/// Offset of the packed list in a label, past the padding and boot block
+const VDEV_PHYS_OFFSET: u64 = 16 << 10;
+/// Size of the `vdev_phys_t` holding the packed list
+const VDEV_PHYS_SIZE: usize = 112 << 10;
+/// Size of the `zio_eck_t` closing the `vdev_phys_t`
+const ZIO_ECK_SIZE: usize = 40;
+/// `zec_magic` of a `zio_eck_t`, in either byte order
+const ZEC_MAGIC: u64 = 0x0210_da7a_b10c_7a11;
+
+/// Decode the pool configurations cached in `path`, usually
+/// `/boot/zfs/zpool.cache` or `/etc/zfs/zpool.cache`
+///
+/// Each pair of the returned list is named after a pool and holds its
+/// configuration.
+pub fn read_cache<P: AsRef<Path>>(path: P) -> NvResult<NvPairList> {
+    let options = UnpackOptions::default();
+    let file = File::open(path).map_err(msgio::to_nv)?;
+    let mut buf = Vec::new();
+    file.take(options.max_bytes as u64 + 1).read_to_end(&mut buf).map_err(msgio::to_nv)?;
+    if buf.len() > options.max_bytes {
+        return Err(NvErr::LimitExceeded(NvLimit::Bytes));
+    }
+    NvPairList::unpack(&buf, &options)
+}
+
+/// Decode the configuration in the labels of the vdev at `path`, a
+/// device or an image file
+///
+/// The labels are tried in order, and the first that decodes is
+/// returned. Their checksums are not verified, but labels without the
+/// checksum trailer's magic are skipped.
+pub fn read_vdev_label<P: AsRef<Path>>(path: P) -> NvResult<NvPairList> {
+    let mut file = File::open(path).map_err(msgio::to_nv)?;
+    // The length of a device is only found by seeking to its end
+    let size = file.seek(SeekFrom::End(0)).map_err(msgio::to_nv)?;
+    let mut first_err = None;
+    for offset in label_offsets(size) {
+        match read_label(&mut file, offset) {
+            Ok(list) => return Ok(list),
+            Err(err) => {
+                first_err.get_or_insert(err);
+            }
+        }
+    }
+    Err(first_err.unwrap_or(NvErr::Truncated(0)))
+}
+
+/// The offsets of the labels that fit in a vdev of `size` bytes, two at
+/// its start and two at its end
+fn label_offsets(size: u64) -> Vec<u64> {
+    let size = size - size % VDEV_LABEL_SIZE;
+    let mut offsets = vec![0, VDEV_LABEL_SIZE];
+    if size >= 4 * VDEV_LABEL_SIZE {
+        offsets.push(size - 2 * VDEV_LABEL_SIZE);
+        offsets.push(size - VDEV_LABEL_SIZE);
+    }
+    offsets.retain(|&offset| offset + VDEV_LABEL_SIZE <= size);
+    offsets
+}
+
+fn read_label(file: &mut File, offset: u64) -> NvResult<NvPairList> {
+    let mut phys = vec![0; VDEV_PHYS_SIZE];
+    file.seek(SeekFrom::Start(offset + VDEV_PHYS_OFFSET)).map_err(msgio::to_nv)?;
+    file.read_exact(&mut phys).map_err(msgio::to_nv)?;
+    let (list, trailer) = phys.split_at(VDEV_PHYS_SIZE - ZIO_ECK_SIZE);
+    let mut magic = [0; 8];
+    magic.copy_from_slice(&trailer[..8]);
+    if u64::from_le_bytes(magic) != ZEC_MAGIC && u64::from_be_bytes(magic) != ZEC_MAGIC {
+        return Err(NvErr::InvalidFormat(0));
+    }
+    // The list is followed by zeroes up to the trailer
+    NvPairList::unpack_prefix(list, &UnpackOptions::default()).map(|(list, _)| list)
+}
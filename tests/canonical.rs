@@ -0,0 +1,119 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+
+extern crate nv;
+#[cfg(feature = "digest")]
+extern crate sha2;
+
+mod tests {
+    use nv::{Endian, NvFlag, NvTree, NvValue, UnpackOptions};
+    #[cfg(feature = "digest")]
+    use sha2::{Digest, Sha256};
+    use std::thread;
+
+    fn tree(pairs: &[(&str, NvValue)]) -> NvTree {
+        let mut tree = NvTree::new(NvFlag::None);
+        for (name, value) in pairs {
+            tree.push(name, value.clone());
+        }
+        tree
+    }
+
+    #[test]
+    fn test_canonical_order() {
+        let disk = tree(&[("path", NvValue::String("/dev/ada0".to_owned())),
+                          ("guid", NvValue::Number(7))]);
+        let disk_reordered = tree(&[("guid", NvValue::Number(7)),
+                                    ("path", NvValue::String("/dev/ada0".to_owned()))]);
+        let a = tree(&[("version", NvValue::Number(5000)),
+                       ("vdev", NvValue::NvList(disk.clone())),
+                       ("children", NvValue::NvListArray(vec![disk.clone(), disk.clone()]))]);
+        let b = tree(&[("children",
+                        NvValue::NvListArray(vec![disk_reordered.clone(), disk_reordered.clone()])),
+                       ("vdev", NvValue::NvList(disk_reordered)),
+                       ("version", NvValue::Number(5000))]);
+        assert_eq!(a.canonical_bytes().unwrap(), b.canonical_bytes().unwrap());
+
+        let unpacked = NvTree::unpack(&a.canonical_bytes().unwrap(),
+                                      &[],
+                                      &UnpackOptions::default())
+            .unwrap();
+        let names: Vec<&str> = unpacked.iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["children", "vdev", "version"]);
+
+        // Arrays are values, so their elements are not reordered
+        let c = tree(&[("numbers", NvValue::NumberArray(vec![1, 2]))]);
+        let d = tree(&[("numbers", NvValue::NumberArray(vec![2, 1]))]);
+        assert_ne!(c.canonical_bytes().unwrap(), d.canonical_bytes().unwrap());
+    }
+
+    #[test]
+    fn test_canonical_duplicates() {
+        let mut a = NvTree::new(NvFlag::NoUnique);
+        a.push("x", NvValue::Number(1));
+        a.push("a", NvValue::Null);
+        a.push("x", NvValue::Number(2));
+        let mut b = NvTree::new(NvFlag::NoUnique);
+        b.push("x", NvValue::Number(2));
+        b.push("x", NvValue::Number(1));
+        b.push("a", NvValue::Null);
+        assert_ne!(a.canonical_bytes().unwrap(), b.canonical_bytes().unwrap());
+    }
+
+    #[test]
+    fn test_canonical_little_endian() {
+        let buf = tree(&[("n", NvValue::Number(0x0102)),
+                         ("ns", NvValue::NumberArray(vec![3]))])
+            .canonical_bytes()
+            .unwrap();
+        // No `NV_FLAG_BIG_ENDIAN` in the header
+        assert_eq!(buf[2] & 0x80, 0);
+        assert_eq!(&buf[buf.len() - 8..], &[3, 0, 0, 0, 0, 0, 0, 0]);
+        let number = buf.windows(2).position(|w| w == b"n\0").unwrap() + 2;
+        assert_eq!(&buf[number..number + 8], &[2, 1, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_canonical_deep() {
+        let mut deep = tree(&[("leaf", NvValue::Number(1))]);
+        for depth in 0..2000 {
+            let mut parent = NvTree::new(NvFlag::None);
+            if depth % 2 == 0 {
+                parent.push("list", NvValue::NvList(deep));
+            } else {
+                parent.push("array", NvValue::NvListArray(vec![deep]));
+            }
+            deep = parent;
+        }
+        // Far too small a stack to recurse once per nested list
+        let canonical = thread::scope(|scope| {
+            thread::Builder::new()
+                .stack_size(64 * 1024)
+                .spawn_scoped(scope, || deep.canonical_bytes().unwrap())
+                .unwrap()
+                .join()
+                .unwrap()
+        });
+        // With a single pair per list, there is nothing to sort
+        assert_eq!(canonical, deep.pack_with_endianness(Endian::Little).unwrap());
+    }
+
+    #[test]
+    fn test_canonical_descriptors() {
+        let tree = tree(&[("fd", NvValue::Descriptor(0))]);
+        assert!(tree.canonical_bytes().is_err());
+    }
+
+    #[cfg(feature = "digest")]
+    #[test]
+    fn test_canonical_digest() {
+        let a = tree(&[("version", NvValue::Number(5000)), ("name", NvValue::Bool(true))]);
+        let b = tree(&[("name", NvValue::Bool(true)), ("version", NvValue::Number(5000))]);
+        assert_eq!(a.digest::<Sha256>().unwrap(), b.digest::<Sha256>().unwrap());
+        assert_eq!(a.digest::<Sha256>().unwrap(),
+                   Sha256::digest(a.canonical_bytes().unwrap()));
+        assert!(tree(&[("fd", NvValue::Descriptor(0))]).digest::<Sha256>().is_err());
+    }
+}
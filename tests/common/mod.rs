@@ -0,0 +1,167 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+// Not every test crate uses every helper
+#![allow(dead_code)]
+
+use nv::{NvCursor, NvFlag, NvTree, NvValue};
+use std::env;
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// A path in the temporary directory, removed along with anything created
+/// at it when dropped, even if the test panics
+pub struct Scratch(PathBuf);
+
+/// A scratch path named after the test crate and `name`, with nothing
+/// there yet
+pub fn scratch(name: &str) -> Scratch {
+    let scratch = Scratch(env::temp_dir().join(format!("nv-{}-{}", process::id(), name)));
+    scratch.remove();
+    scratch
+}
+
+/// A fresh, empty scratch directory
+pub fn scratch_dir(name: &str) -> Scratch {
+    let scratch = scratch(name);
+    fs::create_dir(&scratch.0).unwrap();
+    scratch
+}
+
+impl Scratch {
+    fn remove(&self) {
+        if fs::remove_file(&self.0).is_err() {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+}
+
+impl Deref for Scratch {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for Scratch {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+/// Hand rolled packed `nvlist`s, with the size of every list header
+/// patched in by `finish`
+pub struct Blob {
+    buf: Vec<u8>,
+    headers: Vec<usize>,
+}
+
+impl Blob {
+    pub fn new() -> Blob {
+        Blob { buf: Vec::new(), headers: Vec::new() }.header(0)
+    }
+
+    pub fn header(mut self, descriptors: u64) -> Blob {
+        self.headers.push(self.buf.len());
+        self.buf.push(0x6c);
+        self.buf.push(0);
+        self.buf.push(if cfg!(target_endian = "big") { 0x80 } else { 0 });
+        self.buf.extend_from_slice(&descriptors.to_ne_bytes());
+        self.buf.extend_from_slice(&[0; 8]);
+        self
+    }
+
+    pub fn pair_header(mut self, ty: u8, name: &str, data_size: u64, nitems: u64) -> Blob {
+        self.buf.push(ty);
+        self.buf.extend_from_slice(&(name.len() as u16 + 1).to_ne_bytes());
+        self.buf.extend_from_slice(&data_size.to_ne_bytes());
+        self.buf.extend_from_slice(&nitems.to_ne_bytes());
+        self.buf.extend_from_slice(name.as_bytes());
+        self.buf.push(0);
+        self
+    }
+
+    pub fn pair(self, ty: u8, name: &str, data: &[u8], nitems: u64) -> Blob {
+        let mut blob = self.pair_header(ty, name, data.len() as u64, nitems);
+        blob.buf.extend_from_slice(data);
+        blob
+    }
+
+    pub fn number(self, name: &str, value: u64) -> Blob {
+        self.pair(3, name, &value.to_ne_bytes(), 0)
+    }
+
+    pub fn nvlist(self, name: &str) -> Blob {
+        self.pair(5, name, &[], 0).header(0)
+    }
+
+    pub fn nvlist_array(self, name: &str, nitems: u64) -> Blob {
+        // The data size is that of the sender's pointer array, but
+        // nothing of it is packed
+        self.pair_header(11, name, 8 * nitems, nitems).header(0)
+    }
+
+    pub fn up(self) -> Blob {
+        self.pair(255, "", &[], 0)
+    }
+
+    pub fn array_next(self) -> Blob {
+        self.pair(254, "", &[], 0)
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        let len = self.buf.len();
+        for &at in &self.headers {
+            let size = (len - at - 19) as u64;
+            self.buf[at + 11..at + 19].copy_from_slice(&size.to_ne_bytes());
+        }
+        self.buf
+    }
+}
+
+/// The list `cursor_rewrite` runs on
+pub fn cursor_sample() -> NvTree {
+    let mut nested = NvTree::new(NvFlag::None);
+    nested.push("secret", NvValue::String("hunter2".to_owned()));
+    nested.push("keep", NvValue::Bool(true));
+    let mut tree = NvTree::new(NvFlag::None);
+    tree.push("a", NvValue::Number(1));
+    tree.push("nested", NvValue::NvList(nested));
+    tree.push("b", NvValue::Null);
+    tree.push("c", NvValue::Number(3));
+    tree
+}
+
+/// Take `a`, remove `b`, replace `c` with 30 and mask `nested.secret`,
+/// the same way for every cursor backend
+pub fn cursor_rewrite(cursor: &mut NvCursor) {
+    while cursor.next() {
+        match cursor.name() {
+            Some("a") => assert_eq!(cursor.take(), Some(NvValue::Number(1))),
+            Some("b") => {
+                assert!(cursor.remove());
+                assert!(!cursor.remove());
+            }
+            Some("c") => assert_eq!(cursor.replace(NvValue::Number(30)), Some(NvValue::Number(3))),
+            Some("nested") => {
+                let mut nested = cursor.descend().unwrap();
+                while nested.next() {
+                    if nested.name() == Some("secret") {
+                        nested.replace(NvValue::String("*".to_owned()));
+                    }
+                }
+            }
+            name => panic!("unexpected {:?}", name),
+        }
+    }
+}
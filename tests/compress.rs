@@ -0,0 +1,101 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+
+extern crate nv;
+
+mod tests {
+    use nv::compress;
+    use nv::{NvErr, NvFlag, NvLimit, NvTree, NvValue, UnpackOptions};
+    use std::borrow::Cow;
+
+    fn sample() -> NvTree {
+        let mut tree = NvTree::new(NvFlag::None);
+        for index in 0..64 {
+            let mut disk = NvTree::new(NvFlag::None);
+            disk.push("path", NvValue::String(format!("/dev/ada{}", index)));
+            disk.push("type", NvValue::String("disk".to_owned()));
+            tree.push(&format!("disk{}", index), NvValue::NvList(disk));
+        }
+        tree
+    }
+
+    fn packed() -> Vec<u8> {
+        let mut buf = Vec::new();
+        sample().pack_into(&mut buf).unwrap();
+        buf
+    }
+
+    /// A frame as `compress` lays it out
+    fn frame(codec: u8, size: u64, data: &[u8]) -> Vec<u8> {
+        let mut buf = b"nvz\x01".to_vec();
+        buf.push(codec);
+        buf.extend_from_slice(&size.to_le_bytes());
+        buf.extend_from_slice(data);
+        buf
+    }
+
+    #[test]
+    fn test_plain_passthrough() {
+        let buf = packed();
+        assert!(!compress::is_compressed(&buf));
+        match compress::decompress(&buf, &UnpackOptions::default()).unwrap() {
+            Cow::Borrowed(plain) => assert_eq!(plain, &buf[..]),
+            Cow::Owned(_) => panic!("plain list was copied"),
+        }
+        assert_eq!(NvTree::unpack_any(&buf, &[], &UnpackOptions::default()).unwrap(),
+                   sample());
+    }
+
+    #[test]
+    fn test_malformed_frames() {
+        let options = UnpackOptions::default();
+        assert!(compress::decompress(&frame(1, 8, b"")[..10], &options).is_err());
+        match compress::decompress(&frame(9, 8, b""), &options) {
+            Err(NvErr::InvalidFormat(4)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        let options = UnpackOptions { max_bytes: 1024, ..options };
+        for &codec in &[1, 2] {
+            match compress::decompress(&frame(codec, 1 << 40, b""), &options) {
+                Err(NvErr::LimitExceeded(NvLimit::Bytes)) => {}
+                res => panic!("unexpected {:?}", res),
+            }
+        }
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    #[test]
+    fn test_unsupported_codec() {
+        match compress::decompress(&frame(1, 8, b"data"), &UnpackOptions::default()) {
+            Err(NvErr::Errno(_)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+    }
+
+    #[cfg(any(feature = "zstd", feature = "lz4"))]
+    #[test]
+    fn test_round_trip() {
+        let codecs = [#[cfg(feature = "zstd")]
+                      compress::Codec::Zstd,
+                      #[cfg(feature = "lz4")]
+                      compress::Codec::Lz4];
+        let options = UnpackOptions::default();
+        for &codec in &codecs {
+            let buf = sample().pack_compressed(codec).unwrap();
+            assert!(compress::is_compressed(&buf));
+            assert!(buf.len() < packed().len() / 2, "{:?} did not compress", codec);
+            assert_eq!(compress::decompress(&buf, &options).unwrap().into_owned(), packed());
+            assert_eq!(NvTree::unpack_any(&buf, &[], &options).unwrap(), sample());
+
+            // The declared size has to match the decompressed list
+            let mut wrong = buf.clone();
+            wrong[5] ^= 1;
+            assert!(NvTree::unpack_any(&wrong, &[], &options).is_err());
+            for len in 0..buf.len() {
+                assert!(NvTree::unpack_any(&buf[..len], &[], &options).is_err());
+            }
+        }
+    }
+}
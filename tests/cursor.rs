@@ -0,0 +1,49 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+
+extern crate nv;
+
+mod common;
+
+mod tests {
+    use common::{cursor_rewrite, cursor_sample};
+    use nv::{NvType, NvValue};
+
+    #[test]
+    fn test_cursor_walk() {
+        let mut tree = cursor_sample();
+        let mut cursor = tree.cursor_mut();
+        assert_eq!(cursor.name(), None);
+        assert!(cursor.next());
+        assert_eq!(cursor.name(), Some("a"));
+        assert_eq!(cursor.ty(), Some(NvType::Number));
+        assert_eq!(cursor.get(), Some(NvValue::Number(1)));
+        assert!(cursor.next());
+        assert!(cursor.next());
+        assert_eq!(cursor.ty(), Some(NvType::Null));
+        assert!(cursor.descend().is_none());
+        assert!(cursor.next());
+        assert!(!cursor.next());
+        assert_eq!(cursor.name(), None);
+        assert!(!cursor.next());
+    }
+
+    #[test]
+    fn test_cursor_rewrite() {
+        let mut tree = cursor_sample();
+        cursor_rewrite(&mut tree.cursor_mut());
+        let names: Vec<&str> = tree.iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["nested", "c"]);
+        assert_eq!(tree.get("c"), Some(&NvValue::Number(30)));
+        match tree.get("nested") {
+            Some(NvValue::NvList(nested)) => {
+                let names: Vec<&str> = nested.iter().map(|(name, _)| name).collect();
+                assert_eq!(names, ["keep", "secret"]);
+                assert_eq!(nested.get("secret"), Some(&NvValue::String("*".to_owned())));
+            }
+            value => panic!("unexpected {:?}", value),
+        }
+    }
+}
@@ -0,0 +1,129 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+
+extern crate nv;
+
+mod common;
+
+mod tests {
+    use common::Blob;
+    use nv::{NvDecoder, NvErr, NvLimit, NvTree, NvValue, UnpackOptions};
+    use std::task::Poll;
+
+    fn sample(value: u64) -> Vec<u8> {
+        Blob::new()
+            .number("number", value)
+            .nvlist("child")
+            .pair(4, "string", b"Hello\0", 0)
+            .up()
+            .finish()
+    }
+
+    fn ready(res: Poll<Result<NvTree, NvErr>>) -> NvTree {
+        match res {
+            Poll::Ready(Ok(tree)) => tree,
+            res => panic!("unexpected {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_decoder_byte_at_a_time() {
+        let buf = sample(42);
+        let mut decoder = NvDecoder::new(UnpackOptions::default());
+        for byte in buf[..buf.len() - 1].chunks(1) {
+            assert!(decoder.feed(&mut &byte[..]).is_pending());
+        }
+        let tree = ready(decoder.feed(&mut &buf[buf.len() - 1..]));
+        assert_eq!(tree.get("number"), Some(&NvValue::Number(42)));
+        assert_eq!(decoder.buffered(), 0);
+    }
+
+    #[test]
+    fn test_decoder_several_lists() {
+        let mut stream = sample(1);
+        stream.extend_from_slice(&sample(2));
+        stream.extend_from_slice(&sample(3)[..5]);
+        let mut decoder = NvDecoder::new(UnpackOptions::default());
+        let mut data = &stream[..];
+        let first = ready(decoder.feed(&mut data));
+        assert_eq!(first.get("number"), Some(&NvValue::Number(1)));
+        // Nothing past the end of the first list was taken
+        assert_eq!(decoder.buffered(), 0);
+        assert_eq!(data.len(), stream.len() - sample(1).len());
+        let second = ready(decoder.feed(&mut data));
+        assert_eq!(second.get("number"), Some(&NvValue::Number(2)));
+        assert!(decoder.feed(&mut data).is_pending());
+        assert!(data.is_empty());
+        assert_eq!(decoder.buffered(), 5);
+    }
+
+    #[test]
+    fn test_decoder_limits() {
+        let buf = sample(42);
+        let options = UnpackOptions { max_bytes: 32, ..Default::default() };
+        let mut decoder = NvDecoder::new(options);
+        // The size is checked as soon as the header is in
+        match decoder.feed(&mut &buf[..19]) {
+            Poll::Ready(Err(NvErr::LimitExceeded(NvLimit::Bytes))) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        assert_eq!(decoder.buffered(), 0);
+    }
+
+    #[test]
+    fn test_decoder_oversized_chunk() {
+        let mut buf = sample(42);
+        buf.resize(1 << 20, 0);
+        let options = UnpackOptions { max_bytes: 32, ..Default::default() };
+        let mut decoder = NvDecoder::new(options);
+        // The header is checked before the rest of the chunk is buffered
+        match decoder.feed(&mut &buf[..]) {
+            Poll::Ready(Err(NvErr::LimitExceeded(NvLimit::Bytes))) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        assert_eq!(decoder.buffered(), 0);
+    }
+
+    #[test]
+    fn test_decoder_chunk_past_list() {
+        let mut stream = sample(42);
+        let len = stream.len();
+        stream.resize(1 << 20, 0xff);
+        let mut decoder = NvDecoder::new(UnpackOptions::default());
+        let mut data = &stream[..];
+        let tree = ready(decoder.feed(&mut data));
+        assert_eq!(tree.get("number"), Some(&NvValue::Number(42)));
+        // The garbage after the list is left for the next call to reject
+        assert_eq!(data.len(), stream.len() - len);
+        assert_eq!(decoder.buffered(), 0);
+        match decoder.feed(&mut data) {
+            Poll::Ready(Err(_)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        assert_eq!(decoder.buffered(), 0);
+    }
+
+    #[test]
+    fn test_decoder_many_lists() {
+        let mut stream = Vec::new();
+        for value in 0..1000 {
+            stream.extend_from_slice(&sample(value));
+        }
+        let mut decoder = NvDecoder::new(UnpackOptions::default());
+        let (head, tail) = stream.split_at(stream.len() / 2 + 3);
+        let mut value = 0;
+        for chunk in &[head, tail] {
+            let mut data = *chunk;
+            while !data.is_empty() {
+                if let Poll::Ready(res) = decoder.feed(&mut data) {
+                    assert_eq!(res.unwrap().get("number"), Some(&NvValue::Number(value)));
+                    value += 1;
+                }
+            }
+        }
+        assert_eq!(value, 1000);
+        assert_eq!(decoder.buffered(), 0);
+    }
+}
@@ -0,0 +1,98 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+
+extern crate nv;
+
+mod tests {
+    use nv::{Endian, NvErr, NvFlag, NvListView, NvTree, NvValue, NvValueRef, UnpackOptions};
+
+    /// A list packed on a big-endian host, holding the number
+    /// 0x0102030405060708 as `n` and the numbers 1 and 2 as `a`
+    const BIG_ENDIAN: [u8; 85] = [
+        // List header, with the big-endian flag and 66 bytes of pairs
+        0x6c, 0x00, 0x80,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x42,
+        // "n", a number
+        0x03, 0x00, 0x02,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        b'n', 0x00,
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        // "a", an array of two numbers
+        0x09, 0x00, 0x02,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+        b'a', 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+    ];
+
+    fn sample() -> NvTree {
+        let mut tree = NvTree::new(NvFlag::None);
+        tree.push("n", NvValue::Number(0x0102030405060708));
+        tree.push("a", NvValue::NumberArray(vec![1, 2]));
+        tree
+    }
+
+    fn unpack(buf: &[u8]) -> Result<NvTree, NvErr> {
+        NvTree::unpack(buf, &[], &UnpackOptions::default())
+    }
+
+    #[test]
+    fn test_unpack_big_endian() {
+        assert_eq!(unpack(&BIG_ENDIAN).unwrap(), sample());
+
+        let view = NvListView::parse(&BIG_ENDIAN).unwrap();
+        assert_eq!(view.get_number("n"), Some(0x0102030405060708));
+        match view.get("a") {
+            Some(NvValueRef::NumberArray(numbers)) => {
+                assert_eq!(numbers.iter().collect::<Vec<_>>(), [1, 2]);
+                assert_eq!(numbers.get(1), Some(2));
+            }
+            value => panic!("unexpected {:?}", value),
+        }
+    }
+
+    #[test]
+    fn test_pack_with_endianness() {
+        assert_eq!(sample().pack_with_endianness(Endian::Big).unwrap(), &BIG_ENDIAN[..]);
+
+        let little = sample().pack_with_endianness(Endian::Little).unwrap();
+        assert_eq!(little[2], 0x00);
+        assert_eq!(little[18], 0x00);
+        assert_eq!(little[11], 0x42);
+        assert_eq!(unpack(&little).unwrap(), sample());
+
+        let mut native = Vec::new();
+        sample().pack_into(&mut native).unwrap();
+        assert_eq!(sample().pack_with_endianness(Endian::native()).unwrap(), native);
+    }
+
+    #[test]
+    fn test_nested_big_endian() {
+        let mut inner = NvTree::new(NvFlag::IgnoreCase);
+        inner.push("path", NvValue::String("/dev/ada0".to_owned()));
+        let mut tree = NvTree::new(NvFlag::None);
+        tree.push("vdev", NvValue::NvList(inner.clone()));
+        tree.push("children", NvValue::NvListArray(vec![inner.clone(), inner]));
+
+        let buf = tree.pack_with_endianness(Endian::Big).unwrap();
+        assert_eq!(unpack(&buf).unwrap(), tree);
+        let view = NvListView::parse(&buf).unwrap();
+        assert_eq!(view.to_tree(), tree);
+        assert_eq!(view.get_nvlist("vdev").unwrap().get_str("PATH"), Some("/dev/ada0"));
+
+        // The nested list of "vdev" claims the other byte order, after
+        // the top level header and the pair header of "vdev"
+        let mut mixed = buf.clone();
+        assert_eq!(mixed[19 + 19 + 5 + 2], 0x81);
+        mixed[19 + 19 + 5 + 2] = 0x01;
+        match unpack(&mixed) {
+            Err(NvErr::InvalidFormat(43)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+    }
+}
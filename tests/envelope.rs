@@ -0,0 +1,97 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+
+#![cfg(feature = "envelope")]
+
+extern crate ed25519_dalek;
+extern crate nv;
+
+mod tests {
+    use ed25519_dalek::SigningKey;
+    use nv::envelope::{self, Algorithm, OpenKey, SealKey};
+    use nv::{NvErr, NvFlag, NvTree, NvValue, UnpackOptions};
+
+    fn sample() -> NvTree {
+        let mut tree = NvTree::new(NvFlag::None);
+        tree.push("cmd", NvValue::String("restart".to_owned()));
+        tree.push("jid", NvValue::Number(12));
+        tree
+    }
+
+    fn hmac_keys(key_id: &str, algorithm: Algorithm) -> Option<OpenKey> {
+        match (key_id, algorithm) {
+            ("jail-a", Algorithm::HmacSha256) => Some(OpenKey::HmacSha256(b"secret".to_vec())),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_hmac_envelope() {
+        let key = SealKey::HmacSha256(b"secret".to_vec());
+        let buf = envelope::seal_tree(&sample(), "jail-a", &key).unwrap();
+        let opened = envelope::open(&buf, hmac_keys).unwrap();
+        assert_eq!(opened.key_id, "jail-a");
+        assert_eq!(opened.algorithm, Algorithm::HmacSha256);
+        assert_eq!(envelope::unpack(&buf, &UnpackOptions::default(), hmac_keys).unwrap(),
+                   sample());
+
+        // Any change to the envelope is caught
+        for index in 0..buf.len() {
+            let mut tampered = buf.clone();
+            tampered[index] ^= 1;
+            assert!(envelope::open(&tampered, hmac_keys).is_err(), "byte {}", index);
+        }
+        for len in 0..buf.len() {
+            assert!(envelope::open(&buf[..len], hmac_keys).is_err());
+        }
+
+        let other = SealKey::HmacSha256(b"guess".to_vec());
+        let forged = envelope::seal_tree(&sample(), "jail-a", &other).unwrap();
+        match envelope::open(&forged, hmac_keys) {
+            Err(NvErr::BadSignature) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        let unknown = envelope::seal_tree(&sample(), "jail-b", &key).unwrap();
+        match envelope::open(&unknown, hmac_keys) {
+            Err(NvErr::BadSignature) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_ed25519_envelope() {
+        let signing = SigningKey::from_bytes(&[7; 32]);
+        let verifying = signing.verifying_key();
+        let keys = |key_id: &str, _: Algorithm| match key_id {
+            "host" => Some(OpenKey::Ed25519(verifying)),
+            _ => None,
+        };
+        let buf = envelope::seal_tree(&sample(), "host", &SealKey::Ed25519(signing)).unwrap();
+        let mut packed = Vec::new();
+        sample().pack_into(&mut packed).unwrap();
+        assert_eq!(buf.len(), 6 + 4 + packed.len() + 64);
+        assert_eq!(envelope::open(&buf, keys).unwrap().payload, &packed[..]);
+        assert_eq!(envelope::unpack(&buf, &UnpackOptions::default(), keys).unwrap(),
+                   sample());
+
+        let mut tampered = buf.clone();
+        tampered[12] ^= 0x80;
+        assert!(envelope::open(&tampered, keys).is_err());
+
+        // A shared key can not stand in for a public key
+        let hmac = envelope::seal_tree(&sample(), "host", &SealKey::HmacSha256(vec![1; 32]));
+        let hmac = hmac.unwrap();
+        let as_hmac = |_: &str, _: Algorithm| Some(OpenKey::Ed25519(verifying));
+        assert!(envelope::open(&hmac, as_hmac).is_err());
+    }
+
+    #[test]
+    fn test_key_id_length() {
+        let key = SealKey::HmacSha256(Vec::new());
+        assert!(envelope::seal(b"", "", &key).is_err());
+        assert!(envelope::seal(b"", &"k".repeat(256), &key).is_err());
+        assert!(envelope::seal(b"", &"k".repeat(255), &key).is_ok());
+    }
+}
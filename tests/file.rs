@@ -0,0 +1,86 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+
+extern crate nv;
+
+mod common;
+
+mod tests {
+    use common::scratch_dir;
+    use nv::{NvErr, NvFile, NvFlag, NvLimit, NvTree, NvValue, UnpackOptions};
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn state(generation: u64) -> NvTree {
+        let mut peer = NvTree::new(NvFlag::None);
+        peer.push("addr", NvValue::String("10.0.0.2".to_owned()));
+        let mut tree = NvTree::new(NvFlag::None);
+        tree.push("generation", NvValue::Number(generation));
+        tree.push("peer", NvValue::NvList(peer));
+        tree
+    }
+
+    #[test]
+    fn test_write_and_open() {
+        let dir = scratch_dir("file-write");
+        let path = dir.join("state.nv");
+        NvFile::write_tree_atomic(&path, &state(1)).unwrap();
+
+        let old = unsafe { NvFile::open(&path) }.unwrap();
+        assert_eq!(old.view().to_tree(), state(1));
+
+        // Replacing the file leaves the old mapping alone
+        NvFile::write_tree_atomic(&path, &state(2)).unwrap();
+        let new = unsafe { NvFile::open(&path) }.unwrap();
+        assert_eq!(new.view().get_number("generation"), Some(2));
+        assert_eq!(old.view().get_number("generation"), Some(1));
+        assert_eq!(new.view().get_nvlist("peer").unwrap().get_str("addr"), Some("10.0.0.2"));
+
+        let names: Vec<_> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(names, ["state.nv"]);
+    }
+
+    #[test]
+    fn test_write_permissions() {
+        let dir = scratch_dir("file-permissions");
+        let path = dir.join("state.nv");
+        NvFile::write_tree_atomic(&path, &state(1)).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        // The file replaced keeps its permissions
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        NvFile::write_tree_atomic(&path, &state(2)).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o644);
+    }
+
+    #[test]
+    fn test_open_invalid() {
+        let dir = scratch_dir("file-invalid");
+        let path = dir.join("state.nv");
+
+        fs::write(&path, b"").unwrap();
+        match unsafe { NvFile::open(&path) } {
+            Err(NvErr::Truncated(0)) => {}
+            res => panic!("unexpected {:?}", res.map(|file| file.as_bytes().to_vec())),
+        }
+
+        let mut buf = Vec::new();
+        state(1).pack_into(&mut buf).unwrap();
+        fs::write(&path, &buf[..buf.len() - 1]).unwrap();
+        assert!(unsafe { NvFile::open(&path) }.is_err());
+
+        fs::write(&path, &buf).unwrap();
+        let options = UnpackOptions { max_bytes: buf.len() - 1, ..UnpackOptions::default() };
+        match unsafe { NvFile::open_with(&path, &options) } {
+            Err(NvErr::LimitExceeded(NvLimit::Bytes)) => {}
+            res => panic!("unexpected {:?}", res.map(|file| file.as_bytes().to_vec())),
+        }
+
+        match unsafe { NvFile::open(dir.join("missing")) } {
+            Err(NvErr::Errno(errno)) => assert_eq!(errno, 2),
+            res => panic!("unexpected {:?}", res.map(|file| file.as_bytes().to_vec())),
+        }
+    }
+}
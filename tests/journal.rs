@@ -0,0 +1,150 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+
+extern crate libc;
+extern crate nv;
+
+mod common;
+
+mod tests {
+    use common::scratch;
+    use nv::{NvErr, NvFlag, NvJournal, NvTree, NvValue};
+    use std::fs;
+
+    fn change(mtu: u64) -> NvTree {
+        let mut tree = NvTree::new(NvFlag::None);
+        tree.push("iface", NvValue::String("em0".to_owned()));
+        tree.push("mtu", NvValue::Number(mtu));
+        tree
+    }
+
+    #[test]
+    fn test_journal_replay() {
+        let path = scratch("journal-replay");
+        let (mut journal, records) = NvJournal::open(&path).unwrap();
+        assert!(records.is_empty());
+        for mtu in &[1500, 9000, 4000] {
+            journal.append(&change(*mtu)).unwrap();
+        }
+        assert_eq!(journal.records(), 3);
+        drop(journal);
+
+        let (journal, records) = NvJournal::open(&path).unwrap();
+        assert_eq!(records, [change(1500), change(9000), change(4000)]);
+        assert_eq!(journal.records(), 3);
+    }
+
+    #[test]
+    fn test_journal_torn_tail() {
+        let path = scratch("journal-torn");
+        let (mut journal, _) = NvJournal::open(&path).unwrap();
+        journal.append(&change(1500)).unwrap();
+        journal.append(&change(9000)).unwrap();
+        drop(journal);
+        let full = fs::metadata(&path).unwrap().len();
+
+        // Every cut into the last record drops just that record
+        let complete = fs::read(&path).unwrap();
+        let first_end = (complete.len() + 4) / 2;
+        for len in first_end as u64..full {
+            fs::write(&path, &complete[..len as usize]).unwrap();
+            let (_, records) = NvJournal::open(&path).unwrap();
+            assert_eq!(records, [change(1500)], "cut at {}", len);
+            assert_eq!(fs::metadata(&path).unwrap().len(), first_end as u64);
+        }
+
+        // So does a garbled one, and appending carries on after the last
+        // complete record
+        let mut garbled = complete.clone();
+        let last = garbled.len() - 1;
+        garbled[last] ^= 0xff;
+        fs::write(&path, &garbled).unwrap();
+        let (mut journal, records) = NvJournal::open(&path).unwrap();
+        assert_eq!(records, [change(1500)]);
+        journal.append(&change(4000)).unwrap();
+        drop(journal);
+        let (_, records) = NvJournal::open(&path).unwrap();
+        assert_eq!(records, [change(1500), change(4000)]);
+
+        // As do zeros where the last record was to go
+        let mut zeroed = complete.clone();
+        zeroed.extend_from_slice(&[0; 64]);
+        fs::write(&path, &zeroed).unwrap();
+        let (_, records) = NvJournal::open(&path).unwrap();
+        assert_eq!(records, [change(1500), change(9000)]);
+        assert_eq!(fs::metadata(&path).unwrap().len(), full);
+
+        // A garbled record followed by others was not torn by a crash,
+        // whether its list or its size is garbled
+        for &(offset, bit) in &[(4 + 16, 0x01), (4, 0x01), (4 + 7, 0x80)] {
+            let mut corrupt = complete.clone();
+            corrupt[offset] ^= bit;
+            fs::write(&path, &corrupt).unwrap();
+            match NvJournal::open(&path) {
+                Err(NvErr::InvalidFormat(4)) => {}
+                res => panic!("unexpected {:?}", res.map(|(_, records)| records)),
+            }
+            assert_eq!(fs::read(&path).unwrap(), corrupt);
+        }
+
+        fs::write(&path, b"not a journal").unwrap();
+        match NvJournal::open(&path) {
+            Err(NvErr::InvalidFormat(0)) => {}
+            res => panic!("unexpected {:?}", res.map(|(_, records)| records)),
+        }
+    }
+
+    #[test]
+    fn test_journal_failed_append() {
+        let path = scratch("journal-failed");
+        let (mut journal, _) = NvJournal::open(&path).unwrap();
+        // Pad the journal past every other one, so that only this test
+        // runs into the file size limit
+        let mut padding = NvTree::new(NvFlag::None);
+        padding.push("padding", NvValue::Binary(vec![0; 1 << 16]));
+        journal.append(&padding).unwrap();
+        let len = fs::metadata(&path).unwrap().len();
+
+        // Let the next record get halfway to disk
+        unsafe {
+            libc::signal(libc::SIGXFSZ, libc::SIG_IGN);
+            let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+            assert_eq!(libc::getrlimit(libc::RLIMIT_FSIZE, &mut limit), 0);
+            let old = limit.rlim_cur;
+            limit.rlim_cur = len + 24;
+            assert_eq!(libc::setrlimit(libc::RLIMIT_FSIZE, &limit), 0);
+            assert!(journal.append(&change(1500)).is_err());
+            limit.rlim_cur = old;
+            assert_eq!(libc::setrlimit(libc::RLIMIT_FSIZE, &limit), 0);
+        }
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+        assert_eq!(journal.records(), 1);
+
+        journal.append(&change(9000)).unwrap();
+        drop(journal);
+        let (journal, records) = NvJournal::open(&path).unwrap();
+        assert_eq!(records, [padding, change(9000)]);
+        assert_eq!(journal.records(), 2);
+    }
+
+    #[test]
+    fn test_journal_compact() {
+        let path = scratch("journal-compact");
+        let (mut journal, _) = NvJournal::open(&path).unwrap();
+        journal.append(&change(1500)).unwrap();
+        journal.append(&change(9000)).unwrap();
+
+        let mut snapshot = NvTree::new(NvFlag::None);
+        snapshot.push("em0", NvValue::NvList(change(9000)));
+        journal.compact(&snapshot).unwrap();
+        assert_eq!(journal.records(), 1);
+        journal.append(&change(4000)).unwrap();
+        drop(journal);
+
+        let (journal, records) = NvJournal::open(&path).unwrap();
+        assert_eq!(records, [snapshot, change(4000)]);
+        assert_eq!(journal.records(), 2);
+    }
+}
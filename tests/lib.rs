@@ -3,14 +3,404 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 
+#[cfg_attr(feature = "libnv", macro_use)]
 extern crate nv;
+#[cfg(feature = "libnv")]
+extern crate libc;
+#[cfg(all(feature = "libnv", feature = "digest"))]
+extern crate sha2;
 
+mod common;
+
+#[cfg(feature = "libnv")]
 mod tests {
-    use nv::{NvList, NvFlag};
+    use common::{cursor_rewrite, cursor_sample};
+    use nv::{NvErr, NvFlag, NvList, NvPath, NvTree, NvType, NvValue, RedactRules, Visitor};
+    use std::collections::BTreeMap;
+    use std::fmt::Write;
+    use std::io::{self, Read};
+    use std::net::SocketAddr;
+    use std::os::unix::io::{AsRawFd, BorrowedFd, FromRawFd};
+    use std::os::unix::net::UnixStream;
+    use std::path::{Path, PathBuf};
+    use std::thread;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
     #[test]
     fn test_create_nvlist() {
         let x = NvList::new(NvFlag::All);
         let _ = x.clone();
     }
+
+    #[test]
+    fn test_send_and_share() {
+        let mut list = NvList::new(NvFlag::None).unwrap();
+        list.add_number("the answer", 42);
+        let list = thread::spawn(move || {
+            list.add_string("from", "worker");
+            list
+        }).join().unwrap();
+        assert_eq!(list.get_str("from"), Some("worker"));
+
+        let frozen = list.freeze();
+        let shared = frozen.clone();
+        let answer = thread::spawn(move || shared.get_number("the answer")).join().unwrap();
+        assert_eq!(answer, Some(42));
+        assert_eq!(frozen.thaw().get_number("the answer"), Some(42));
+    }
+
+    #[test]
+    fn test_integer_round_trip() {
+        let mut list = NvList::new(NvFlag::None).unwrap();
+        list.add("u8", 255u8);
+        list.add("i8", -128i8);
+        list.add("usize", usize::MAX);
+        list.add("isize", -1isize);
+        assert_eq!(list.get::<u8>("u8").unwrap(), 255);
+        assert_eq!(list.get::<i8>("i8").unwrap(), -128);
+        assert_eq!(list.get::<usize>("usize").unwrap(), usize::MAX);
+        assert_eq!(list.get::<isize>("isize").unwrap(), -1);
+        assert_eq!(list.get_number("isize"), Some(u64::MAX));
+        match list.get::<i8>("u8") {
+            Err(NvErr::OutOfRange(255)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        match list.get::<u16>("u16") {
+            Err(NvErr::NotFound) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+
+        list.add("i128", -2i128);
+        list.add("u128", u128::from(u64::MAX));
+        assert_eq!(list.get::<i128>("i128").unwrap(), -2);
+        assert_eq!(list.get::<u128>("u128").unwrap(), u128::from(u64::MAX));
+        assert_eq!(list.error(), 0);
+        list.add("huge", u128::MAX);
+        assert!(!list.exists("huge"));
+        assert_eq!(list.error(), libc::EOVERFLOW);
+    }
+
+    nv_enum! {
+        #[derive(Debug, PartialEq)]
+        enum State {
+            Idle,
+            Busy = 5,
+            Failed = -1,
+        }
+    }
+
+    #[test]
+    fn test_enum_round_trip() {
+        let mut list = NvList::new(NvFlag::None).unwrap();
+        list.add("idle", State::Idle);
+        list.add("busy", State::Busy);
+        list.add("failed", &State::Failed);
+        list.add_number("unknown", 3);
+        assert_eq!(list.get_number("busy"), Some(5));
+        assert_eq!(list.get_number("failed"), Some(u64::MAX));
+        assert_eq!(list.get::<State>("idle").unwrap(), State::Idle);
+        assert_eq!(list.get::<State>("busy").unwrap(), State::Busy);
+        assert_eq!(list.get::<State>("failed").unwrap(), State::Failed);
+        match list.get::<State>("unknown") {
+            Err(NvErr::OutOfRange(3)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_typed_get() {
+        let mut list = NvList::new(NvFlag::None).unwrap();
+        list.add_string("name", "nv");
+        list.add_null("nothing");
+        list.add_number_slice("numbers", &[1, 2, 3]);
+        list.add_descriptor("stdin", 0);
+        assert_eq!(list.get::<&str>("name").unwrap(), "nv");
+        assert_eq!(list.get::<String>("name").unwrap(), "nv");
+        assert_eq!(list.get::<&[u64]>("numbers").unwrap(), &[1, 2, 3]);
+        assert_eq!(list.get::<Vec<u64>>("numbers").unwrap(), vec![1, 2, 3]);
+        assert_eq!(list.get::<Option<u64>>("nothing").unwrap(), None);
+        assert_eq!(list.get::<Option<&str>>("name").unwrap(), Some("nv"));
+        assert!(list.get::<BorrowedFd>("stdin").is_ok());
+        assert!(list.get_or("missing", true).unwrap());
+    }
+
+    #[test]
+    fn test_add_std_types() {
+        let mut map = BTreeMap::new();
+        map.insert("one", 1u64);
+        map.insert("two", 2u64);
+        let addr: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        let time = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+
+        let mut list = NvList::new(NvFlag::None).unwrap();
+        list.add("str", "value");
+        list.add("bools", &[true, false][..]);
+        list.add("numbers", vec![1u64, 2]);
+        list.add("strings", vec!["a".to_owned()]);
+        list.add("bytes", vec![0u8, 255]);
+        list.add("map", &map);
+        list.add("path", Path::new("/tmp/nv"));
+        list.add("addr", addr);
+        list.add("timeout", Duration::from_millis(1500));
+        list.add("time", time);
+
+        assert_eq!(list.get_str("str"), Some("value"));
+        assert_eq!(list.get_bool_slice("bools"), Some(&[true, false][..]));
+        assert_eq!(list.get_number_slice("numbers"), Some(&[1, 2][..]));
+        assert_eq!(list.get_string_vec("strings"), Some(vec!["a".to_owned()]));
+        assert_eq!(list.get::<Vec<u8>>("bytes").unwrap(), vec![0, 255]);
+        assert_eq!(list.get_nvlist("map").unwrap().get_number("two"), Some(2));
+        assert_eq!(list.get::<PathBuf>("path").unwrap(), PathBuf::from("/tmp/nv"));
+        assert_eq!(list.get::<SocketAddr>("addr").unwrap(), addr);
+        assert_eq!(list.get_number("timeout"), Some(1_500_000_000));
+        assert_eq!(list.get::<SystemTime>("time").unwrap(), time);
+    }
+
+    #[test]
+    fn test_add_tree_error() {
+        let mut tree = NvTree::new(NvFlag::None);
+        tree.push("twice", NvValue::Null);
+        tree.push("twice", NvValue::Null);
+
+        let mut list = NvList::new(NvFlag::None).unwrap();
+        list.add("tree", NvValue::NvList(tree));
+        assert_eq!(list.error(), libc::EEXIST);
+    }
+
+    #[test]
+    fn test_add_fmt() {
+        let mut list = NvList::new(NvFlag::None).unwrap();
+        nv_add_fmt!(list, "empty", "");
+        let long = "x".repeat(1000);
+        list.add_fmt("long", format_args!("{}{}", long, long));
+        {
+            let mut writer = list.string_writer("discarded");
+            writer.write_str("never added").unwrap();
+        }
+        assert_eq!(list.get_str("empty"), Some(""));
+        assert_eq!(list.get_str("long").map(str::len), Some(2000));
+        assert!(!list.exists("discarded"));
+        assert_eq!(list.error(), 0);
+
+        list.add_fmt("nul", format_args!("a{}b", '\0'));
+        assert_eq!(list.error(), 22);
+    }
+
+    #[test]
+    fn test_entry() {
+        let mut list = NvList::new(NvFlag::NoUnique).unwrap();
+        list.add_number("name", 1);
+        list.add_string("name", "one");
+        assert_eq!(list.entry("name").ty(), Some(NvType::Number));
+        list.entry("name").insert(true);
+        assert!(!list.exists_type("name", NvType::Number));
+        assert!(!list.exists_type("name", NvType::String));
+        assert_eq!(list.get_bool("name"), Some(true));
+
+        list.entry("name").and_modify(|value: u64| value + 1);
+        assert_eq!(list.get_bool("name"), Some(true));
+        list.entry("name").and_modify(|value: bool| !value);
+        assert_eq!(list.get_bool("name"), Some(false));
+
+        assert!(list.entry("name").remove());
+        assert!(!list.entry("name").remove());
+        assert!(list.is_empty());
+
+        let mut list = NvList::new(NvFlag::All).unwrap();
+        list.add_number("a", 1);
+        list.add_string("Name", "one");
+        list.add_number("b", 2);
+        list.add_null("NAME");
+        assert_eq!(list.entry("name").ty(), Some(NvType::String));
+        list.entry("name").insert(3u64);
+        assert_eq!(list.get_number("nAmE"), Some(3));
+        assert_eq!(list.get_number("a"), Some(1));
+        assert_eq!(list.get_number("b"), Some(2));
+        assert!(!list.exists_type("name", NvType::Null));
+    }
+
+    #[test]
+    fn test_walk() {
+        struct Paths(Vec<String>);
+
+        impl<'a> Visitor<'a, &'a NvValue> for Paths {
+            fn value(&mut self, path: &NvPath<'a>, _: &'a NvValue) {
+                self.0.push(path.to_string());
+            }
+
+            fn leave(&mut self, path: &NvPath<'a>) {
+                self.0.push(format!("leave {}", path));
+            }
+        }
+
+        let mut leaf = NvList::new(NvFlag::None).unwrap();
+        leaf.add_number("depth", 2);
+        let mut child = NvList::new(NvFlag::None).unwrap();
+        child.add_nvlist("leaf", &leaf);
+        child.add_bool("last", true);
+        let mut list = NvList::new(NvFlag::None).unwrap();
+        list.add_nvlist_slice("children", &[child, NvList::new(NvFlag::IgnoreCase).unwrap()]);
+        list.add_number("after", 1);
+
+        let mut paths = Paths(Vec::new());
+        list.visit(&mut paths).unwrap();
+        assert_eq!(paths.0,
+                   ["children", "children[0].leaf", "children[0].leaf.depth",
+                    "leave children[0].leaf", "children[0].last", "leave children[0]",
+                    "leave children[1]", "after"]);
+        match list.to_tree().unwrap().get("children") {
+            Some(NvValue::NvListArray(children)) => {
+                assert_eq!(children[1].flags(), NvFlag::IgnoreCase);
+            }
+            value => panic!("unexpected {:?}", value),
+        }
+    }
+
+    #[test]
+    fn test_cursor() {
+        let mut list = NvList::from_tree(&cursor_sample()).unwrap();
+        cursor_rewrite(&mut list.cursor_mut());
+        assert!(!list.exists("a"));
+        assert!(!list.exists("b"));
+        assert_eq!(list.get_number("c"), Some(30));
+        let nested = list.get_nvlist("nested").unwrap();
+        assert_eq!(nested.get_str("secret"), Some("*"));
+        assert_eq!(nested.get_bool("keep"), Some(true));
+    }
+
+    #[test]
+    fn test_cursor_no_unique() {
+        let mut list = NvList::new(NvFlag::NoUnique).unwrap();
+        list.add_null("dup");
+        list.add_number("x", 1);
+        list.add_null("dup");
+        {
+            let mut cursor = list.cursor_mut();
+            assert!(cursor.next() && cursor.next() && cursor.next());
+            // Only the pair under the cursor goes, not the first of its name
+            assert!(cursor.remove());
+            assert!(!cursor.next());
+        }
+        let mut pairs = Vec::new();
+        let mut cursor = list.cursor_mut();
+        while cursor.next() {
+            pairs.push((cursor.name().unwrap().to_owned(), cursor.ty().unwrap()));
+        }
+        assert_eq!(pairs, [("dup".to_owned(), NvType::Null), ("x".to_owned(), NvType::Number)]);
+    }
+
+    #[test]
+    fn test_cursor_take_descriptors() {
+        let (one, mut one_peer) = UnixStream::pair().unwrap();
+        let (two, mut two_peer) = UnixStream::pair().unwrap();
+        let mut list = NvList::new(NvFlag::None).unwrap();
+        list.add_descriptor("fd", one.as_raw_fd());
+        list.add_descriptor_slice("fds", &[two.as_raw_fd()]);
+        drop((one, two));
+        let (fd, fds) = {
+            let mut cursor = list.cursor_mut();
+            assert!(cursor.next());
+            let fd = cursor.take();
+            assert!(cursor.next());
+            (fd, cursor.take())
+        };
+        assert!(list.is_empty());
+        drop(list);
+        // The taken descriptors are the caller's, and outlive the list
+        let fds = match (fd, fds) {
+            (Some(NvValue::Descriptor(fd)), Some(NvValue::DescriptorArray(fds))) => {
+                vec![fd, fds[0]]
+            }
+            res => panic!("unexpected {:?}", res),
+        };
+        for (&fd, peer) in fds.iter().zip(&mut [&mut one_peer, &mut two_peer]) {
+            let mut sock = unsafe { UnixStream::from_raw_fd(fd) };
+            io::Write::write_all(&mut sock, b"!").unwrap();
+            let mut buf = [0];
+            peer.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"!");
+        }
+    }
+
+    #[test]
+    fn test_cursor_take_nested_descriptors() {
+        let (one, mut one_peer) = UnixStream::pair().unwrap();
+        let (two, mut two_peer) = UnixStream::pair().unwrap();
+        let mut nested = NvList::new(NvFlag::None).unwrap();
+        nested.add_descriptor("fd", one.as_raw_fd());
+        let mut inner = NvList::new(NvFlag::None).unwrap();
+        inner.add_descriptor_slice("fds", &[two.as_raw_fd()]);
+        let mut list = NvList::new(NvFlag::None).unwrap();
+        list.add_nvlist("nested", &nested);
+        list.add_nvlist_slice("lists", &[inner]);
+        drop((one, two, nested));
+        let (nested, lists) = {
+            let mut cursor = list.cursor_mut();
+            assert!(cursor.next());
+            let nested = cursor.take();
+            assert!(cursor.next());
+            (nested, cursor.take())
+        };
+        assert!(list.is_empty());
+        drop(list);
+        // Descriptors nested in the taken lists are the caller's too
+        let fds = match (nested, lists) {
+            (Some(NvValue::NvList(nested)), Some(NvValue::NvListArray(lists))) => {
+                match (nested.get("fd"), lists[0].get("fds")) {
+                    (Some(NvValue::Descriptor(fd)), Some(NvValue::DescriptorArray(fds))) => {
+                        vec![*fd, fds[0]]
+                    }
+                    res => panic!("unexpected {:?}", res),
+                }
+            }
+            res => panic!("unexpected {:?}", res),
+        };
+        for (&fd, peer) in fds.iter().zip(&mut [&mut one_peer, &mut two_peer]) {
+            let mut sock = unsafe { UnixStream::from_raw_fd(fd) };
+            io::Write::write_all(&mut sock, b"!").unwrap();
+            let mut buf = [0];
+            peer.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"!");
+        }
+    }
+
+    #[test]
+    fn test_redacted_and_retain() {
+        let mut tls = NvList::new(NvFlag::None).unwrap();
+        tls.add_string("key", "private");
+        let mut list = NvList::new(NvFlag::None).unwrap();
+        list.add_nvlist("tls", &tls);
+        list.add_number("uid", 0);
+        list.add_string("token", "secret");
+        list.add_descriptor("stdin", 0);
+
+        let redacted = list.redacted(&RedactRules::new(&["tls.key", "token"])).unwrap();
+        assert_eq!(redacted.get_nvlist("tls").unwrap().get_str("key"), Some("<redacted>"));
+        assert_eq!(redacted.get_str("token"), Some("<redacted>"));
+        assert_eq!(redacted.get_number("uid"), Some(0));
+        assert!(!redacted.exists("stdin"));
+        assert_eq!(list.get_str("token"), Some("secret"));
+
+        list.retain(|name, value| name != "stdin" && *value != NvValue::Number(0));
+        assert!(list.exists("tls"));
+        assert!(list.exists("token"));
+        assert!(!list.exists("uid"));
+        assert!(!list.exists("stdin"));
+    }
+
+    #[test]
+    fn test_canonical() {
+        let mut a = NvList::new(NvFlag::None).unwrap();
+        a.add_string("name", "tank");
+        a.add_number("version", 5000);
+        let mut b = NvList::new(NvFlag::None).unwrap();
+        b.add_number("version", 5000);
+        b.add_string("name", "tank");
+        assert_eq!(a.canonical_bytes().unwrap(), b.canonical_bytes().unwrap());
+        #[cfg(feature = "digest")]
+        assert_eq!(a.digest::<::sha2::Sha256>().unwrap(), b.digest::<::sha2::Sha256>().unwrap());
+
+        b.add_descriptor("stdin", 0);
+        assert!(b.canonical_bytes().is_err());
+    }
 }
@@ -0,0 +1,121 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+
+extern crate nv;
+
+mod tests {
+    use nv::nvpair::{Encoding, NvPairList, NvPairValue};
+    use nv::{NvErr, NvFlag, NvLimit, NvTree, NvValue, UnpackOptions};
+
+    fn be32(buf: &mut Vec<u8>, value: u32) {
+        buf.extend_from_slice(&value.to_be_bytes());
+    }
+
+    /// `{ "name": "tank", "version": 5000 }` as packed by `nvlist_pack`
+    /// with `NV_ENCODE_XDR`
+    fn tank() -> Vec<u8> {
+        let mut buf = vec![1, 1, 0, 0];
+        be32(&mut buf, 0);
+        be32(&mut buf, 1);
+        for &word in &[32, 32, 4] {
+            be32(&mut buf, word);
+        }
+        buf.extend_from_slice(b"name");
+        for &word in &[9, 1, 4] {
+            be32(&mut buf, word);
+        }
+        buf.extend_from_slice(b"tank");
+        for &word in &[36, 32, 7] {
+            be32(&mut buf, word);
+        }
+        buf.extend_from_slice(b"version\0");
+        for &word in &[8, 1, 0, 5000, 0, 0] {
+            be32(&mut buf, word);
+        }
+        buf
+    }
+
+    fn sample() -> NvPairList {
+        let mut child = NvPairList::new(NvPairList::UNIQUE_NAME);
+        child.push("path", NvPairValue::String("/dev/ada0p3".to_owned()));
+        child.push("guid", NvPairValue::Uint64(0x1234_5678_9abc_def0));
+        let mut list = NvPairList::new(NvPairList::UNIQUE_NAME);
+        list.push("flag", NvPairValue::Boolean);
+        list.push("on", NvPairValue::BooleanValue(true));
+        list.push("byte", NvPairValue::Byte(0xff));
+        list.push("int8", NvPairValue::Int8(-2));
+        list.push("uint8", NvPairValue::Uint8(200));
+        list.push("int16", NvPairValue::Int16(-300));
+        list.push("uint16", NvPairValue::Uint16(60000));
+        list.push("int32", NvPairValue::Int32(-70000));
+        list.push("uint32", NvPairValue::Uint32(4_000_000_000));
+        list.push("int64", NvPairValue::Int64(-1));
+        list.push("hrtime", NvPairValue::Hrtime(1_500_000_000));
+        list.push("double", NvPairValue::Double(0.25));
+        list.push("bytes", NvPairValue::ByteArray(vec![1, 2, 3, 4, 5]));
+        list.push("bools", NvPairValue::BooleanArray(vec![true, false]));
+        list.push("int8s", NvPairValue::Int8Array(vec![-1, 1, 0]));
+        list.push("uint8s", NvPairValue::Uint8Array(vec![7]));
+        list.push("int16s", NvPairValue::Int16Array(vec![-5, 5]));
+        list.push("uint16s", NvPairValue::Uint16Array(vec![65535]));
+        list.push("int32s", NvPairValue::Int32Array(vec![i32::MIN]));
+        list.push("uint32s", NvPairValue::Uint32Array(vec![]));
+        list.push("int64s", NvPairValue::Int64Array(vec![i64::MAX, -3]));
+        list.push("uint64s", NvPairValue::Uint64Array(vec![1, 2, 3]));
+        list.push("strings", NvPairValue::StringArray(vec!["a".to_owned(), "".to_owned()]));
+        list.push("vdev_tree", NvPairValue::NvList(child.clone()));
+        list.push("children", NvPairValue::NvListArray(vec![child.clone(), child]));
+        list.push("after", NvPairValue::String("nested lists".to_owned()));
+        list
+    }
+
+    #[test]
+    fn test_unpack_xdr() {
+        let list = NvPairList::unpack(&tank(), &UnpackOptions::default()).unwrap();
+        assert_eq!(list.flags(), NvPairList::UNIQUE_NAME);
+        assert_eq!(list.get("name"), Some(&NvPairValue::String("tank".to_owned())));
+        assert_eq!(list.get("version"), Some(&NvPairValue::Uint64(5000)));
+        assert_eq!(list.pack(Encoding::Xdr).unwrap()[4..], tank()[4..]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let list = sample();
+        for &encoding in &[Encoding::Xdr, Encoding::Native] {
+            let buf = list.pack(encoding).unwrap();
+            assert_eq!(NvPairList::unpack(&buf, &UnpackOptions::default()).unwrap(), list);
+            for len in 0..buf.len() {
+                assert!(NvPairList::unpack(&buf[..len], &UnpackOptions::default()).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let buf = sample().pack(Encoding::Xdr).unwrap();
+        let options = UnpackOptions { max_depth: 0, ..UnpackOptions::default() };
+        match NvPairList::unpack(&buf, &options) {
+            Err(NvErr::LimitExceeded(NvLimit::Depth)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_tree_conversion() {
+        let list = NvPairList::unpack(&tank(), &UnpackOptions::default()).unwrap();
+        let tree = list.to_tree().unwrap();
+        assert_eq!(tree.flags(), NvFlag::None);
+        assert_eq!(tree.get("version"), Some(&NvValue::Number(5000)));
+        assert_eq!(NvPairList::from_tree(&tree).unwrap(), list);
+
+        match sample().to_tree() {
+            Err(NvErr::Errno(_)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        let mut tree = NvTree::new(NvFlag::None);
+        tree.push("fd", NvValue::Descriptor(0));
+        assert!(NvPairList::from_tree(&tree).is_err());
+    }
+}
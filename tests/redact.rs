@@ -0,0 +1,147 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+
+extern crate nv;
+extern crate sha2;
+
+mod tests {
+    use nv::{NvFlag, NvTree, NvValue, RedactRules};
+    use sha2::{Digest, Sha256};
+    use std::sync::Arc;
+
+    fn string(value: &str) -> NvValue {
+        NvValue::String(value.to_owned())
+    }
+
+    fn sample() -> NvTree {
+        let mut tls = NvTree::new(NvFlag::None);
+        tls.push("cert", string("public"));
+        tls.push("key", string("private"));
+        let mut user = NvTree::new(NvFlag::None);
+        user.push("name", string("root"));
+        user.push("db_password", string("hunter2"));
+        let mut tree = NvTree::new(NvFlag::None);
+        tree.push("tls", NvValue::NvList(tls));
+        tree.push("users", NvValue::NvListArray(vec![user.clone(), user]));
+        tree.push("key", string("not the tls key"));
+        tree.push("blob", NvValue::Binary(b"abc".to_vec()));
+        tree.push("fd", NvValue::Descriptor(0));
+        tree.push("fds", NvValue::DescriptorArray(vec![0, 1]));
+        tree
+    }
+
+    fn users(tree: &NvTree) -> &[NvTree] {
+        match tree.get("users") {
+            Some(NvValue::NvListArray(users)) => users,
+            value => panic!("unexpected {:?}", value),
+        }
+    }
+
+    fn nested<'a>(tree: &'a NvTree, name: &str) -> &'a NvTree {
+        match tree.get(name) {
+            Some(NvValue::NvList(nested)) => nested,
+            value => panic!("unexpected {:?}", value),
+        }
+    }
+
+    #[test]
+    fn test_redacted() {
+        let tree = sample();
+        let rules = RedactRules::new(&["*password", "tls.key", "users[1].name"]);
+        let redacted = tree.redacted(&rules);
+
+        let names: Vec<&str> = redacted.iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["tls", "users", "key", "blob"]);
+        assert_eq!(redacted.get("key"), tree.get("key"));
+        assert_eq!(redacted.get("blob"), Some(&string("<3 bytes>")));
+
+        let tls = nested(&redacted, "tls");
+        assert_eq!(tls.get("cert"), Some(&string("public")));
+        assert_eq!(tls.get("key"), Some(&string("<redacted>")));
+
+        match redacted.get("users") {
+            Some(NvValue::NvListArray(users)) => {
+                assert_eq!(users[0].get("name"), Some(&string("root")));
+                assert_eq!(users[1].get("name"), Some(&string("<redacted>")));
+                for user in users {
+                    assert_eq!(user.get("db_password"), Some(&string("<redacted>")));
+                }
+            }
+            value => panic!("unexpected {:?}", value),
+        }
+    }
+
+    #[test]
+    fn test_redacted_binary_hash() {
+        fn sha256(value: &[u8]) -> String {
+            let hash = Sha256::digest(value);
+            let hex: Vec<String> = hash[..8].iter().map(|byte| format!("{:02x}", byte)).collect();
+            format!("sha256:{}", hex.concat())
+        }
+
+        let rules = RedactRules { binary_hash: Some(Arc::new(sha256)), ..RedactRules::default() };
+        assert_eq!(sample().redacted(&rules).get("blob"),
+                   Some(&string("<3 bytes sha256:ba7816bf8f01cfea>")));
+    }
+
+    #[test]
+    fn test_redacted_globs() {
+        let tree = sample();
+        let rules = RedactRules {
+            marker: "***".to_owned(),
+            ..RedactRules::new(&["t?s"])
+        };
+        assert_eq!(tree.redacted(&rules).get("tls"), Some(&string("***")));
+        let redacted = tree.redacted(&RedactRules::new(&["*.c*"]));
+        assert_eq!(nested(&redacted, "tls").get("cert"), Some(&string("<redacted>")));
+        assert_eq!(nested(&redacted, "tls").get("key"), Some(&string("private")));
+        let rules = RedactRules::new(&["*"]);
+        assert!(tree.redacted(&rules).iter().all(|(_, value)| *value == string("<redacted>")));
+        // `*` does not cross a `.`
+        let rules = RedactRules::new(&["users[0]*"]);
+        assert_eq!(tree.redacted(&rules), tree.redacted(&RedactRules::default()));
+        let rules = RedactRules::new(&["*s*[*]*.*?e"]);
+        assert_eq!(users(&tree.redacted(&rules))[1].get("name"), Some(&string("<redacted>")));
+    }
+
+    #[test]
+    fn test_redacted_dotted_names() {
+        let mut svc = NvTree::new(NvFlag::None);
+        svc.push("api.token", string("abc"));
+        svc.push("api.url", string("https://example.org"));
+        let mut tree = NvTree::new(NvFlag::None);
+        tree.push("db.password", string("hunter2"));
+        tree.push("svc", NvValue::NvList(svc));
+
+        // Names are matched whole, so `*` spans their `.`s
+        let redacted = tree.redacted(&RedactRules::new(&["*password*", "*token*"]));
+        assert_eq!(redacted.get("db.password"), Some(&string("<redacted>")));
+        assert_eq!(nested(&redacted, "svc").get("api.token"), Some(&string("<redacted>")));
+        assert_eq!(nested(&redacted, "svc").get("api.url"), Some(&string("https://example.org")));
+        let redacted = tree.redacted(&RedactRules::new(&["db?pass*"]));
+        assert_eq!(redacted.get("db.password"), Some(&string("<redacted>")));
+    }
+
+    #[test]
+    fn test_redacted_many_stars() {
+        // Backtracking into every split of every `*` would never finish
+        let mut tree = NvTree::new(NvFlag::None);
+        tree.push(&"a".repeat(2000), NvValue::Null);
+        let rules = RedactRules::new(&["*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*b", "a*a*a*a*a*a*a*?"]);
+        let redacted = tree.redacted(&rules);
+        assert_eq!(redacted.iter().next().map(|(_, value)| value), Some(&string("<redacted>")));
+        let rules = RedactRules::new(&["*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*b"]);
+        assert_eq!(tree.redacted(&rules), tree);
+    }
+
+    #[test]
+    fn test_retain() {
+        let mut tree = sample();
+        tree.retain(|name, value| name != "key" && !matches!(value, NvValue::Descriptor(_)));
+        let names: Vec<&str> = tree.iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["tls", "users", "blob", "fds"]);
+        assert_eq!(nested(&tree, "tls").len(), 2);
+    }
+}
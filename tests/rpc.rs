@@ -0,0 +1,209 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+
+extern crate libc;
+extern crate nv;
+
+mod tests {
+    use nv::rpc::{Client, Server};
+    use nv::{NvErr, NvFlag, NvTree, NvValue, UnpackOptions};
+    use std::io::{Read, Write};
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn server() -> Server {
+        let mut server = Server::new();
+        let mut count = 0;
+        server.register("count", move |_: &NvTree| {
+            count += 1;
+            let mut reply = NvTree::new(NvFlag::None);
+            reply.push("count", NvValue::Number(count));
+            Ok(reply)
+        });
+        server.register("fail", |_: &NvTree| Err(libc::EPERM));
+        server.register("slow", |_: &NvTree| {
+            thread::sleep(Duration::from_millis(200));
+            Ok(NvTree::new(NvFlag::None))
+        });
+        server
+    }
+
+    #[test]
+    fn test_rpc_calls() {
+        let (client_sock, server_sock) = UnixStream::pair().unwrap();
+        let handle = thread::spawn(move || server().serve(&server_sock));
+        let mut client = Client::new(client_sock);
+        let args = NvTree::new(NvFlag::None);
+        for expected in 1..4 {
+            let reply = client.call("count", &args).unwrap();
+            assert_eq!(reply.get("count"), Some(&NvValue::Number(expected)));
+            assert_eq!(reply.len(), 1);
+        }
+        match client.call("fail", &args) {
+            Err(NvErr::Errno(libc::EPERM)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        match client.call("missing", &args) {
+            Err(NvErr::Errno(libc::EOPNOTSUPP)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        drop(client);
+        handle.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_rpc_timeout() {
+        let (client_sock, server_sock) = UnixStream::pair().unwrap();
+        let handle = thread::spawn(move || server().serve(&server_sock));
+        let mut client = Client::new(client_sock);
+        client.set_timeout(Some(Duration::from_millis(50)));
+        let args = NvTree::new(NvFlag::None);
+        let start = Instant::now();
+        match client.call("slow", &args) {
+            Err(NvErr::Errno(libc::ETIMEDOUT)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        // The late reply to the slow call is skipped
+        client.set_timeout(None);
+        let reply = client.call("count", &args).unwrap();
+        assert_eq!(reply.get("count"), Some(&NvValue::Number(1)));
+        drop(client);
+        handle.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_rpc_partial_reply_timeout() {
+        let (client_sock, mut server_sock) = UnixStream::pair().unwrap();
+        let handle = thread::spawn(move || {
+            NvTree::recv(&server_sock, &UnpackOptions::default()).unwrap();
+            let mut reply = NvTree::new(NvFlag::None);
+            reply.push("id", NvValue::Number(1));
+            let mut buf = Vec::new();
+            reply.pack_into(&mut buf).unwrap();
+            server_sock.write_all(&buf[..buf.len() / 2]).unwrap();
+            // Hold the socket open without sending the rest
+            let _ = server_sock.read(&mut [0]);
+        });
+        let mut client = Client::new(client_sock);
+        client.set_timeout(Some(Duration::from_millis(50)));
+        let start = Instant::now();
+        match client.call("count", &NvTree::new(NvFlag::None)) {
+            Err(NvErr::Errno(libc::ETIMEDOUT)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+        drop(client);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_rpc_reply_without_id() {
+        let (client_sock, server_sock) = UnixStream::pair().unwrap();
+        let handle = thread::spawn(move || {
+            NvTree::recv(&server_sock, &UnpackOptions::default()).unwrap();
+            NvTree::new(NvFlag::None).send(&server_sock).unwrap();
+        });
+        let mut client = Client::new(client_sock);
+        match client.call("count", &NvTree::new(NvFlag::None)) {
+            Err(NvErr::Errno(libc::EPROTO)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_rpc_late_reply_descriptors() {
+        let (client_sock, server_sock) = UnixStream::pair().unwrap();
+        let (mut peer, passed) = UnixStream::pair().unwrap();
+        let handle = thread::spawn(move || {
+            let mut late = NvTree::new(NvFlag::None);
+            late.push("id", NvValue::Number(1000));
+            late.push("fd", NvValue::Descriptor(passed.as_raw_fd()));
+            NvTree::recv(&server_sock, &UnpackOptions::default()).unwrap();
+            late.send(&server_sock).unwrap();
+            drop(passed);
+            let mut reply = NvTree::new(NvFlag::None);
+            reply.push("id", NvValue::Number(1));
+            reply.send(&server_sock).unwrap();
+        });
+        let mut client = Client::new(client_sock);
+        client.call("count", &NvTree::new(NvFlag::None)).unwrap();
+        handle.join().unwrap();
+        // The copy passed along with the late reply was closed too
+        peer.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(peer.read(&mut [0]).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_rpc_error_reply_descriptors() {
+        let (client_sock, server_sock) = UnixStream::pair().unwrap();
+        let (mut peer, passed) = UnixStream::pair().unwrap();
+        let handle = thread::spawn(move || {
+            for &(id, error) in &[(1, libc::EPERM as u64), (2, 1 << 40)] {
+                NvTree::recv(&server_sock, &UnpackOptions::default()).unwrap();
+                let mut reply = NvTree::new(NvFlag::None);
+                reply.push("id", NvValue::Number(id));
+                reply.push("error", NvValue::Number(error));
+                reply.push("fd", NvValue::Descriptor(passed.as_raw_fd()));
+                reply.send(&server_sock).unwrap();
+            }
+        });
+        let mut client = Client::new(client_sock);
+        match client.call("fail", &NvTree::new(NvFlag::None)) {
+            Err(NvErr::Errno(libc::EPERM)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        // An error that is no errno value is not truncated into one
+        match client.call("fail", &NvTree::new(NvFlag::None)) {
+            Err(NvErr::Errno(libc::EINVAL)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        handle.join().unwrap();
+        // Every copy passed along with the failed replies was closed
+        peer.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(peer.read(&mut [0]).unwrap(), 0);
+    }
+
+    #[cfg(feature = "libnv")]
+    #[test]
+    fn test_rpc_lists() {
+        use nv::NvList;
+
+        let (client_sock, server_sock) = UnixStream::pair().unwrap();
+        let (mut peer, passed) = UnixStream::pair().unwrap();
+        let handle = thread::spawn(move || {
+            let mut server = Server::new();
+            server.register_list("echo", |request: &NvList| {
+                let mut reply = NvList::new(NvFlag::None).map_err(|_| libc::ENOMEM)?;
+                reply.add_number("value", request.get_number("value").ok_or(libc::EINVAL)?);
+                let fd = request.get_descriptor("fd").ok_or(libc::EINVAL)?;
+                reply.add_descriptor("fd", fd.as_raw_fd());
+                Ok(reply)
+            });
+            server.serve(&server_sock)
+        });
+        let mut client = Client::new(client_sock);
+        let mut args = NvList::new(NvFlag::None).unwrap();
+        args.add_number("value", 42);
+        args.add_descriptor("fd", passed.as_raw_fd());
+        let reply = client.call_list("echo", &args).unwrap();
+        assert_eq!(reply.get_number("value").unwrap(), 42);
+        assert!(reply.get_descriptor("fd").unwrap().as_raw_fd() != passed.as_raw_fd());
+        drop(client);
+        handle.join().unwrap().unwrap();
+
+        // Only the reply holds a copy of the descriptor now
+        drop((args, passed));
+        peer.set_nonblocking(true).unwrap();
+        assert!(peer.read(&mut [0]).is_err());
+        drop(reply);
+        peer.set_nonblocking(false).unwrap();
+        peer.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(peer.read(&mut [0]).unwrap(), 0);
+    }
+}
@@ -0,0 +1,58 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+
+extern crate libc;
+extern crate nv;
+
+mod tests {
+    use nv::rpc::Server;
+    use nv::service::Service;
+    use nv::{NvErr, NvFlag, NvTree, NvValue};
+    use std::os::unix::process::ExitStatusExt;
+
+    fn setup(server: &mut Server) {
+        server.register("pid", |_: &NvTree| {
+            let mut reply = NvTree::new(NvFlag::None);
+            reply.push("pid", NvValue::Number(unsafe { libc::getpid() } as u64));
+            Ok(reply)
+        });
+        server.register("die", |_: &NvTree| unsafe { libc::_exit(3) });
+        server.register("panic", |_: &NvTree| panic!("handler failed"));
+    }
+
+    #[test]
+    fn test_service_calls() {
+        let mut service = Service::spawn(setup).unwrap();
+        assert!(service.pid() != unsafe { libc::getpid() });
+        let args = NvTree::new(NvFlag::None);
+        for _ in 0..3 {
+            let reply = service.call("pid", &args).unwrap();
+            assert_eq!(reply.get("pid"), Some(&NvValue::Number(service.pid() as u64)));
+        }
+        assert!(service.try_wait().unwrap().is_none());
+        assert_eq!(service.shutdown().unwrap().code(), Some(0));
+    }
+
+    #[test]
+    fn test_service_child_death() {
+        let args = NvTree::new(NvFlag::None);
+
+        let mut service = Service::spawn(setup).unwrap();
+        match service.call("die", &args) {
+            Err(NvErr::Errno(libc::ECHILD)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        assert_eq!(service.try_wait().unwrap().and_then(|status| status.code()), Some(3));
+
+        let mut service = Service::spawn(setup).unwrap();
+        match service.call("panic", &args) {
+            Err(NvErr::Errno(libc::ECHILD)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        let status = service.shutdown().unwrap();
+        assert_eq!(status.code(), Some(1));
+        assert!(status.signal().is_none());
+    }
+}
@@ -0,0 +1,111 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+
+#![cfg(feature = "tokio")]
+
+extern crate libc;
+extern crate nv;
+extern crate tokio;
+
+mod tests {
+    use nv::{AsyncNvExt, NvFlag, NvTree, NvValue, UnpackOptions};
+    use std::env;
+    use std::fs::{self, File};
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net;
+    use tokio::net::UnixStream;
+    use tokio::runtime::Builder;
+
+    /// An unlinked scratch file, so the test owns every descriptor it sends
+    fn tempfile() -> File {
+        let path = env::temp_dir().join(format!("nv-tokio-{}", ::std::process::id()));
+        let file = File::create(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_tokio_send_recv() {
+        let rt = Builder::new_current_thread().enable_io().build().unwrap();
+        let _guard = rt.enter();
+        let (left, right) = UnixStream::pair().unwrap();
+        let (spare, _) = net::UnixStream::pair().unwrap();
+        let (first, second) = net::UnixStream::pair().unwrap();
+        let file = tempfile();
+
+        let mut child = NvTree::new(NvFlag::None);
+        child.push("fd", NvValue::Descriptor(spare.as_raw_fd()));
+        let mut tree = NvTree::new(NvFlag::None);
+        tree.push("the answer", NvValue::Number(42));
+        tree.push("child", NvValue::NvList(child));
+        tree.push("fds",
+                  NvValue::DescriptorArray(vec![first.as_raw_fd(),
+                                                second.as_raw_fd(),
+                                                file.as_raw_fd()]));
+
+        rt.block_on(left.send_nvlist(&tree)).unwrap();
+        let received = rt.block_on(right.recv_nvlist(&UnpackOptions::default())).unwrap();
+        assert_eq!(received.get("the answer"), Some(&NvValue::Number(42)));
+        let fd = match received.get("child") {
+            Some(NvValue::NvList(child)) => {
+                match child.get("fd") {
+                    Some(&NvValue::Descriptor(fd)) => fd,
+                    res => panic!("unexpected {:?}", res),
+                }
+            }
+            res => panic!("unexpected {:?}", res),
+        };
+        // A new descriptor referring to the same socket
+        assert!(fd != spare.as_raw_fd());
+        let fds = match received.get("fds") {
+            Some(NvValue::DescriptorArray(fds)) => fds.clone(),
+            res => panic!("unexpected {:?}", res),
+        };
+        assert_eq!(fds.len(), 3);
+        assert!(!fds.contains(&first.as_raw_fd()));
+        for fd in Some(fd).into_iter().chain(fds) {
+            unsafe {
+                assert_eq!(libc::close(fd), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tokio_blocking_peer() {
+        let rt = Builder::new_current_thread().enable_io().build().unwrap();
+        let _guard = rt.enter();
+        let (left, right) = net::UnixStream::pair().unwrap();
+        right.set_nonblocking(true).unwrap();
+        let right = UnixStream::from_std(right).unwrap();
+
+        let mut tree = NvTree::new(NvFlag::IgnoreCase);
+        tree.push("strings", NvValue::StringArray(vec!["a".to_owned(), "b".to_owned()]));
+        tree.send(&left).unwrap();
+        let received = rt.block_on(right.recv_nvlist(&UnpackOptions::default())).unwrap();
+        assert_eq!(received, tree);
+
+        rt.block_on(right.send_nvlist(&tree)).unwrap();
+        assert_eq!(NvTree::recv(&left, &UnpackOptions::default()).unwrap(), tree);
+    }
+
+    #[cfg(feature = "libnv")]
+    #[test]
+    fn test_tokio_send_recv_list() {
+        use nv::NvList;
+
+        let rt = Builder::new_current_thread().enable_io().build().unwrap();
+        let _guard = rt.enter();
+        let (left, right) = UnixStream::pair().unwrap();
+        let file = tempfile();
+
+        let mut list = NvList::new(NvFlag::None).unwrap();
+        list.add_number("the answer", 42);
+        list.add_descriptor("file", file.as_raw_fd());
+        rt.block_on(left.send_list(&list)).unwrap();
+        let received = rt.block_on(right.recv_list(&UnpackOptions::default())).unwrap();
+        assert_eq!(received.get_number("the answer").unwrap(), 42);
+        assert!(received.get_descriptor("file").unwrap().as_raw_fd() != file.as_raw_fd());
+    }
+}
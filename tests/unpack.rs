@@ -0,0 +1,212 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+
+extern crate nv;
+
+mod common;
+
+mod tests {
+    use common::Blob;
+    use nv::{NvErr, NvFlag, NvLimit, NvTree, NvValue, UnpackOptions};
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+    use std::os::unix::net::UnixStream;
+
+    fn unpack(buf: &[u8]) -> Result<NvTree, NvErr> {
+        NvTree::unpack(buf, &[], &UnpackOptions::default())
+    }
+
+    #[test]
+    fn test_unpack_empty() {
+        let tree = unpack(&Blob::new().finish()).unwrap();
+        assert!(tree.is_empty());
+        assert_eq!(tree.flags(), NvFlag::None);
+    }
+
+    #[test]
+    fn test_unpack_scalars() {
+        let buf = Blob::new()
+            .pair(1, "null", &[], 0)
+            .pair(2, "bool", &[1], 0)
+            .number("number", 42)
+            .pair(4, "string", b"Hello\0", 0)
+            .pair(7, "binary", &[0xde, 0xad], 0)
+            .pair(9, "numbers", &[1u64.to_ne_bytes(), 2u64.to_ne_bytes()].concat(), 2)
+            .pair(10, "strings", b"a\0bc\0", 2)
+            .finish();
+        let tree = unpack(&buf).unwrap();
+        assert_eq!(tree.get("null"), Some(&NvValue::Null));
+        assert_eq!(tree.get("bool"), Some(&NvValue::Bool(true)));
+        assert_eq!(tree.get("number"), Some(&NvValue::Number(42)));
+        assert_eq!(tree.get("string"), Some(&NvValue::String("Hello".to_owned())));
+        assert_eq!(tree.get("binary"), Some(&NvValue::Binary(vec![0xde, 0xad])));
+        assert_eq!(tree.get("numbers"), Some(&NvValue::NumberArray(vec![1, 2])));
+        assert_eq!(tree.get("strings"),
+                   Some(&NvValue::StringArray(vec!["a".to_owned(), "bc".to_owned()])));
+    }
+
+    #[test]
+    fn test_unpack_nested() {
+        let buf = Blob::new()
+            .nvlist("child")
+            .number("the answer", 42)
+            .up()
+            .nvlist_array("children", 2)
+            .number("first", 1)
+            .array_next()
+            .header(0)
+            .array_next()
+            .number("after", 7)
+            .finish();
+        let tree = unpack(&buf).unwrap();
+        let mut child = NvTree::new(NvFlag::None);
+        child.push("the answer", NvValue::Number(42));
+        assert_eq!(tree.get("child"), Some(&NvValue::NvList(child)));
+        let mut first = NvTree::new(NvFlag::None);
+        first.push("first", NvValue::Number(1));
+        assert_eq!(tree.get("children"),
+                   Some(&NvValue::NvListArray(vec![first, NvTree::new(NvFlag::None)])));
+        assert_eq!(tree.get("after"), Some(&NvValue::Number(7)));
+    }
+
+    #[test]
+    fn test_unpack_descriptors() {
+        let buf = Blob::new()
+            .pair(6, "fd", &1u64.to_ne_bytes(), 0)
+            .finish();
+        let mut buf_with_fds = buf.clone();
+        buf_with_fds[3..11].copy_from_slice(&2u64.to_ne_bytes());
+        let tree = NvTree::unpack(&buf_with_fds, &[10, 11], &UnpackOptions::default()).unwrap();
+        assert_eq!(tree.get("fd"), Some(&NvValue::Descriptor(11)));
+        match NvTree::unpack(&buf, &[10], &UnpackOptions::default()) {
+            Err(NvErr::InvalidFormat(19)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_send_recv_descriptors() {
+        let (left, right) = UnixStream::pair().unwrap();
+        let pairs: Vec<_> = (0..3).map(|_| UnixStream::pair().unwrap()).collect();
+        let mut tree = NvTree::new(NvFlag::None);
+        tree.push("fds",
+                  NvValue::DescriptorArray(pairs.iter().map(|pair| pair.0.as_raw_fd()).collect()));
+        tree.send(&left).unwrap();
+
+        let received = NvTree::recv(&right, &UnpackOptions::default()).unwrap();
+        let fds = match received.get("fds") {
+            Some(NvValue::DescriptorArray(fds)) => fds.clone(),
+            res => panic!("unexpected {:?}", res),
+        };
+        assert_eq!(fds.len(), 3);
+        for (fd, pair) in fds.into_iter().zip(&pairs) {
+            let mut sock = unsafe { UnixStream::from_raw_fd(fd) };
+            sock.write_all(b"x").unwrap();
+            let mut byte = [0];
+            (&pair.1).read_exact(&mut byte).unwrap();
+            assert_eq!(&byte, b"x");
+        }
+    }
+
+    #[test]
+    fn test_unpack_malformed() {
+        let buf = Blob::new().number("number", 42).finish();
+        for len in 0..buf.len() {
+            match unpack(&buf[..len]) {
+                Err(NvErr::Truncated(_)) | Err(NvErr::InvalidFormat(_)) => {}
+                res => panic!("unexpected {:?} for length {}", res, len),
+            }
+        }
+        let mut bad_magic = buf.clone();
+        bad_magic[0] = 0x6d;
+        match unpack(&bad_magic) {
+            Err(NvErr::InvalidFormat(0)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        match unpack(&Blob::new().up().finish()) {
+            Err(NvErr::InvalidFormat(19)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        match unpack(&Blob::new().nvlist("unclosed").finish()) {
+            Err(NvErr::Truncated(_)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        match unpack(&Blob::new().pair(2, "bool", &[2], 0).finish()) {
+            Err(NvErr::InvalidFormat(19)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+        // An unknown version, and flags libnv does not define
+        for &(at, byte) in &[(1, 1), (2, 0x04), (2, 0x40)] {
+            let mut bad_header = buf.clone();
+            bad_header[at] |= byte;
+            match unpack(&bad_header) {
+                Err(NvErr::InvalidFormat(0)) => {}
+                res => panic!("unexpected {:?} for {:#x} at {}", res, byte, at),
+            }
+        }
+        let sized_nvlist = Blob::new().pair(5, "nested", &[0; 8], 0).header(0).up().finish();
+        match unpack(&sized_nvlist) {
+            Err(NvErr::InvalidFormat(19)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+    }
+
+    fn limit(buf: &[u8], options: UnpackOptions) -> NvLimit {
+        match NvTree::unpack(buf, &[], &options) {
+            Err(NvErr::LimitExceeded(limit)) => limit,
+            res => panic!("unexpected {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_unpack_limits() {
+        let nested = Blob::new().nvlist("a").nvlist("b").up().up().finish();
+        assert!(NvTree::unpack(&nested, &[], &UnpackOptions { max_depth: 2, ..Default::default() })
+            .is_ok());
+        assert_eq!(limit(&nested, UnpackOptions { max_depth: 1, ..Default::default() }),
+                   NvLimit::Depth);
+        assert_eq!(limit(&nested, UnpackOptions { max_pairs: 1, ..Default::default() }),
+                   NvLimit::Pairs);
+        assert_eq!(limit(&nested, UnpackOptions { max_bytes: 64, ..Default::default() }),
+                   NvLimit::Bytes);
+
+        let array = Blob::new().pair(8, "bools", &[0, 1, 0], 3).finish();
+        assert_eq!(limit(&array, UnpackOptions { max_array_len: 2, ..Default::default() }),
+                   NvLimit::ArrayLength);
+
+        let string = Blob::new().pair(4, "string", b"Hello\0", 0).finish();
+        assert!(NvTree::unpack(&string,
+                               &[],
+                               &UnpackOptions { max_string_len: 5, ..Default::default() })
+            .is_ok());
+        assert_eq!(limit(&string, UnpackOptions { max_string_len: 4, ..Default::default() }),
+                   NvLimit::StringLength);
+
+        let mut fd = Blob::new().pair(6, "fd", &2u64.to_ne_bytes(), 0).finish();
+        fd[3..11].copy_from_slice(&3u64.to_ne_bytes());
+        let fds = [10, 11, 12];
+        assert!(NvTree::unpack(&fd,
+                               &fds,
+                               &UnpackOptions { max_descriptors: 3, ..Default::default() })
+            .is_ok());
+        match NvTree::unpack(&fd, &fds, &UnpackOptions { max_descriptors: 2, ..Default::default() }) {
+            Err(NvErr::LimitExceeded(NvLimit::Descriptors)) => {}
+            res => panic!("unexpected {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_unpack_fuzz_corpus() {
+        let corpus = concat!(env!("CARGO_MANIFEST_DIR"), "/fuzz/corpus/unpack");
+        for entry in fs::read_dir(corpus).unwrap() {
+            let path = entry.unwrap().path();
+            let buf = fs::read(&path).unwrap();
+            let res = NvTree::unpack(&buf, &[0, 1, 2, 3], &UnpackOptions::default());
+            let name = path.file_name().unwrap().to_str().unwrap();
+            assert_eq!(res.is_ok(), !name.ends_with("-truncated"), "{}: {:?}", name, res);
+        }
+    }
+}
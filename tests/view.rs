@@ -0,0 +1,176 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+
+extern crate nv;
+
+mod common;
+
+mod tests {
+    use common::Blob;
+    use nv::{NvErr, NvFlag, NvListView, NvPath, NvPathElem, NvRead, NvTree, NvType, NvValue,
+             NvValueRef, UnpackOptions, Visitor};
+
+    fn sample() -> Vec<u8> {
+        Blob::new()
+            .pair(2, "bool", &[1], 0)
+            .number("number", 42)
+            .pair(4, "string", b"Hello\0", 0)
+            .pair(7, "binary", &[0xde, 0xad], 0)
+            .pair(8, "bools", &[1, 0, 1], 3)
+            .pair(10, "strings", b"a\0bc\0", 2)
+            .nvlist("child")
+            .number("the answer", 42)
+            .up()
+            .nvlist_array("children", 2)
+            .number("first", 1)
+            .array_next()
+            .header(0)
+            .number("second", 2)
+            .array_next()
+            .number("number", 7)
+            .finish()
+    }
+
+    #[test]
+    fn test_view_getters() {
+        let buf = sample();
+        let view = NvListView::parse(&buf).unwrap();
+        assert!(!view.is_empty());
+        assert_eq!(view.get_bool("bool"), Some(true));
+        assert_eq!(view.get_number("number"), Some(42));
+        assert_eq!(view.get_str("string"), Some("Hello"));
+        assert_eq!(view.get_binary("binary"), Some(&[0xde, 0xad][..]));
+        assert_eq!(view.get_bool_slice("bools"), Some(&[true, false, true][..]));
+        assert_eq!(view.get_number("string"), None);
+        assert!(view.exists_type("strings", NvType::StringArray));
+        assert!(!view.exists("missing"));
+        match view.get("strings") {
+            Some(NvValueRef::StringArray(strings)) => {
+                assert_eq!(strings.iter().collect::<Vec<_>>(), ["a", "bc"]);
+            }
+            res => panic!("unexpected {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_view_nested() {
+        let buf = sample();
+        let view = NvListView::parse(&buf).unwrap();
+        let child = view.get_nvlist("child").unwrap();
+        assert_eq!(child.get_number("the answer"), Some(42));
+        assert_eq!(child.get_number("number"), None);
+        match view.get("children") {
+            Some(NvValueRef::NvListArray(children)) => {
+                assert_eq!(children.len(), 2);
+                let numbers: Vec<_> = children.iter()
+                    .map(|child| child.iter().map(|(name, _)| name).collect::<Vec<_>>())
+                    .collect();
+                assert_eq!(numbers, [["first"], ["second"]]);
+            }
+            res => panic!("unexpected {:?}", res),
+        }
+        let names: Vec<_> = view.iter().map(|(name, _)| name).collect();
+        assert_eq!(names,
+                   ["bool", "number", "string", "binary", "bools", "strings", "child",
+                    "children", "number"]);
+    }
+
+    #[test]
+    fn test_view_matches_tree() {
+        let buf = sample();
+        let view = NvListView::parse(&buf).unwrap();
+        let tree = NvTree::unpack(&buf, &[], &UnpackOptions::default()).unwrap();
+        assert_eq!(view.to_tree(), tree);
+        assert_eq!(NvRead::get_str(&tree, "string"), NvRead::get_str(&view, "string"));
+        assert_eq!(NvRead::get_nvlist(&tree, "child").unwrap(),
+                   view.get_nvlist("child").unwrap().to_tree());
+    }
+
+    #[test]
+    fn test_view_malformed() {
+        let buf = sample();
+        for len in 0..buf.len() {
+            match NvListView::parse(&buf[..len]) {
+                Err(NvErr::Truncated(_)) | Err(NvErr::InvalidFormat(_)) => {}
+                res => panic!("unexpected {:?} for length {}", res, len),
+            }
+        }
+    }
+
+    #[test]
+    fn test_view_walk() {
+        let buf = sample();
+        let view = NvListView::parse(&buf).unwrap();
+        let paths: Vec<String> = view.walk().map(|(path, _)| path.to_string()).collect();
+        assert_eq!(paths,
+                   ["bool", "number", "string", "binary", "bools", "strings", "child",
+                    "child.the answer", "children", "children[0].first", "children[1].second",
+                    "number"]);
+        let (path, value) = view.walk().find(|(path, _)| path.len() == 3).unwrap();
+        assert_eq!(&path[..],
+                   &[NvPathElem::Name("children"), NvPathElem::Index(0), NvPathElem::Name("first")]);
+        assert_eq!(value.to_value(), NvValue::Number(1));
+    }
+
+    #[test]
+    fn test_view_visit() {
+        struct Events(Vec<String>);
+
+        impl<'a> Visitor<'a> for Events {
+            fn value(&mut self, path: &NvPath<'a>, value: NvValueRef<'a>) {
+                if value.ty() == NvType::Number {
+                    self.0.push(format!("{}", path));
+                }
+            }
+
+            fn enter(&mut self, path: &NvPath<'a>, _: NvFlag) {
+                self.0.push(format!("enter {}", path));
+            }
+
+            fn leave(&mut self, path: &NvPath<'a>) {
+                self.0.push(format!("leave {}", path));
+            }
+        }
+
+        let buf = sample();
+        let mut events = Events(Vec::new());
+        NvListView::parse(&buf).unwrap().visit(&mut events);
+        assert_eq!(events.0,
+                   ["number", "enter child", "child.the answer", "leave child",
+                    "enter children[0]", "children[0].first", "leave children[0]",
+                    "enter children[1]", "children[1].second", "leave children[1]", "number"]);
+    }
+
+    #[test]
+    fn test_tree_walk() {
+        let buf = sample();
+        let view = NvListView::parse(&buf).unwrap();
+        let tree = NvTree::unpack(&buf, &[], &UnpackOptions::default()).unwrap();
+        let expected: Vec<(String, NvType)> =
+            view.walk().map(|(path, value)| (path.to_string(), value.ty())).collect();
+        let paths: Vec<(String, NvType)> =
+            tree.walk().map(|(path, value)| (path.to_string(), value.ty())).collect();
+        assert_eq!(paths, expected);
+
+        struct Depth(usize, usize);
+
+        impl<'a> Visitor<'a, &'a NvValue> for Depth {
+            fn value(&mut self, _: &NvPath<'a>, _: &'a NvValue) {}
+
+            fn enter(&mut self, _: &NvPath<'a>, _: NvFlag) {
+                self.0 += 1;
+                self.1 = self.1.max(self.0);
+            }
+
+            fn leave(&mut self, _: &NvPath<'a>) {
+                self.0 -= 1;
+            }
+        }
+
+        let mut depth = Depth(0, 0);
+        tree.visit(&mut depth);
+        assert_eq!((depth.0, depth.1), (0, 1));
+    }
+}
@@ -0,0 +1,114 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+
+extern crate nv;
+
+mod common;
+
+mod tests {
+    use common::Blob;
+    use nv::{NvFlag, NvTree, NvValue, NvWriter, UnpackOptions};
+    use std::io::{ErrorKind, Read};
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+
+    fn sample() -> NvTree {
+        let mut child = NvTree::new(NvFlag::IgnoreCase);
+        child.push("the answer", NvValue::Number(42));
+        let mut tree = NvTree::new(NvFlag::NoUnique);
+        tree.push("null", NvValue::Null);
+        tree.push("bool", NvValue::Bool(true));
+        tree.push("string", NvValue::String("Hello".to_owned()));
+        tree.push("binary", NvValue::Binary(vec![0xde, 0xad]));
+        tree.push("bools", NvValue::BoolArray(vec![true, false]));
+        tree.push("numbers", NvValue::NumberArray(vec![1, 2, 3]));
+        tree.push("strings",
+                  NvValue::StringArray(vec!["a".to_owned(), "bc".to_owned()]));
+        tree.push("child", NvValue::NvList(child.clone()));
+        tree.push("children",
+                  NvValue::NvListArray(vec![child, NvTree::new(NvFlag::None)]));
+        tree.push("after", NvValue::Number(7));
+        tree
+    }
+
+    #[test]
+    fn test_pack_round_trip() {
+        let tree = sample();
+        let mut buf = Vec::new();
+        tree.pack_into(&mut buf).unwrap();
+        assert_eq!(NvTree::unpack(&buf, &[], &UnpackOptions::default()).unwrap(), tree);
+    }
+
+    #[test]
+    fn test_writer_matches_format() {
+        let expected = Blob::new()
+            .number("first", 1)
+            .nvlist("child")
+            .up()
+            .nvlist_array("children", 1)
+            .number("inner", 2)
+            .array_next()
+            .finish();
+        let mut writer = NvWriter::new(Vec::new(), NvFlag::None, expected.len() as u64).unwrap();
+        writer.add_number("first", 1).unwrap();
+        writer.begin_nvlist("child", NvFlag::None).unwrap();
+        writer.end_nvlist().unwrap();
+        writer.begin_nvlist_array("children", 1).unwrap();
+        writer.begin_element(NvFlag::None).unwrap();
+        writer.add_number("inner", 2).unwrap();
+        writer.end_element().unwrap();
+        writer.end_nvlist_array().unwrap();
+        assert_eq!(writer.finish().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_message_descriptor_counts() {
+        let (left, mut right) = UnixStream::pair().unwrap();
+        let mut child = NvTree::new(NvFlag::None);
+        child.push("fd", NvValue::Descriptor(left.as_raw_fd()));
+        let mut tree = NvTree::new(NvFlag::None);
+        tree.push("child", NvValue::NvList(child));
+        tree.push("fd", NvValue::Descriptor(left.as_raw_fd()));
+        tree.send(&left).unwrap();
+
+        // As in libnv, every header counts the descriptors of its list and
+        // of those nested in it
+        let mut expected = Blob::new()
+            .nvlist("child")
+            .pair(6, "fd", &0u64.to_ne_bytes(), 0)
+            .up()
+            .pair(6, "fd", &1u64.to_ne_bytes(), 0)
+            .finish();
+        expected[3..11].copy_from_slice(&2u64.to_ne_bytes());
+        expected[44 + 3..44 + 11].copy_from_slice(&1u64.to_ne_bytes());
+        let mut buf = vec![0; expected.len()];
+        right.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn test_writer_misuse() {
+        let mut writer = NvWriter::new(Vec::new(), NvFlag::None, 19).unwrap();
+        assert_eq!(writer.add_number("too much", 1).unwrap_err().kind(),
+                   ErrorKind::InvalidInput);
+
+        let mut writer = NvWriter::measure(NvFlag::None);
+        assert!(writer.end_nvlist().is_err());
+        assert!(writer.add_string("nul", "a\0b").is_err());
+        assert!(writer.add_bool_slice("empty", &[]).is_err());
+        writer.begin_nvlist_array("children", 2).unwrap();
+        assert!(writer.add_null("outside").is_err());
+        writer.begin_element(NvFlag::None).unwrap();
+        writer.end_element().unwrap();
+        assert!(writer.end_nvlist_array().is_err());
+
+        let mut tree = NvTree::new(NvFlag::None);
+        tree.push("fd", NvValue::Descriptor(0));
+        assert!(tree.pack_into(Vec::new()).is_err());
+
+        let writer = NvWriter::new(Vec::new(), NvFlag::None, 20).unwrap();
+        assert!(writer.finish().is_err());
+    }
+}
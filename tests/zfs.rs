@@ -0,0 +1,66 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+
+extern crate nv;
+
+mod common;
+
+mod tests {
+    use common::scratch;
+    use nv::nvpair::{Encoding, NvPairList, NvPairValue};
+    use nv::zfs::{self, VDEV_LABEL_SIZE};
+    use std::fs::{self, File};
+    use std::io::{Seek, SeekFrom, Write};
+
+    fn config(guid: u64) -> NvPairList {
+        let mut vdev_tree = NvPairList::new(NvPairList::UNIQUE_NAME);
+        vdev_tree.push("type", NvPairValue::String("disk".to_owned()));
+        vdev_tree.push("guid", NvPairValue::Uint64(guid + 1));
+        let mut config = NvPairList::new(NvPairList::UNIQUE_NAME);
+        config.push("name", NvPairValue::String("tank".to_owned()));
+        config.push("pool_guid", NvPairValue::Uint64(guid));
+        config.push("vdev_tree", NvPairValue::NvList(vdev_tree));
+        config
+    }
+
+    /// Write the label at `offset` the way ZFS lays it out
+    fn write_label(file: &mut File, offset: u64, list: &NvPairList) {
+        let buf = list.pack(Encoding::Xdr).unwrap();
+        file.seek(SeekFrom::Start(offset + (16 << 10))).unwrap();
+        file.write_all(&buf).unwrap();
+        file.seek(SeekFrom::Start(offset + (128 << 10) - 40)).unwrap();
+        file.write_all(&0x0210_da7a_b10c_7a11u64.to_le_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_read_vdev_label() {
+        let path = scratch("zfs-label");
+        let mut file = File::create(&path).unwrap();
+        file.set_len(64 * VDEV_LABEL_SIZE + 4096).unwrap();
+        // Only the third label survives, as if the start was overwritten
+        write_label(&mut file, 62 * VDEV_LABEL_SIZE, &config(7));
+        drop(file);
+        let label = zfs::read_vdev_label(&path);
+        assert_eq!(label.unwrap(), config(7));
+    }
+
+    #[test]
+    fn test_read_vdev_label_missing() {
+        let path = scratch("zfs-empty");
+        File::create(&path).unwrap().set_len(8 * VDEV_LABEL_SIZE).unwrap();
+        let label = zfs::read_vdev_label(&path);
+        assert!(label.is_err());
+    }
+
+    #[test]
+    fn test_read_cache() {
+        let mut cache = NvPairList::new(NvPairList::UNIQUE_NAME);
+        cache.push("tank", NvPairValue::NvList(config(7)));
+        let path = scratch("zfs-cache");
+        fs::write(&path, cache.pack(Encoding::Xdr).unwrap()).unwrap();
+        let read = zfs::read_cache(&path);
+        assert_eq!(read.unwrap().get("tank"), Some(&NvPairValue::NvList(config(7))));
+    }
+}